@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SeekDirection {
+    Forward,
+    Backward,
+}
+
+//キーに割り当てられる操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    TogglePause,
+    Stop,
+    VolumeUp,
+    VolumeDown,
+    Seek(SeekDirection),
+    NextTrack,
+    PreviousTrack,
+    ToggleRepeat,
+    ToggleShuffle,
+    CycleVisualizer,
+    CycleColormap,
+    CycleTheme,
+    ToggleHelp,
+    Quit,
+}
+
+impl Action {
+
+    //ヘルプに並べる順
+    pub const ALL: [Action; 15] = [
+        Action::TogglePause,
+        Action::Stop,
+        Action::VolumeUp,
+        Action::VolumeDown,
+        Action::Seek(SeekDirection::Forward),
+        Action::Seek(SeekDirection::Backward),
+        Action::NextTrack,
+        Action::PreviousTrack,
+        Action::ToggleRepeat,
+        Action::ToggleShuffle,
+        Action::CycleVisualizer,
+        Action::CycleColormap,
+        Action::CycleTheme,
+        Action::ToggleHelp,
+        Action::Quit,
+    ];
+
+    //設定ファイルの[keys]での名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::TogglePause => "toggle_pause",
+            Action::Stop => "stop",
+            Action::VolumeUp => "volume_up",
+            Action::VolumeDown => "volume_down",
+            Action::Seek(SeekDirection::Forward) => "seek_forward",
+            Action::Seek(SeekDirection::Backward) => "seek_backward",
+            Action::NextTrack => "next_track",
+            Action::PreviousTrack => "previous_track",
+            Action::ToggleRepeat => "repeat",
+            Action::ToggleShuffle => "shuffle",
+            Action::CycleVisualizer => "visualizer",
+            Action::CycleColormap => "colormap",
+            Action::CycleTheme => "theme",
+            Action::ToggleHelp => "help",
+            Action::Quit => "quit",
+        }
+    }
+
+    //ヘルプに表示する説明
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::TogglePause => "Play / pause",
+            Action::Stop => "Stop",
+            Action::VolumeUp => "Volume up",
+            Action::VolumeDown => "Volume down",
+            Action::Seek(SeekDirection::Forward) => "Seek forward",
+            Action::Seek(SeekDirection::Backward) => "Seek backward",
+            Action::NextTrack => "Next track",
+            Action::PreviousTrack => "Previous track",
+            Action::ToggleRepeat => "Cycle repeat mode",
+            Action::ToggleShuffle => "Toggle shuffle",
+            Action::CycleVisualizer => "Next visualizer",
+            Action::CycleColormap => "Next colormap",
+            Action::CycleTheme => "Next theme",
+            Action::ToggleHelp => "Show / hide this help",
+            Action::Quit => "Quit",
+        }
+    }
+
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Action {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL.iter()
+        .find(|action| action.name() == s)
+        .copied()
+        .ok_or_else(|| {
+            let names: Vec<&str> = Action::ALL.iter().map(Action::name).collect();
+            format!("Unknown action `{}` (expected one of: {})", s, names.join(", "))
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test_action {
+
+    use super::*;
+
+    #[test]
+    fn test_action_names_round_trip() {
+        for action in Action::ALL {
+            assert_eq!(action.name().parse(), Ok(action));
+        }
+        assert!("pause".parse::<Action>().is_err());
+    }
+
+}
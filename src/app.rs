@@ -1,160 +1,491 @@
 
-use crate::audio::analyzer;
 use crate::error::FerriaError;
-use crate::visualizer;
 
 use crate::audio::{
     player::{AudioPlayer, PlaybackStatus},
     loader::AudioTrack,
-    analyzer::{SpectrumData,AudioAnalyzer},
+    analyzer::{SpectrumData,AudioAnalyzer,SampleBlock},
+    playlist::{Playlist, RepeatMode},
+    output::OutputBackend,
+    offline::DumpOptions,
 };
-use crate::visualizer::visualizer::SpectrumVisualizer;
-
-use ratatui::crossterm::execute;
-use ratatui::crossterm::terminal::{Clear, ClearType, EnterAlternateScreen};
-use ratatui::crossterm::{event::{
+use crate::visualizer::registry::{VisualizerMode, VisualizerRegistry};
+use crate::visualizer::visualize_color::Colormap;
+use crate::config::{self, Config};
+use crate::action::{Action, SeekDirection};
+use crate::keys::ChordReader;
+use crate::visualizer::theme::{ColorDepth, ThemeSet};
+use crate::ui::now_playing::{self, MIN_WIDTH_FOR_PANEL, PANEL_WIDTH};
+use crate::ui::cover_art::{CoverArtView, GraphicsProtocol};
+use crate::ui::{help, playlist, tooltip};
+use crate::ui::header::{self, HEADER_HEIGHT};
+use crate::ui::footer::{self, PlaybackSummary, FOOTER_HEIGHT};
+use crate::ui::notification::Notifications;
+use crate::ui::terminal::TerminalSession;
+
+use ratatui::crossterm::event::{
     self, 
     Event, 
+    KeyCode,
     KeyEventKind, 
-    KeyEvent},
-     terminal::{
-        disable_raw_mode,
-        enable_raw_mode,
-    }};
-use ratatui::layout::{Constraint, Layout};
-use ratatui::prelude::{Backend, CrosstermBackend};
-use ratatui::Terminal;
-
-use std::time::Duration;
+    KeyEvent,
+    MouseButton,
+    MouseEvent,
+    MouseEventKind,
+    };
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+
+use std::time::{Duration, Instant};
 use std::thread;
 use std::path::PathBuf;
 use std::sync::mpsc;
 
+//トラックの残りがこの時間(+クロスフェードの長さ)を切ったら次のトラックを先読みする
+const PRELOAD_AHEAD: Duration = Duration::from_secs(10);
+
+//起動時に指定されるオプション
+#[derive(Debug, Clone)]
+pub struct AppOptions {
+    pub tracks: Vec<PathBuf>,
+    pub output: OutputBackend,
+    //指定されていれば再生せずに解析結果を書き出す
+    pub dump: Option<DumpOptions>,
+    //設定ファイル、環境変数、コマンドライン引数を重ねた設定
+    pub config: Config,
+}
+
 pub struct FerriaApp {
     player: AudioPlayer,
+    playlist: Playlist,
+    options: AppOptions,
+    sample_tx: Option<mpsc::Sender<SampleBlock>>,
+    visualizers: VisualizerRegistry,
+    cover_art: CoverArtView,
+    //現在のトラックに対して次のトラックの先読みを試みたか
+    preload_attempted: bool,
+    //複数のキーを続けて押す割り当ての入力途中
+    chords: ChordReader,
+    show_help: bool,
+    //フッターに出す一時的なメッセージ
+    notifications: Notifications,
+    //前回描いた画面の配置と、最後にマウスがあった位置
+    layout: ScreenLayout,
+    mouse: Option<Position>,
+}
+
+//マウスの位置がどこを指しているか調べるための、画面の各部分の領域
+#[derive(Debug, Clone, Copy, Default)]
+struct ScreenLayout {
+    visualizer: Rect,
+    progress: Rect,
+    //プレイリストのパネルと、表示し始めたトラックの位置
+    playlist: Option<(Rect, usize)>,
 }
 
 impl FerriaApp {
 
-    pub fn new() -> Result<Self, FerriaError>{
-        let player = AudioPlayer::new()?;
-        Ok( FerriaApp{ player } )
-    }
+    pub fn new(options: AppOptions) -> Result<Self, FerriaError>{
 
+        if options.tracks.is_empty() {
+            return Err(FerriaError::APPError("No audio tracks to play".to_string()));
+        }
 
-    pub fn run(&mut self) -> Result<(), FerriaError> {
+        let config = &options.config;
 
-        println!("Ferria を起動します...");
+        let player = AudioPlayer::with_output(&options.output)?;
+        player.configure(&config.player_config());
 
-        let mut stdout = std::io::stdout();
-        execute!(stdout, EnterAlternateScreen)?;
-        execute!(stdout, Clear(ClearType::All))?;
+        let mut playlist = Playlist::new(options.tracks.clone());
+        playlist.set_repeat(config.player.repeat);
+        playlist.start_shuffled(config.player.shuffle);
 
-        let _terminal_restore_guard = TerminalRestoreGuard;
+        let mut visualizers = VisualizerRegistry::new(config.visualizer.mode, config.visualizer.colormap, &config.visualizer_config());
 
-        //ratatuiのセットアップ
-        let backend = CrosstermBackend::new(&stdout);
-        let mut terminal = Terminal::new(backend)?;
+        //組み込みのテーマに$XDG_CONFIG_HOME/ferria/themes/*.tomlを加える
+        let mut themes = ThemeSet::load(config::config_dir().map(|dir| dir.join("themes")).as_deref())?;
+        themes.select(&config.visualizer.theme)?;
+        themes.set_depth(ColorDepth::detect());
+        visualizers.set_themes(themes);
 
-        let mut visualizer = SpectrumVisualizer::new();
+        let cover_art = CoverArtView::new(GraphicsProtocol::detect());
 
-        let mut last_spectrum_data: Option<SpectrumData> = None;
+        Ok( FerriaApp{ player, playlist, options, sample_tx: None, visualizers, cover_art, preload_attempted: false, chords: ChordReader::default(), show_help: false, notifications: Notifications::default(), layout: ScreenLayout::default(), mouse: None } )
+    }
 
-        let audio_file_path = PathBuf::from("assets/eine.mp3");
-        println!("オーディオトラックをロード中: {:?}", audio_file_path);
 
-        let audio_track = AudioTrack::new(&audio_file_path)?;
-        println!("オーディオトラックのロードが完了しました。タイトル:{:?}, アーティスト:{:?}", audio_track.metadata.title, audio_track.metadata.artist);
+    pub fn run(&mut self) -> Result<(), FerriaError> {
 
-        let (sample_tx, sample_rx) = mpsc::channel::<f32>();
-        let (spectrum_tx, spectrum_rx) = mpsc::channel::<SpectrumData>();
+        println!("Ferria を起動します...");
+
+        //抜けるとき(エラーやパニックも含む)に端末を元に戻す
+        let mut session = TerminalSession::enter()?;
 
-        self.player.play(audio_track, Some(sample_tx))?;
+        let mut last_spectrum_data: Option<SpectrumData> = None;
 
-        //sample_rateは実際はAudioPlayerのOutputStreamから取得したものを使用
-        let handle_analyzer = AudioAnalyzer::run_in_thread(1024, 44100, sample_rx, spectrum_tx);
+        let (sample_tx, sample_rx) = mpsc::channel::<SampleBlock>();
+        let (spectrum_tx, spectrum_rx) = mpsc::channel::<SpectrumData>();
 
-        println!("再生を開始しました。");
+        self.sample_tx = Some(sample_tx);
 
-        let start_time = std::time::Instant::now();
-        let total_duration = self.player.get_current_metadata().and_then(|m| m.duration);
+        self.play_current()?;
+
+        //サンプルレートとチャンネル数はSampleBlockで再生中のトラックから渡される
+        let _handle_analyzer = AudioAnalyzer::run_in_thread(self.options.config.analyzer_config(), sample_rx, spectrum_tx)?;
 
         loop {
 
-            let status = self.player.get_status();
-            let elapsed = start_time.elapsed();
+            //先読みしたトラックへ途切れなく切り替わった分だけプレイリストも進める
+            let track_changes = self.player.take_track_changes();
+            if track_changes > 0 {
+                for _ in 0..track_changes {
+                    self.playlist.advance_on_end();
+                }
+                self.preload_attempted = false;
+            }
 
-            if let Some(duration) = total_duration {
-                if elapsed > duration + Duration::from_secs(2) {
-                    println!("再生が予想時間を超えました。強制終了します。");
-                    break;
+            self.preload_next();
+
+            //先読みが間に合わずにトラックが最後まで再生されたらプレイリストの次のトラックへ
+            //プレイリストの最後まで再生したら停止状態にして、画面はそのまま残す
+            if self.player.is_track_finished() {
+
+                if self.playlist.advance_on_end().is_some() {
+                    self.play_current()?;
+                } else {
+                    self.player.stop();
                 }
             }
 
+            let status = self.player.get_status();
+
             while let Ok(data) = spectrum_rx.try_recv() {
+                self.visualizers.update(&data);
                 last_spectrum_data = Some(data);
             }
             
-            terminal.draw(|frame| {
-                let area = frame.area();
-                visualizer.draw(frame, area, last_spectrum_data.as_ref());
+            let metadata = self.player.get_current_metadata();
+
+            //トラックが変わってカバー画像が変わったら、ヴィジュアライザーの色も差し替える
+            if self.cover_art.set_cover(metadata.as_ref().and_then(|m| m.cover.as_ref())) {
+                self.visualizers.set_artwork_palette(self.cover_art.palette());
+            }
+
+            let summary = PlaybackSummary {
+                status,
+                position: self.player.position(),
+                duration: metadata.as_ref().and_then(|m| m.duration),
+                volume: self.player.volume(),
+                repeat: self.playlist.repeat(),
+                shuffle: self.playlist.is_shuffle(),
+            };
+            let playlist_position = self.playlist.current_index().map(|index| (index + 1, self.playlist.len()));
+
+            let mut layout = ScreenLayout::default();
+
+            session.terminal().draw(|frame| {
+                let [header_area, area, footer_area] = Layout::vertical([
+                    Constraint::Length(HEADER_HEIGHT),
+                    Constraint::Min(0),
+                    Constraint::Length(FOOTER_HEIGHT),
+                ]).areas(frame.area());
+                [layout.progress, _] = footer::footer_areas(footer_area);
+
+                header::draw_header(frame, header_area, metadata.as_ref(), playlist_position);
+                footer::draw_footer(frame, footer_area, &summary, self.notifications.current(Instant::now()));
+
+                //幅が足りなければヴィジュアライザーだけを表示する
+                let visualizer_area = if area.width >= MIN_WIDTH_FOR_PANEL {
+                    let [visualizer_area, panel_area] = Layout::horizontal([Constraint::Min(0), Constraint::Length(PANEL_WIDTH)]).areas(area);
+
+                    //曲が複数あればパネルの下にプレイリストを出す
+                    let panel_area = if self.playlist.len() > 1 {
+                        let height = (self.playlist.len() as u16 + 2).min(panel_area.height / 2);
+                        let [panel_area, playlist_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(height)]).areas(panel_area);
+                        let offset = playlist::draw_playlist(frame, playlist_area, self.playlist.tracks(), self.playlist.current_index());
+                        layout.playlist = Some((playlist_area, offset));
+                        panel_area
+                    } else {
+                        panel_area
+                    };
+
+                    //カバー画像は枠の内側がほぼ正方形(1セルに縦2ピクセル)になる高さにする
+                    if self.cover_art.has_image() {
+                        let [cover_area, info_area] = Layout::vertical([Constraint::Length(PANEL_WIDTH / 2 + 1), Constraint::Min(0)]).areas(panel_area);
+                        self.cover_art.draw(frame, cover_area);
+                        now_playing::draw_now_playing(frame, info_area, metadata.as_ref());
+                    } else {
+                        now_playing::draw_now_playing(frame, panel_area, metadata.as_ref());
+                    }
+
+                    visualizer_area
+                } else {
+                    area
+                };
+
+                self.visualizers.draw(frame, visualizer_area, last_spectrum_data.as_ref());
+                layout.visualizer = visualizer_area;
+
+                //マウスを重ねた棒の周波数とレベル
+                if let Some(mouse) = self.mouse
+                    && visualizer_area.contains(mouse)
+                    && let Some(data) = last_spectrum_data.as_ref()
+                    && let Some(text) = self.visualizers.inspect(visualizer_area, mouse.x, mouse.y, data) {
+                    tooltip::draw_tooltip(frame, visualizer_area, mouse, &text);
+                }
+
+                if self.show_help {
+                    help::draw_help(frame, frame.area(), &self.options.config.keys);
+                }
 
             })?;
 
-            // println!("現在のステータス: {:?}, 経過時間: {:?}", status, elapsed);
+            self.layout = layout;
 
-            if status == PlaybackStatus::Stopped {
-                println!("再生が終了しました");
+            self.cover_art.write_graphics(session.terminal().backend_mut())?;
+
+            if event::poll(Duration::from_millis(50))?
+                && !self.handle_event(event::read()?, &mut session)? {
                 break;
             }
 
-            if event::poll(Duration::from_millis(50))? {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(session);
+
+        Ok(())
+    } 
+
+    //プレイリストの現在のトラックを読み込んで再生を開始する
+    //読み込めないトラックは飛ばして次のトラックを試す
+    fn play_current(&mut self) -> Result<(), FerriaError> {
+
+        let mut last_error = None;
+
+        for _ in 0..self.playlist.len() {
+
+            let Some(path) = self.playlist.current().map(PathBuf::from) else { break };
 
-                let event = event::read()?;
-                match event {
-                    Event::Key(event) => if !self.handle_key_event(&event) {
+            match AudioTrack::new(&path) {
+                Ok(audio_track) => {
+                    self.notifications.info(format!("Now playing: {}", audio_track.metadata.display_title()));
+                    self.preload_attempted = false;
+                    return self.player.play(audio_track, self.sample_tx.clone());
+                },
+                Err(e) => {
+                    self.notifications.error(format!("Failed to load {}: {}", path.display(), e));
+                    last_error = Some(e);
+
+                    if self.playlist.next_track().is_none() {
                         break;
-                    },
-                    _ => {}, //リサイズなどは今後実装？
+                    }
                 }
             }
+        }
 
-            thread::sleep(Duration::from_millis(10));
+        Err(last_error.unwrap_or_else(|| FerriaError::APPError("No playable tracks in playlist".to_string())))
+
+    }
+
+    //現在のトラックの終わりが近づいたら、プレイリストで次に再生されるトラックを先読みしてキューに追加する
+    fn preload_next(&mut self) {
+
+        if self.preload_attempted || self.player.get_status() == PlaybackStatus::Stopped {
+            return;
         }
 
-        drop(_terminal_restore_guard);
-        println!("Ferria 終了します。");
+        //長さが分からないトラックは終わりが近いか判断できないので、すぐに先読みしてギャップレスで繋ぐ(クロスフェードはしない)
+        if self.player.remaining().is_some_and(|remaining| remaining > PRELOAD_AHEAD + self.player.crossfade()) {
+            return;
+        }
+
+        self.preload_attempted = true;
+
+        let Some(path) = self.playlist.peek_on_end().map(PathBuf::from) else { return };
+
+        //失敗してもトラック終了時にplay_currentで改めて読み込むので、ここではエラーを表示するだけ
+        //フォーマットが違って繋げなかった(Ok(false))トラックも、同じくトラック終了時に新しく再生し直す
+        let result = AudioTrack::new(&path).and_then(|track| self.player.enqueue(track));
+        if let Err(e) = result {
+            self.notifications.error(format!("Failed to preload {}: {}", path.display(), e));
+        }
+
+    }
+
+    //プレイリストの順序が変わったら先読みをやり直す
+    fn invalidate_preload(&mut self) {
+        self.player.clear_queued_tracks();
+        self.preload_attempted = false;
+    }
+
+    fn play_next(&mut self) -> Result<(), FerriaError> {
+
+        if self.playlist.next_track().is_some() {
+            self.play_current()?;
+        }
 
         Ok(())
-    } 
+    }
+
+    //tracks上のインデックスを指定して再生する
+    fn play_track(&mut self, index: usize) -> Result<(), FerriaError> {
+
+        self.invalidate_preload();
+
+        if self.playlist.jump_to(index).is_some() {
+            self.play_current()?;
+        }
+
+        Ok(())
+    }
+
+    fn play_previous(&mut self) -> Result<(), FerriaError> {
+
+        if self.playlist.previous_track().is_some() {
+            self.play_current()?;
+        }
+
+        Ok(())
+    }
+
+    //true->loop continue / false->break;
+    fn handle_event(&mut self, event: Event, session: &mut TerminalSession) -> Result<bool, FerriaError> {
+
+        match event {
+            Event::Key(event) => self.handle_key_event(&event),
+            Event::Mouse(event) => self.handle_mouse_event(&event),
+            //画面を消して描き直すので、sixel/kittyの画像も置き直す
+            Event::Resize(_, _) => {
+                session.resize()?;
+                self.cover_art.invalidate();
+                Ok(true)
+            },
+            _ => Ok(true),
+        }
+
+    }
 
     //true->loop continue / false->break;
-    fn handle_key_event(&self, event: &KeyEvent) -> bool {
+    fn handle_key_event(&mut self, event: &KeyEvent) -> Result<bool, FerriaError> {
 
-        if event.kind != KeyEventKind::Press { return true };
+        if event.kind != KeyEventKind::Press { return Ok(true) };
 
-        match event.code {
-            event::KeyCode::Enter |
-            event::KeyCode::Char(' ') |
-            event::KeyCode::Char('p') => {
-                push_key_turn(&self.player)
+        //ヘルプはEscでも閉じられるようにする
+        if self.show_help && event.code == KeyCode::Esc {
+            self.show_help = false;
+            return Ok(true);
+        }
+
+        match self.chords.feed(&self.options.config.keys, event, Instant::now()) {
+            Some(action) => self.perform(action),
+            None => Ok(true),
+        }
+
+    }
+
+    fn handle_mouse_event(&mut self, event: &MouseEvent) -> Result<bool, FerriaError> {
+
+        let position = Position::new(event.column, event.row);
+        self.mouse = Some(position);
+
+        let layout = self.layout;
+
+        match event.kind {
+            //進捗バーはクリックでもドラッグでもシークする
+            MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left) if layout.progress.contains(position) => {
+                let duration = self.player.get_current_metadata().and_then(|m| m.duration);
+                if let Some(target) = footer::seek_position(layout.progress, event.column, duration)
+                    && let Err(e) = self.player.seek(target) {
+                    self.notifications.error(e.to_string());
+                }
             },
-            event::KeyCode::Char('s') => { 
-                push_key_stop(&self.player)
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some((area, offset)) = layout.playlist
+                    && area.contains(position)
+                    && let Some(index) = playlist::track_at(area, offset, self.playlist.len(), event.row) {
+                    self.play_track(index)?;
+                }
             },
-            event::KeyCode::Char('+') => {
-                push_key_volume_up(&self.player)
-            }
-            event::KeyCode::Char('-') => {
-                push_key_volume_down(&self.player)
-            }
-            event::KeyCode::Char('q') |
-            event::KeyCode::Char('c') if event.modifiers.contains(event::KeyModifiers::CONTROL) => {
-                push_key_kill(&self.player)
+            MouseEventKind::ScrollUp if layout.visualizer.contains(position) => {
+                push_key_volume_up(&self.player);
             },
-            _ => true,
+            MouseEventKind::ScrollDown if layout.visualizer.contains(position) => {
+                push_key_volume_down(&self.player);
+            },
+            _ => {},
         }
 
+        Ok(true)
+
+    }
+
+    fn perform(&mut self, action: Action) -> Result<bool, FerriaError> {
+
+        let continue_loop = match action {
+            //停止中は現在のトラックを最初から再生し直す
+            Action::TogglePause if self.player.get_status() == PlaybackStatus::Stopped => {
+                self.play_current()?;
+                true
+            },
+            Action::TogglePause => push_key_turn(&self.player),
+            Action::Stop => push_key_stop(&self.player),
+            Action::VolumeUp => push_key_volume_up(&self.player),
+            Action::VolumeDown => push_key_volume_down(&self.player),
+            Action::Seek(direction) => {
+                let step = self.options.config.seek_step();
+                let result = match direction {
+                    SeekDirection::Forward => push_key_seek_forward(&self.player, step),
+                    SeekDirection::Backward => push_key_seek_backward(&self.player, step),
+                };
+                if let Err(e) = result {
+                    self.notifications.error(e.to_string());
+                }
+                true
+            },
+            Action::NextTrack => {
+                self.play_next()?;
+                true
+            },
+            Action::PreviousTrack => {
+                self.play_previous()?;
+                true
+            },
+            Action::ToggleRepeat => {
+                self.invalidate_preload();
+                let repeat = push_key_repeat(&mut self.playlist);
+                self.notifications.info(format!("Repeat: {:?}", repeat));
+                true
+            },
+            Action::ToggleShuffle => {
+                self.invalidate_preload();
+                let shuffle = push_key_shuffle(&mut self.playlist);
+                self.notifications.info(if shuffle { "Shuffle on" } else { "Shuffle off" });
+                true
+            },
+            Action::CycleVisualizer => {
+                let mode = push_key_visualizer(&mut self.visualizers);
+                self.notifications.info(format!("Visualizer: {:?}", mode));
+                true
+            },
+            Action::CycleColormap => {
+                let colormap = push_key_colormap(&mut self.visualizers);
+                self.notifications.info(format!("Colormap: {:?}", colormap));
+                true
+            },
+            Action::CycleTheme => {
+                let theme = push_key_theme(&mut self.visualizers);
+                self.notifications.info(format!("Theme: {}", theme));
+                true
+            },
+            Action::ToggleHelp => {
+                self.show_help = !self.show_help;
+                true
+            },
+            Action::Quit => push_key_kill(&self.player),
+        };
+
+        Ok(continue_loop)
+
     }
 
 }
@@ -163,14 +494,8 @@ pub fn push_key_turn(player: &AudioPlayer) -> bool {
 
     let current_status = player.get_status();
     match current_status {
-        PlaybackStatus::Playing => {
-            player.pause();
-            println!("pause.");
-        },
-        PlaybackStatus::Paused => {
-            player.resume();
-            println!("restart.");
-        },
+        PlaybackStatus::Playing => player.pause(),
+        PlaybackStatus::Paused => player.resume(),
         _ => {},
     }
 
@@ -192,17 +517,38 @@ pub fn push_key_volume_down(player: &AudioPlayer) -> bool {
     true
 }
 
+//シークできなかったときはエラーをフッターに出す
+pub fn push_key_seek_forward(player: &AudioPlayer, step: Duration) -> Result<(), FerriaError> {
+    player.seek_forward(step)
+}
+
+pub fn push_key_seek_backward(player: &AudioPlayer, step: Duration) -> Result<(), FerriaError> {
+    player.seek_backward(step)
+}
+
+pub fn push_key_repeat(playlist: &mut Playlist) -> RepeatMode {
+    playlist.set_repeat(playlist.repeat().cycle());
+    playlist.repeat()
+}
+
+pub fn push_key_shuffle(playlist: &mut Playlist) -> bool {
+    playlist.set_shuffle(!playlist.is_shuffle());
+    playlist.is_shuffle()
+}
+
+pub fn push_key_visualizer(visualizers: &mut VisualizerRegistry) -> VisualizerMode {
+    visualizers.cycle()
+}
+
+pub fn push_key_colormap(visualizers: &mut VisualizerRegistry) -> Colormap {
+    visualizers.cycle_colormap()
+}
+
+pub fn push_key_theme(visualizers: &mut VisualizerRegistry) -> String {
+    visualizers.cycle_theme().to_string()
+}
+
 pub fn push_key_kill(player: &AudioPlayer) -> bool {
     player.stop();
     false
 }
-
-struct TerminalRestoreGuard;
-impl Drop for TerminalRestoreGuard {
-    fn drop(&mut self) {
-
-        if let Err(e) = disable_raw_mode() {
-            eprintln!("Failed to disable raw mode: {}", e);
-        }
-    }
-}
\ No newline at end of file
@@ -1,80 +1,279 @@
 
+use clap::ValueEnum;
 use realfft::num_complex::Complex;
 use realfft::RealFftPlanner;
-use std::fmt::format;
-use std::path::Display;
 use std::vec::Vec;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
 use crate::error::FerriaError;
+use crate::audio::bands::{self, Band, BandLayout};
+use crate::audio::scaling::{AmplitudeScale, Weighting};
+use crate::audio::window::WindowFunction;
+
+//AudioAnalyzerを作るときの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalyzerConfig {
+    pub fft_size: usize,
+    //隣り合う解析フレームの間隔(サンプル数)。fft_sizeより小さければ窓が重なる
+    pub hop_size: usize,
+    pub band_layout: BandLayout,
+    pub window: WindowFunction,
+    //binsとbandsの値の表し方
+    pub scale: AmplitudeScale,
+    pub weighting: Weighting,
+    pub level: LevelMode,
+    //LevelMode::Agcで、音量が下がったときに基準の振幅が追従する時定数(秒)。0ならフレームごとの最大値
+    pub agc_release: f32,
+}
+
+//スペクトルの振幅の基準
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LevelMode {
+    ///フルスケールの正弦波が1.0(0dBFS)になる絶対値
+    #[default]
+    Absolute,
+    ///最近の最大振幅が1.0になるように自動で合わせる
+    Agc,
+}
+
+//AGCで持ち上げる上限(-80dBFSより小さい音は無音として扱う)
+const AGC_FLOOR: f32 = 1e-4;
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig {
+            fft_size: 1024,
+            //75%重ねる
+            hop_size: 256,
+            band_layout: BandLayout::default(),
+            window: WindowFunction::default(),
+            scale: AmplitudeScale::default(),
+            weighting: Weighting::default(),
+            level: LevelMode::default(),
+            agc_release: 2.0,
+        }
+    }
+}
+
+impl AnalyzerConfig {
+
+    //窓の重なり(0.0〜1.0未満)からhop_sizeを決める
+    pub fn hop_from_overlap(fft_size: usize, overlap: f32) -> usize {
+        ((fft_size as f32 * (1.0 - overlap)).round() as usize).max(1)
+    }
+
+}
 
 //オーディオサンプルを分析して、周波数スペクトルを生成
 pub struct AudioAnalyzer {
     fft_size: usize,
     sample_rate: u32,
     planner: RealFftPlanner<f32>,
+    band_layout: BandLayout,
+    //サンプルレートごとに決まる帯域の境界周波数
+    band_edges: Vec<(f32, f32, f32)>,
+    window_function: WindowFunction,
+    //fft_size分の窓関数の係数
+    window: Vec<f32>,
+    scale: AmplitudeScale,
+    weighting: Weighting,
+    //bins[i]にかける重み付けの係数(サンプルレートごとに決まる)
+    weights: Vec<f32>,
+    //FFTの振幅をフルスケール基準にする係数(2 / 窓関数の係数の和)
+    full_scale: f32,
+    level: LevelMode,
+    hop_size: usize,
+    agc_release: f32,
+    //AGCが基準にしている振幅
+    agc_peak: f32,
 }
 
 //分析結果として得られるスペクトルデータ
 #[derive(Debug, Clone)]
 pub struct SpectrumData {
     pub bins: Vec<f32>,
+    //binsをBandLayoutに従って周波数帯域ごとにまとめたもの
+    pub bands: Vec<Band>,
+    //重み付けした後の最大振幅(フルスケール基準、AGCの前)
     pub max_amplitude: f32,
+    //LevelMode::Agcのときにbins, bandsにかけた倍率。割り戻せばフルスケール基準の値になる
+    pub agc_gain: Option<f32>,
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    //bins, bandsの値の表し方。描画するときはscale.to_magnitudeで振幅に戻す
+    pub scale: AmplitudeScale,
+    //スペクトルと同じ区間の時間波形。オシロスコープやベクトルスコープの描画に使う
+    pub waveform: WaveformData,
+}
+
+//解析した区間(fft_sizeフレーム分)の時間波形
+//チャンネルごとに分けて、窓関数をかける前の値を持つ
+#[derive(Debug, Clone, Default)]
+pub struct WaveformData {
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: u32,
+}
+
+impl WaveformData {
+
+    //フレーム数(チャンネルごとのサンプル数)
+    pub fn len(&self) -> usize {
+        self.channels.iter().map(Vec::len).min().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //チャンネルを平均した波形
+    pub fn mono(&self) -> Vec<f32> {
+
+        let channels = self.channels.len() as f32;
+
+        (0..self.len())
+        .map(|i| self.channels.iter().map(|channel| channel[i]).sum::<f32>() / channels)
+        .collect()
+
+    }
+
+}
+
+impl SpectrumData {
+
+    //bins[index]の中心周波数(Hz)。bins[0]はDC成分を除いた1番目のビン
+    pub fn bin_frequency(&self, index: usize) -> f32 {
+        (index + 1) as f32 * self.sample_rate as f32 / self.fft_size as f32
+    }
+
+}
+
+//再生中のSourceから解析スレッドに送られるインターリーブされたサンプルの塊
+//デコーダのサンプルレートとチャンネル数を一緒に運ぶ
+#[derive(Debug, Clone)]
+pub struct SampleBlock {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl SampleBlock {
+
+    //チャンネルを平均してモノラルにする
+    pub fn downmix(&self) -> impl Iterator<Item = f32> + '_ {
+
+        let channels = self.channels.max(1) as usize;
+
+        self.samples
+        .chunks_exact(channels)
+        .map(move |frame| frame.iter().sum::<f32>() / channels as f32)
+
+    }
+
+    //インターリーブされたサンプルをチャンネルごとのバッファに振り分ける
+    pub fn deinterleave_into(&self, buffers: &mut [Vec<f32>]) {
+
+        let channels = self.channels.max(1) as usize;
+
+        for frame in self.samples.chunks_exact(channels) {
+            for (buffer, &sample) in buffers.iter_mut().zip(frame) {
+                buffer.push(sample);
+            }
+        }
+
+    }
+
 }
 
 impl AudioAnalyzer {
 
     pub fn new(fft_size: usize, sample_rate: u32) -> Result<Self, FerriaError> {
+        Self::with_config(AnalyzerConfig { fft_size, hop_size: fft_size, ..Default::default() }, sample_rate)
+    }
+
+    pub fn with_config(config: AnalyzerConfig, sample_rate: u32) -> Result<Self, FerriaError> {
+
+        let fft_size = config.fft_size;
 
         if !fft_size.is_power_of_two() || fft_size == 0 {
             return Err(FerriaError::AnalyzerError(format!("FFT size must be a power of two and non-zero, got {}", fft_size)));
         }
 
+        if config.hop_size == 0 {
+            return Err(FerriaError::AnalyzerError("Hop size must be non-zero".to_string()));
+        }
+
         Ok( AudioAnalyzer{
             fft_size,
             sample_rate,
             planner: RealFftPlanner::<f32>::new(),
+            band_layout: config.band_layout,
+            band_edges: config.band_layout.edges(sample_rate),
+            window_function: config.window,
+            window: config.window.coefficients(fft_size),
+            scale: config.scale,
+            weighting: config.weighting,
+            weights: bin_weights(config.weighting, fft_size, sample_rate),
+            full_scale: full_scale_factor(&config.window.coefficients(fft_size)),
+            level: config.level,
+            hop_size: config.hop_size,
+            agc_release: config.agc_release.max(0.0),
+            agc_peak: 0.0,
             } )
 
     }
 
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.band_edges = self.band_layout.edges(sample_rate);
+        self.weights = bin_weights(self.weighting, self.fft_size, sample_rate);
+        self.agc_peak = 0.0;
+    }
+
     ///オーディオサンプルから周波数スペクトルを計算する
     ///inline展開を試してみる
     /// 
-    //オーディオサンプルにHann窓関数を適応
+    //オーディオサンプルに窓関数を適応
+    //fft_sizeと同じ長さなら作っておいた係数を使う
     #[inline]
     fn apply_window_function(&self, samples: &mut [f32]) {
 
-        let n = samples.len() as f32;
-
-        if n <= 1.0 {
-            //1サンプル以下なら窓関数は不要
-            return;
-        }
-
-        for i in 0..samples.len() {
-        
-            let  window_val = 0.5f32 * (1.0f32 - (2.0f32 * std::f32::consts::PI * i as f32 / (n - 1.0f32)).cos());
-
-            samples[i] *= window_val;
-
+        if samples.len() == self.window.len() {
+            for (sample, coefficient) in samples.iter_mut().zip(&self.window) {
+                *sample *= coefficient;
+            }
+        } else {
+            let coefficients = self.window_function.coefficients(samples.len());
+            for (sample, coefficient) in samples.iter_mut().zip(coefficients) {
+                *sample *= coefficient;
+            }
         }
 
     }
 
     #[inline]
-    //FFT結果の複素数から正規化された振幅スペクトルデータを計算する
-    fn calculate_spectrum_data(&self, fft_output: &[Complex<f32>]) -> SpectrumData {
+    //FFT結果の複素数から振幅スペクトルデータを計算する
+    //振幅はフルスケールの正弦波が1.0になるように揃え、AGCのときはさらに最近の最大振幅で割る
+    fn calculate_spectrum_data(&mut self, fft_output: &[Complex<f32>]) -> SpectrumData {
 
         let mut max_amplitude = 0.0f32;
 
         let mut bands: Vec<f32> = fft_output.iter()
         .skip(1)//最初の要素(DC成分)と最後の要素(ナイキスト周波数)は通常は除外
-        .map(|c| {
+        .zip(&self.weights)
+        .map(|(c, weight)| {
 
-            let amp = c.norm();//複素数の絶対値(振幅)
+            //複素数の絶対値(振幅)をフルスケール基準にして、周波数の重み付けをかける
+            let amp = c.norm() * self.full_scale * weight;
 
             if amp > max_amplitude {
                 max_amplitude = amp;
@@ -85,30 +284,53 @@ impl AudioAnalyzer {
         })
         .collect();
 
+        let agc_gain = (self.level == LevelMode::Agc).then(|| 1.0 / self.track_peak(max_amplitude));
 
-        if max_amplitude > 0.0 {
-            //全体の最大振幅で正規化(表示レンジに合わせるため)
+        if let Some(gain) = agc_gain {
             for band in bands.iter_mut() {
-                *band /= max_amplitude;
+                *band *= gain;
             }
         }
-        else {
-            //max_amplitudeが0の場合(無音など),全てのバンドも0にする
-            for band in bands.iter_mut() {
-                *band = 0.0;
+
+        //BandLayoutに従って周波数帯域ごとにまとめる
+        let bin_width = self.sample_rate as f32 / self.fft_size as f32;
+        let mut grouped = bands::group_bins(&self.band_edges, &bands, bin_width);
+
+        //帯域にまとめるのは振幅のまま行い、最後にスケールを変える
+        if self.scale != AmplitudeScale::Linear {
+            for value in bands.iter_mut().chain(grouped.iter_mut().map(|band| &mut band.value)) {
+                *value = self.scale.apply(*value);
             }
         }
 
-        //ここで必要に応じて、対数スケールへの変換や、周波数帯域のグルーピング(ex: オクターブバンド)を行う
-        //現状は線形スケールの振幅をそのまま返す
-
         SpectrumData { 
             bins: bands, 
+            bands: grouped,
             max_amplitude,
+            agc_gain,
+            sample_rate: self.sample_rate,
+            fft_size: self.fft_size,
+            scale: self.scale,
+            waveform: WaveformData::default(),
         }
 
     } 
 
+    //大きくなるときはすぐに追従し、小さくなるときはagc_releaseの時定数でゆっくり下げる
+    fn track_peak(&mut self, max_amplitude: f32) -> f32 {
+
+        if max_amplitude >= self.agc_peak || self.agc_release == 0.0 || self.sample_rate == 0 {
+            self.agc_peak = max_amplitude;
+        } else {
+            let frame_seconds = self.hop_size.min(self.fft_size) as f32 / self.sample_rate as f32;
+            let decay = (-frame_seconds / self.agc_release).exp();
+            self.agc_peak = max_amplitude + (self.agc_peak - max_amplitude) * decay;
+        }
+
+        self.agc_peak.max(AGC_FLOOR)
+
+    }
+
     #[inline]
     pub fn analyze(&mut self, samples: &[f32]) -> Result<SpectrumData, FerriaError> {
 
@@ -128,61 +350,98 @@ impl AudioAnalyzer {
         fft.process(&mut input_buffer, &mut output_buffer)
         .map_err(|e| FerriaError::AnalyzerError(format!("FFT processing failed: {}", e)))?;
 
-        Ok(self.calculate_spectrum_data(&output_buffer))
+        let mut spectrum_data = self.calculate_spectrum_data(&output_buffer);
+        spectrum_data.waveform = WaveformData { channels: vec![samples.to_vec()], sample_rate: self.sample_rate };
+
+        Ok(spectrum_data)
 
     }
 
+    //サンプルレートは受信したSampleBlockに合わせて切り替える
+    //直近fft_sizeフレームをリングバッファに持ち、hop_sizeフレームごとに解析する
     pub fn run_in_thread (
-        fft_size: usize,
-        sample_rate: u32,
-        sample_rx: mpsc::Receiver<f32>,
+        config: AnalyzerConfig,
+        sample_rx: mpsc::Receiver<SampleBlock>,
         spectrum_tx: mpsc::Sender<SpectrumData>,
     ) -> Result<thread::JoinHandle<()>, FerriaError> {
 
-        let mut analyzer = AudioAnalyzer::new(fft_size, sample_rate)?;
+        let fft_size = config.fft_size;
+        let hop_size = config.hop_size;
+
+        let mut analyzer = AudioAnalyzer::with_config(config, 0)?;
 
-        let mut sample_buffer: Vec<f32> = Vec::with_capacity(fft_size);
+        //モノラルにダウンミックスしたサンプルのリングバッファ
+        let mut mono_ring = SampleRing::new(fft_size);
+        //描画用にチャンネルごとのサンプルも同じ長さだけ持つ
+        let mut channel_rings: Vec<SampleRing> = Vec::new();
+        //リングから取り出した解析用のサンプル
+        let mut window = vec![0.0f32; fft_size];
+        //前回解析してから受け取ったフレーム数
+        let mut since_last = 0usize;
 
         let handle = thread::spawn(move || {
 
             loop {
-                //サンプルバッファがいっぱいになるまでサンプルを受信
-                while sample_buffer.len() < fft_size {
 
-                    match sample_rx.recv_timeout(Duration::from_millis(100)) {
+                let block = match sample_rx.recv_timeout(Duration::from_millis(100)) {
 
-                        Ok(sample) => sample_buffer.push(sample),
+                    Ok(block) => block,
 
-                        //タイムアウトしてもデータが来ていないだけなので続行させる
-                        Err(mpsc::RecvTimeoutError::Timeout) => {
-                        },
+                    //タイムアウトしてもデータが来ていないだけなので続行させる
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
 
-                        Err(mpsc::RecvTimeoutError::Disconnected) => {
-                            eprintln!("Spectrum data receiver disconnected. Analyzer thread exiting.");
-                            return;
-                        }
-                     }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        eprintln!("Spectrum data receiver disconnected. Analyzer thread exiting.");
+                        return;
+                    }
+                };
+
+                let channels = block.channels.max(1) as usize;
+
+                //サンプルレートかチャンネル数が変わったら前のトラックのサンプルは捨てる
+                if block.sample_rate != analyzer.sample_rate() || channels != channel_rings.len() {
+                    analyzer.set_sample_rate(block.sample_rate);
+                    mono_ring.clear();
+                    channel_rings = (0..channels).map(|_| SampleRing::new(fft_size)).collect();
+                    since_last = 0;
                 }
-            
 
-                //バッファ溜まったらFFT続行
-                match analyzer.analyze(&sample_buffer[..fft_size]) {
+                for frame in block.samples.chunks_exact(channels) {
 
-                    Ok(spectrum_data) => {
+                    mono_ring.push(frame.iter().sum::<f32>() / channels as f32);
+                    for (ring, &sample) in channel_rings.iter_mut().zip(frame) {
+                        ring.push(sample);
+                    }
 
-                        if let Err(_) = spectrum_tx.send(spectrum_data) {
-                            eprintln!("Spectrum data receiver disconnected. Analyzer thread exiting.");
-                            break;
-                        }
-                    },
+                    since_last += 1;
 
-                    Err(e) => {
-                        //エラーが発生してもスレッドは続行?微妙かも
-                        eprintln!("Error during FFT analysis: {}", e);
+                    if !mono_ring.is_full() || since_last < hop_size {
+                        continue;
                     }
-                }
 
-                sample_buffer.clear();
+                    since_last = 0;
+                    mono_ring.copy_to(&mut window);
+
+                    match analyzer.analyze(&window) {
+
+                        Ok(mut spectrum_data) => {
+
+                            spectrum_data.waveform.channels = channel_rings.iter()
+                            .map(SampleRing::to_vec)
+                            .collect();
+
+                            if spectrum_tx.send(spectrum_data).is_err() {
+                                eprintln!("Spectrum data receiver disconnected. Analyzer thread exiting.");
+                                return;
+                            }
+                        },
+
+                        Err(e) => {
+                            //エラーが発生してもスレッドは続行?微妙かも
+                            eprintln!("Error during FFT analysis: {}", e);
+                        }
+                    }
+                }
 
             }
 
@@ -194,21 +453,106 @@ impl AudioAnalyzer {
 
 }
 
+//振幅Aの正弦波のFFTのピークは A * (窓関数の係数の和) / 2 になるので、その逆数
+fn full_scale_factor(window: &[f32]) -> f32 {
+
+    let sum: f32 = window.iter().sum();
+
+    if sum > 0.0 { 2.0 / sum } else { 1.0 }
+
+}
+
+//bins[i](DC成分を除いたi + 1番目のビン)ごとの重み付けの係数
+fn bin_weights(weighting: Weighting, fft_size: usize, sample_rate: u32) -> Vec<f32> {
+
+    let bin_width = sample_rate as f32 / fft_size as f32;
+
+    (1..=fft_size / 2)
+    .map(|i| weighting.gain(i as f32 * bin_width))
+    .collect()
+
+}
+
+//直近capacity個のサンプルを持つ固定長のリングバッファ
+#[derive(Debug, Clone)]
+pub struct SampleRing {
+    data: Vec<f32>,
+    //次に書き込む位置
+    write: usize,
+    filled: usize,
+}
+
+impl SampleRing {
+
+    pub fn new(capacity: usize) -> Self {
+        SampleRing { data: vec![0.0; capacity], write: 0, filled: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.filled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filled == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.filled == self.data.len()
+    }
+
+    //いっぱいなら一番古いサンプルを上書きする
+    pub fn push(&mut self, sample: f32) {
+
+        if self.data.is_empty() {
+            return;
+        }
+
+        self.data[self.write] = sample;
+        self.write = (self.write + 1) % self.data.len();
+        self.filled = (self.filled + 1).min(self.data.len());
+
+    }
+
+    //古い順にoutへコピーする。outの長さはlen()と同じであること
+    pub fn copy_to(&self, out: &mut [f32]) {
+
+        let start = (self.write + self.data.len() - self.filled) % self.data.len().max(1);
+        let head = (self.data.len() - start).min(self.filled);
+
+        out[..head].copy_from_slice(&self.data[start..start + head]);
+        out[head..self.filled].copy_from_slice(&self.data[..self.filled - head]);
+
+    }
+
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut out = vec![0.0; self.filled];
+        self.copy_to(&mut out);
+        out
+    }
+
+    pub fn clear(&mut self) {
+        self.write = 0;
+        self.filled = 0;
+    }
+
+}
+
 #[cfg(test)]
 mod test_analyzer {
 
     use super::*;
+    use crate::audio::bands::BandScale;
     use std::f32::consts::PI;
 
     fn generate_sine_wave(freq_hz: f32, sample_rate: u32, num_samples: usize) -> Vec<f32> {
 
-        let mut samples = vec![0.0; num_samples];
-
-        for i in 0..num_samples {
-            samples[i] = (2.0 * PI * freq_hz * i as f32 / sample_rate as f32).sin(); 
-        }
-
-        samples
+        (0..num_samples)
+        .map(|i| (2.0 * PI * freq_hz * i as f32 / sample_rate as f32).sin())
+        .collect()
 
     }
 
@@ -252,6 +596,63 @@ mod test_analyzer {
         dbg!(samples);
     }
 
+    #[test]
+    fn test_sample_block_downmix() {
+        let block = SampleBlock { samples: vec![1.0, 0.0, 0.5, 0.5, -1.0, 1.0], sample_rate: 48000, channels: 2 };
+        assert_eq!(block.downmix().collect::<Vec<_>>(), vec![0.5, 0.5, 0.0]);
+
+        let mono = SampleBlock { samples: vec![0.1, 0.2], sample_rate: 48000, channels: 1 };
+        assert_eq!(mono.downmix().collect::<Vec<_>>(), vec![0.1, 0.2]);
+
+        let mut buffers = vec![Vec::new(); 2];
+        block.deinterleave_into(&mut buffers);
+        assert_eq!(buffers, vec![vec![1.0, 0.5, -1.0], vec![0.0, 0.5, 1.0]]);
+
+        //チャンネルごとの波形を平均するとダウンミックスと同じになる
+        let waveform = WaveformData { channels: buffers, sample_rate: 48000 };
+        assert_eq!(waveform.len(), 3);
+        assert_eq!(waveform.mono(), block.downmix().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_bin_frequency_uses_sample_rate() {
+        let fft_size = 1024;
+        let sample_rate = 48000;
+        let test_freq_hz = 3000.0;
+
+        let mut analyzer = AudioAnalyzer::new(fft_size, sample_rate).unwrap();
+        let spectrum = analyzer.analyze(&generate_sine_wave(test_freq_hz, sample_rate, fft_size)).unwrap();
+
+        let peak_index = spectrum.bins.iter().enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap();
+
+        let resolution = sample_rate as f32 / fft_size as f32;
+        assert!((spectrum.bin_frequency(peak_index) - test_freq_hz).abs() <= resolution);
+    }
+
+    #[test]
+    fn test_spectrum_bands_follow_layout() {
+        let fft_size = 4096;
+        let sample_rate = 48000;
+
+        let config = AnalyzerConfig {
+            fft_size,
+            hop_size: fft_size,
+            band_layout: BandLayout { scale: BandScale::ThirdOctave, count: 0 },
+            ..Default::default()
+        };
+        let mut analyzer = AudioAnalyzer::with_config(config, sample_rate).unwrap();
+        let spectrum = analyzer.analyze(&generate_sine_wave(1000.0, sample_rate, fft_size)).unwrap();
+
+        assert_eq!(spectrum.bands.len(), 31);
+
+        let peak = spectrum.bands.iter().max_by(|a, b| a.value.total_cmp(&b.value)).unwrap();
+        assert_eq!(peak.center_hz, 1000.0);
+        assert!(peak.low_hz < 1000.0 && peak.high_hz > 1000.0);
+    }
+
     #[test]
     fn test_calculate_spectrum_data_basic() {
 
@@ -263,7 +664,9 @@ mod test_analyzer {
             Complex::new(0.05, 0.0),
         ];
 
-        let analyzer = AudioAnalyzer::new(1024, 44100).unwrap();
+        //フレームごとの最大値で正規化するAGC
+        let config = AnalyzerConfig { fft_size: 1024, level: LevelMode::Agc, agc_release: 0.0, ..Default::default() };
+        let mut analyzer = AudioAnalyzer::with_config(config, 44100).unwrap();
         let spectrum = analyzer.calculate_spectrum_data(&fft_output);
 
         assert_eq!(spectrum.bins.len(), fft_output.len() - 1);//DC成分を除外
@@ -271,9 +674,137 @@ mod test_analyzer {
         let max_band_val = spectrum.bins.iter().cloned().fold(0.0f32, f32::max);
         assert!((max_band_val - 1.0).abs() < 0.001);//最大値がほぼ1.0であることを確認
 
-        let expected_max_raw_amplitude = Complex::new(0.5, 0.5).norm();
-        assert!((spectrum.max_amplitude - expected_max_raw_amplitude).abs() < 0.001);
+        let expected_max_amplitude = Complex::new(0.5, 0.5).norm() * analyzer.full_scale;
+        assert!((spectrum.max_amplitude - expected_max_amplitude).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_weighting_and_decibel_scale() {
+        let fft_size = 4096;
+        let sample_rate = 48000;
+
+        let config = AnalyzerConfig {
+            fft_size,
+            hop_size: fft_size,
+            band_layout: BandLayout { scale: BandScale::ThirdOctave, count: 0 },
+            window: WindowFunction::FlatTop,
+            scale: AmplitudeScale::Decibel { reference: 1.0 },
+            weighting: Weighting::A,
+            ..Default::default()
+        };
+        let mut analyzer = AudioAnalyzer::with_config(config, sample_rate).unwrap();
+
+        //同じ振幅の100Hzと1kHz
+        let samples: Vec<f32> = generate_sine_wave(100.0, sample_rate, fft_size).iter()
+        .zip(generate_sine_wave(1000.0, sample_rate, fft_size))
+        .map(|(a, b)| (a + b) / 2.0)
+        .collect();
+        let spectrum = analyzer.analyze(&samples).unwrap();
+
+        assert_eq!(spectrum.scale, AmplitudeScale::Decibel { reference: 1.0 });
+
+        let peak_near = |hz: f32| (0..spectrum.bins.len())
+        .filter(|&i| (spectrum.bin_frequency(i) - hz).abs() < 30.0)
+        .map(|i| spectrum.bins[i])
+        .fold(f32::MIN, f32::max);
+
+        //振幅0.5の1kHzは-6dBFSで、100HzはA特性でさらに約19dB下がる
+        assert!((peak_near(1000.0) + 6.02).abs() < 0.1);
+        assert!((peak_near(100.0) + 6.02 + 19.1).abs() < 1.0, "100Hz is {}dB", peak_near(100.0));
+
+        //描画用に振幅へ戻せる
+        let band_1k = spectrum.bands.iter().find(|band| band.center_hz == 1000.0).unwrap();
+        assert!(spectrum.scale.to_magnitude(band_1k.value) <= 1.0);
+    }
+
+    #[test]
+    fn test_absolute_level_is_dbfs() {
+        let fft_size = 4096;
+        let sample_rate = 48000;
+
+        for window in [WindowFunction::Hann, WindowFunction::FlatTop, WindowFunction::Rectangular] {
+            let config = AnalyzerConfig { fft_size, window, scale: AmplitudeScale::Decibel { reference: 1.0 }, ..Default::default() };
+            let mut analyzer = AudioAnalyzer::with_config(config, sample_rate).unwrap();
+
+            //ビンの中心に乗る周波数(bins[84] = 85 * 48000 / 4096 Hz)
+            let frequency = 85.0 * sample_rate as f32 / fft_size as f32;
+
+            //フルスケールの正弦波は0dBFS、振幅0.1なら-20dBFS
+            let full = analyzer.analyze(&generate_sine_wave(frequency, sample_rate, fft_size)).unwrap();
+            assert!(full.bins[84].abs() < 0.1, "{}: {}dBFS", window, full.bins[84]);
+
+            let quiet: Vec<f32> = generate_sine_wave(frequency, sample_rate, fft_size).iter().map(|s| s * 0.1).collect();
+            let quiet = analyzer.analyze(&quiet).unwrap();
+            assert!((quiet.bins[84] + 20.0).abs() < 0.1, "{}: {}dBFS", window, quiet.bins[84]);
+        }
     }
 
+    #[test]
+    fn test_agc_releases_slowly() {
+        let config = AnalyzerConfig { fft_size: 1024, hop_size: 1024, level: LevelMode::Agc, agc_release: 1.0, ..Default::default() };
+        let mut analyzer = AudioAnalyzer::with_config(config, 44100).unwrap();
+
+        let loud = generate_sine_wave(1000.0, 44100, 1024);
+        let quiet: Vec<f32> = loud.iter().map(|s| s * 0.1).collect();
+        let peak = |data: &SpectrumData| data.bins.iter().cloned().fold(0.0f32, f32::max);
+
+        assert!((peak(&analyzer.analyze(&loud).unwrap()) - 1.0).abs() < 1e-4);
+
+        //急に小さくなってもすぐには持ち上げない
+        let first = peak(&analyzer.analyze(&quiet).unwrap());
+        assert!(first < 0.2);
+
+        let mut last = first;
+        for _ in 0..400 {
+            last = peak(&analyzer.analyze(&quiet).unwrap());
+        }
+        assert!(last > first);
+        assert!((last - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_sample_ring_keeps_latest_samples_in_order() {
+        let mut ring = SampleRing::new(4);
 
-}
\ No newline at end of file
+        ring.push(1.0);
+        ring.push(2.0);
+        assert!(!ring.is_full());
+        assert_eq!(ring.to_vec(), vec![1.0, 2.0]);
+
+        for sample in 3..=6 {
+            ring.push(sample as f32);
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.to_vec(), vec![3.0, 4.0, 5.0, 6.0]);
+
+        ring.clear();
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_run_in_thread_overlaps_windows() {
+        let config = AnalyzerConfig { fft_size: 256, hop_size: 64, ..Default::default() };
+        let (sample_tx, sample_rx) = mpsc::channel();
+        let (spectrum_tx, spectrum_rx) = mpsc::channel();
+
+        let handle = AudioAnalyzer::run_in_thread(config, sample_rx, spectrum_tx).unwrap();
+
+        //ブロックの区切りとhopがずれていても間隔はhop_sizeごと
+        let frames = 1000;
+        let samples: Vec<f32> = generate_sine_wave(440.0, 8000, frames).into_iter().flat_map(|s| [s, -s]).collect();
+        for chunk in samples.chunks(2 * 100) {
+            sample_tx.send(SampleBlock { samples: chunk.to_vec(), sample_rate: 8000, channels: 2 }).unwrap();
+        }
+        drop(sample_tx);
+        handle.join().unwrap();
+
+        let spectra: Vec<SpectrumData> = spectrum_rx.try_iter().collect();
+        assert_eq!(spectra.len(), (frames - 256) / 64 + 1);
+
+        //2つ目の窓は最初の窓からhop_sizeだけ進んだ区間
+        let expected = generate_sine_wave(440.0, 8000, frames);
+        assert_eq!(spectra[1].waveform.channels[0], expected[64..320].to_vec());
+        assert_eq!(spectra[1].waveform.channels[1][0], -expected[64]);
+    }
+
+}
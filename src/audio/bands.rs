@@ -0,0 +1,222 @@
+use clap::ValueEnum;
+
+//帯域の下限(Hz)。これより下は可聴域外なのでまとめない
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20000.0;
+
+//ISO 266の1/3オクターブ中心周波数
+const THIRD_OCTAVE_CENTERS: [f32; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0,
+    200.0, 250.0, 315.0, 400.0, 500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0,
+    2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0, 8000.0, 10000.0, 12500.0, 16000.0,
+    20000.0,
+];
+
+//Zwickerの臨界帯域(Bark)の境界周波数
+const BARK_EDGES: [f32; 25] = [
+    20.0, 100.0, 200.0, 300.0, 400.0, 510.0, 630.0, 770.0, 920.0, 1080.0,
+    1270.0, 1480.0, 1720.0, 2000.0, 2320.0, 2700.0, 3150.0, 3700.0, 4400.0, 5300.0,
+    6400.0, 7700.0, 9500.0, 12000.0, 15500.0,
+];
+
+//スペクトルを帯域にまとめるときの周波数軸のスケール
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BandScale {
+    ///等間隔
+    Linear,
+    ///対数等間隔
+    Log,
+    ///1/3オクターブ(ISO中心周波数、帯域数は固定)
+    ThirdOctave,
+    ///メル尺度
+    Mel,
+    ///Bark尺度(臨界帯域、帯域数は固定)
+    Bark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandLayout {
+    pub scale: BandScale,
+    //Linear, Log, Melのときの帯域数
+    pub count: usize,
+}
+
+impl Default for BandLayout {
+    fn default() -> Self {
+        BandLayout { scale: BandScale::Log, count: 64 }
+    }
+}
+
+//周波数帯域1つ分の値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub low_hz: f32,
+    pub center_hz: f32,
+    pub high_hz: f32,
+    pub value: f32,
+}
+
+impl BandLayout {
+
+    //サンプルレートに対する各帯域の(下端, 中心, 上端)の周波数を返す
+    //ナイキスト周波数を超える帯域は含めない
+    pub fn edges(&self, sample_rate: u32) -> Vec<(f32, f32, f32)> {
+
+        let nyquist = sample_rate as f32 / 2.0;
+        let top = nyquist.min(MAX_FREQUENCY);
+        let count = self.count.max(1);
+
+        if top <= MIN_FREQUENCY {
+            return Vec::new();
+        }
+
+        match self.scale {
+
+            BandScale::Linear => {
+                let width = (top - MIN_FREQUENCY) / count as f32;
+                (0..count)
+                .map(|i| {
+                    let low = MIN_FREQUENCY + width * i as f32;
+                    (low, low + width / 2.0, low + width)
+                })
+                .collect()
+            },
+
+            BandScale::Log => {
+                let ratio = (top / MIN_FREQUENCY).powf(1.0 / count as f32);
+                (0..count)
+                .map(|i| {
+                    let low = MIN_FREQUENCY * ratio.powi(i as i32);
+                    (low, low * ratio.sqrt(), low * ratio)
+                })
+                .collect()
+            },
+
+            BandScale::ThirdOctave => {
+                let half_band = 2f32.powf(1.0 / 6.0);
+                THIRD_OCTAVE_CENTERS.iter()
+                .map(|&center| (center / half_band, center, center * half_band))
+                .filter(|&(_, _, high)| high <= nyquist)
+                .collect()
+            },
+
+            BandScale::Mel => {
+                let low_mel = hz_to_mel(MIN_FREQUENCY);
+                let width = (hz_to_mel(top) - low_mel) / count as f32;
+                (0..count)
+                .map(|i| {
+                    let low = low_mel + width * i as f32;
+                    (mel_to_hz(low), mel_to_hz(low + width / 2.0), mel_to_hz(low + width))
+                })
+                .collect()
+            },
+
+            BandScale::Bark => {
+                BARK_EDGES.windows(2)
+                .map(|edge| (edge[0], (edge[0] * edge[1]).sqrt(), edge[1]))
+                .filter(|&(_, _, high)| high <= nyquist)
+                .collect()
+            },
+        }
+
+    }
+
+}
+
+//線形ビンの振幅を帯域ごとにまとめる
+//bins[i]の中心周波数は(i + 1) * bin_width(DC成分は除外済み)
+//帯域に含まれるビンが無い(低域で帯域がビン間隔より狭い)場合は中心周波数で線形補間する
+pub fn group_bins(edges: &[(f32, f32, f32)], bins: &[f32], bin_width: f32) -> Vec<Band> {
+
+    edges.iter()
+    .map(|&(low_hz, center_hz, high_hz)| {
+
+        let start = ((low_hz / bin_width).ceil() as usize).saturating_sub(1);
+        let end = (((high_hz / bin_width).ceil() as usize).saturating_sub(1)).min(bins.len());
+
+        let value = if start < end {
+            bins[start..end].iter().sum::<f32>() / (end - start) as f32
+        }
+        else {
+            interpolate(bins, center_hz / bin_width - 1.0)
+        };
+
+        Band { low_hz, center_hz, high_hz, value }
+    })
+    .collect()
+
+}
+
+fn interpolate(bins: &[f32], position: f32) -> f32 {
+
+    if bins.is_empty() {
+        return 0.0;
+    }
+
+    let position = position.clamp(0.0, (bins.len() - 1) as f32);
+    let index = position.floor() as usize;
+    let next = (index + 1).min(bins.len() - 1);
+    let fraction = position - index as f32;
+
+    bins[index] * (1.0 - fraction) + bins[next] * fraction
+
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+#[cfg(test)]
+mod test_bands {
+
+    use super::*;
+
+    #[test]
+    fn test_edges_are_contiguous_and_increasing() {
+        for scale in [BandScale::Linear, BandScale::Log, BandScale::Mel] {
+            let edges = BandLayout { scale, count: 32 }.edges(44100);
+            assert_eq!(edges.len(), 32);
+            for pair in edges.windows(2) {
+                assert!((pair[0].2 - pair[1].0).abs() < 0.01);
+                assert!(pair[0].1 < pair[1].1);
+            }
+            assert!((edges[0].0 - MIN_FREQUENCY).abs() < 0.01);
+            assert!((edges[31].2 - MAX_FREQUENCY).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_fixed_layouts_respect_nyquist() {
+        let third = BandLayout { scale: BandScale::ThirdOctave, count: 0 };
+        assert_eq!(third.edges(48000).len(), 31);
+        assert_eq!(third.edges(44100).len(), 30);
+        assert!(third.edges(16000).iter().all(|&(_, _, high)| high <= 8000.0));
+        assert_eq!(third.edges(44100)[17].1, 1000.0);
+
+        let bark = BandLayout { scale: BandScale::Bark, count: 0 };
+        assert_eq!(bark.edges(44100).len(), 24);
+        assert_eq!(bark.edges(22050).len(), 22);
+    }
+
+    #[test]
+    fn test_group_bins() {
+        //ビン間隔100Hz、bins[i]は(i + 1) * 100Hz
+        let bins: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let edges = vec![(100.0, 250.0, 400.0), (400.0, 700.0, 1000.0)];
+        let bands = group_bins(&edges, &bins, 100.0);
+
+        //100, 200, 300Hzのビン
+        assert_eq!(bands[0].value, (0.0 + 1.0 + 2.0) / 3.0);
+        assert_eq!(bands[1].value, (3.0 + 4.0 + 5.0 + 6.0 + 7.0 + 8.0) / 6.0);
+        assert_eq!(bands[1].low_hz, 400.0);
+
+        //ビンを含まない狭い帯域は補間する
+        let narrow = group_bins(&[(140.0, 150.0, 160.0)], &bins, 100.0);
+        assert!((narrow[0].value - 0.5).abs() < 0.001);
+    }
+
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use image::RgbImage;
+
+use crate::error::FerriaError;
+
+//APIC/PICTUREの画像種別のうち表紙
+pub const PICTURE_TYPE_FRONT_COVER: u8 = 3;
+
+//ドミナントカラーを数えるときに縮小するサイズ
+const SAMPLE_SIZE: u32 = 64;
+
+//タグに埋め込まれた画像(デコード前のバイト列)
+//メタデータは毎フレーム複製されるので、画像データはArcで共有する
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverArt {
+    pub mime_type: String,
+    pub picture_type: u8,
+    pub data: Arc<[u8]>,
+}
+
+impl CoverArt {
+
+    pub fn new(mime_type: &str, picture_type: u8, data: Vec<u8>) -> Self {
+        CoverArt { mime_type: mime_type.to_string(), picture_type, data: data.into() }
+    }
+
+    pub fn is_front_cover(&self) -> bool {
+        self.picture_type == PICTURE_TYPE_FRONT_COVER
+    }
+
+    //MIMEタイプは当てにならないことがあるので、形式は中身から判定させる
+    pub fn decode(&self) -> Result<RgbImage, FerriaError> {
+        image::load_from_memory(&self.data)
+        .map(|image| image.to_rgb8())
+        .map_err(|e| FerriaError::AudioError(format!("Failed to decode cover art ({}): {}", self.mime_type, e)))
+    }
+
+}
+
+//画像の代表的な色を多い順にcount個まで返す
+//各チャンネル上位4bitで色を分類し、分類ごとの平均色を使う
+pub fn dominant_colors(image: &RgbImage, count: usize) -> Vec<(u8, u8, u8)> {
+
+    let sample = image::imageops::thumbnail(image, SAMPLE_SIZE.min(image.width()), SAMPLE_SIZE.min(image.height()));
+
+    //[画素数, rの合計, gの合計, bの合計]
+    let mut buckets = vec![[0u32; 4]; 16 * 16 * 16];
+
+    for pixel in sample.pixels() {
+        let [r, g, b] = pixel.0;
+        let index = ((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4);
+        let bucket = &mut buckets[index];
+        bucket[0] += 1;
+        bucket[1] += r as u32;
+        bucket[2] += g as u32;
+        bucket[3] += b as u32;
+    }
+
+    let mut used: Vec<[u32; 4]> = buckets.into_iter().filter(|bucket| bucket[0] > 0).collect();
+    used.sort_by(|a, b| b[0].cmp(&a[0]));
+
+    used.iter()
+    .take(count)
+    .map(|&[n, r, g, b]| ((r / n) as u8, (g / n) as u8, (b / n) as u8))
+    .collect()
+
+}
+
+//ドミナントカラーを暗い順に並べ、黒から始まるカラーマップの色にする
+pub fn artwork_palette(image: &RgbImage) -> Vec<(u8, u8, u8)> {
+
+    let mut colors = dominant_colors(image, 5);
+    colors.sort_by(|a, b| luminance(*a).total_cmp(&luminance(*b)));
+
+    let mut stops = vec![(0, 0, 0)];
+    stops.extend(colors);
+
+    stops
+
+}
+
+fn luminance((r, g, b): (u8, u8, u8)) -> f32 {
+    0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32
+}
+
+#[cfg(test)]
+mod test_cover {
+
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_dominant_colors_by_area() {
+        //3/4が赤、1/4が青
+        let image = RgbImage::from_fn(8, 8, |x, _| if x < 6 { Rgb([200, 10, 10]) } else { Rgb([10, 10, 200]) });
+
+        assert_eq!(dominant_colors(&image, 4), vec![(200, 10, 10), (10, 10, 200)]);
+        assert_eq!(artwork_palette(&image), vec![(0, 0, 0), (10, 10, 200), (200, 10, 10)]);
+    }
+
+    #[test]
+    fn test_decode_png() {
+        let image = RgbImage::from_pixel(2, 3, Rgb([1, 2, 3]));
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        let cover = CoverArt::new("image/png", PICTURE_TYPE_FRONT_COVER, png);
+        assert_eq!(cover.decode().unwrap(), image);
+        assert!(CoverArt::new("image/jpeg", 0, vec![0, 1, 2]).decode().is_err());
+    }
+
+}
@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::FerriaError;
+
+//マジックバイトを調べるときに読む長さ
+const HEADER_LENGTH: usize = 64;
+
+//コンテナとコーデックの組み合わせ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Mp3,
+    Flac,
+    OggVorbis,
+    Opus,
+    Wav,
+    Aiff,
+    //MP4コンテナ(AAC/ALAC)
+    Mp4,
+    //ADTSストリームのAAC
+    Aac,
+}
+
+impl AudioFormat {
+
+    //先頭のバイト列(ID3v2タグは読み飛ばしたもの)から形式を判定する
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+
+        if header.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+
+        if header.starts_with(b"OggS") {
+            return ogg_codec(header);
+        }
+
+        if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE" {
+            return Some(AudioFormat::Wav);
+        }
+
+        if header.len() >= 12 && &header[0..4] == b"FORM" && (&header[8..12] == b"AIFF" || &header[8..12] == b"AIFC") {
+            return Some(AudioFormat::Aiff);
+        }
+
+        if header.len() >= 8 && &header[4..8] == b"ftyp" {
+            return Some(AudioFormat::Mp4);
+        }
+
+        //フレーム同期(11bit)の後のレイヤーが0ならADTS、それ以外はMPEGオーディオ
+        if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 {
+            return if header[1] & 0x06 == 0 { Some(AudioFormat::Aac) } else { Some(AudioFormat::Mp3) };
+        }
+
+        None
+
+    }
+
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "mp3" => Some(AudioFormat::Mp3),
+            "flac" => Some(AudioFormat::Flac),
+            "ogg" | "oga" => Some(AudioFormat::OggVorbis),
+            "opus" => Some(AudioFormat::Opus),
+            "wav" => Some(AudioFormat::Wav),
+            "aif" | "aiff" | "aifc" => Some(AudioFormat::Aiff),
+            "m4a" | "m4b" | "mp4" => Some(AudioFormat::Mp4),
+            "aac" => Some(AudioFormat::Aac),
+            _ => None,
+        }
+
+    }
+
+    //マジックバイトを優先し、判定できなければ拡張子で判断する
+    pub fn detect<P: AsRef<Path>>(path: P) -> Result<Self, FerriaError> {
+
+        let path = path.as_ref();
+
+        let mut file = File::open(path)?;
+        let header = read_header(&mut file)?;
+
+        Self::from_magic(&header)
+        .or_else(|| {
+            //ID3タグの後ろが判定できなかった場合はMP3とみなす
+            if has_id3v2(&file) { Some(AudioFormat::Mp3) } else { None }
+        })
+        .or_else(|| Self::from_extension(path))
+        .ok_or_else(|| FerriaError::AudioError(format!("Unsupported audio format: {}", path.display())))
+
+    }
+
+    //代表的な拡張子(デコーダーへのヒントに使う)
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Flac => "flac",
+            AudioFormat::OggVorbis => "ogg",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Wav => "wav",
+            AudioFormat::Aiff => "aiff",
+            AudioFormat::Mp4 => "m4a",
+            AudioFormat::Aac => "aac",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "MP3",
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::OggVorbis => "Ogg Vorbis",
+            AudioFormat::Opus => "Opus",
+            AudioFormat::Wav => "WAV",
+            AudioFormat::Aiff => "AIFF",
+            AudioFormat::Mp4 => "MP4",
+            AudioFormat::Aac => "AAC",
+        }
+    }
+
+}
+
+//先頭のID3v2タグを読み飛ばして、その後ろのHEADER_LENGTHバイトを返す
+fn read_header<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>, FerriaError> {
+
+    let mut header = read_up_to(reader, HEADER_LENGTH)?;
+
+    if let Some(tag_size) = id3v2_size(&header) {
+        reader.seek(SeekFrom::Start(tag_size as u64))?;
+        header = read_up_to(reader, HEADER_LENGTH)?;
+    }
+
+    Ok(header)
+
+}
+
+fn has_id3v2(mut file: &File) -> bool {
+
+    let mut header = [0u8; 10];
+
+    file.seek(SeekFrom::Start(0)).is_ok()
+    && file.read_exact(&mut header).is_ok()
+    && id3v2_size(&header).is_some()
+
+}
+
+//ID3v2タグ全体(ヘッダー、フッター込み)のバイト数
+pub(crate) fn id3v2_size(header: &[u8]) -> Option<usize> {
+
+    if header.len() < 10 || &header[0..3] != b"ID3" {
+        return None;
+    }
+
+    //サイズは各バイト7bitのsyncsafe整数
+    let size = header[6..10].iter().fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    Some(10 + size + footer)
+
+}
+
+fn read_up_to<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>, FerriaError> {
+
+    let mut buffer = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+
+}
+
+//Oggの最初のページに入っている識別ヘッダーでコーデックを判定する
+fn ogg_codec(page: &[u8]) -> Option<AudioFormat> {
+
+    let segments = *page.get(26)? as usize;
+    let packet = page.get(27 + segments..)?;
+
+    if packet.starts_with(b"OpusHead") {
+        Some(AudioFormat::Opus)
+    }
+    else if packet.starts_with(b"\x01vorbis") {
+        Some(AudioFormat::OggVorbis)
+    }
+    else {
+        None
+    }
+
+}
+
+#[cfg(test)]
+mod test_format {
+
+    use super::*;
+    use std::io::Cursor;
+
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    #[test]
+    fn test_from_magic() {
+        assert_eq!(AudioFormat::from_magic(b"fLaC\0\0\0\x22"), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::from_magic(b"RIFF\0\0\0\0WAVEfmt "), Some(AudioFormat::Wav));
+        assert_eq!(AudioFormat::from_magic(b"FORM\0\0\0\0AIFFCOMM"), Some(AudioFormat::Aiff));
+        assert_eq!(AudioFormat::from_magic(b"\0\0\0\x20ftypM4A "), Some(AudioFormat::Mp4));
+        assert_eq!(AudioFormat::from_magic(&[0xFF, 0xFB, 0x90, 0x00]), Some(AudioFormat::Mp3));
+        assert_eq!(AudioFormat::from_magic(&[0xFF, 0xF1, 0x50, 0x80]), Some(AudioFormat::Aac));
+        assert_eq!(AudioFormat::from_magic(&ogg_page(b"OpusHead\x01\x02")), Some(AudioFormat::Opus));
+        assert_eq!(AudioFormat::from_magic(&ogg_page(b"\x01vorbis\0\0")), Some(AudioFormat::OggVorbis));
+        assert_eq!(AudioFormat::from_magic(b"plain text"), None);
+    }
+
+    #[test]
+    fn test_header_skips_id3v2() {
+        //10バイトのヘッダー + 4バイトのタグ本体の後ろにFLAC
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x04".to_vec();
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"fLaC");
+
+        let header = read_header(&mut Cursor::new(data)).unwrap();
+        assert_eq!(AudioFormat::from_magic(&header), Some(AudioFormat::Flac));
+    }
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(AudioFormat::from_extension("a/b.FLAC"), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::from_extension("song.opus"), Some(AudioFormat::Opus));
+        assert_eq!(AudioFormat::from_extension("song.m4a"), Some(AudioFormat::Mp4));
+        assert_eq!(AudioFormat::from_extension("cover.jpg"), None);
+    }
+
+}
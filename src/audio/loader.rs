@@ -1,21 +1,212 @@
-use std::{fs::File, io::BufReader, path::Path};
-use ratatui::crossterm::event::read;
+use std::{fs::File, io::BufReader, path::{Path, PathBuf}};
+use rodio::source::SeekError;
 use rodio::{Decoder, Source};
-use id3::{Tag, TagLike};
+use rodio::decoder::Mp4Type;
 use std::time::Duration;
 use std::io::{Error, ErrorKind};
+use symphonia::core::{formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
 
+use crate::audio::cover::CoverArt;
+use crate::audio::format::AudioFormat;
+use crate::audio::opus::OpusDecoder;
+use crate::audio::tags::{self, RawTags};
 use crate::error::FerriaError;
 
-#[derive(Clone)]
+//ReplayGainの値(dB, ピークは0.0〜1.0)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain_db: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+
+    pub fn is_empty(&self) -> bool {
+        *self == ReplayGain::default()
+    }
+
+}
+
+//タグに無い項目はNoneのままにして、表示するときに補う
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AudioTrackMetaData {
-    pub title: String,
-    pub artist: String,
+    pub path: Option<PathBuf>,
+    pub format: Option<AudioFormat>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    pub lyrics: Option<String>,
+    pub replay_gain: ReplayGain,
+    //埋め込みのカバー画像(デコードは表示する側で行う)
+    pub cover: Option<CoverArt>,
+    //ストリームの情報
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub bit_depth: Option<u32>,
+    pub bitrate_kbps: Option<u32>,
     pub duration: Option<Duration>,
 }
 
+impl AudioTrackMetaData {
+
+    //タグの値から作る。形式ごとのキーの違いはread_tagsでVorbisコメントの名前に揃えてある
+    pub fn from_tags(tags: &RawTags) -> Self {
+
+        let text = |keys: &[&str]| keys.iter().find_map(|key| tags.get(key)).map(str::to_string);
+
+        //"3/12"のように総数が一緒に入っていることがある
+        let (track_number, track_in_total) = tags.get("TRACKNUMBER").map(parse_number_pair).unwrap_or_default();
+        let (disc_number, disc_in_total) = tags.get("DISCNUMBER").map(parse_number_pair).unwrap_or_default();
+
+        let number = |keys: &[&str]| keys.iter().find_map(|key| tags.get(key)).and_then(|value| value.trim().parse().ok());
+
+        AudioTrackMetaData {
+            title: text(&["TITLE"]),
+            artist: text(&["ARTIST"]),
+            album: text(&["ALBUM"]),
+            album_artist: text(&["ALBUMARTIST", "ALBUM ARTIST", "ALBUM_ARTIST"]),
+            track_number,
+            track_total: number(&["TRACKTOTAL", "TOTALTRACKS"]).or(track_in_total),
+            disc_number,
+            disc_total: number(&["DISCTOTAL", "TOTALDISCS"]).or(disc_in_total),
+            year: ["DATE", "YEAR", "ORIGINALDATE"].iter().find_map(|key| tags.get(key)).and_then(parse_year),
+            genre: text(&["GENRE"]),
+            composer: text(&["COMPOSER"]),
+            comment: text(&["COMMENT", "DESCRIPTION"]),
+            lyrics: text(&["LYRICS", "UNSYNCEDLYRICS"]),
+            replay_gain: replay_gain_from_tags(tags),
+            cover: tags.cover.clone(),
+            ..Default::default()
+        }
+
+    }
+
+    //タイトルが無ければファイル名を使う
+    pub fn display_title(&self) -> String {
+        self.title.clone()
+        .or_else(|| self.path.as_ref()?.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "Unknown Title".to_string())
+    }
+
+    pub fn display_artist(&self) -> &str {
+        self.artist.as_deref().or(self.album_artist.as_deref()).unwrap_or("Unknown Artist")
+    }
+
+}
+
+//"3/12" -> (Some(3), Some(12)), "3" -> (Some(3), None)
+fn parse_number_pair(value: &str) -> (Option<u32>, Option<u32>) {
+
+    let mut parts = value.splitn(2, '/');
+    let number = parts.next().and_then(|n| n.trim().parse().ok());
+    let total = parts.next().and_then(|n| n.trim().parse().ok());
+
+    (number, total)
+
+}
+
+//"2021-04-01"や"2021"の先頭の4桁を年とする
+fn parse_year(value: &str) -> Option<i32> {
+    let digits: String = value.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() == 4 { digits.parse().ok() } else { None }
+}
+
+//"-7.20 dB" -> -7.2
+fn parse_gain(value: &str) -> Option<f32> {
+    value.trim().trim_end_matches(|c: char| c.is_ascii_alphabetic() || c.is_whitespace()).parse().ok()
+}
+
+fn replay_gain_from_tags(tags: &RawTags) -> ReplayGain {
+
+    let value = |key: &str| tags.get(key).and_then(parse_gain);
+
+    let mut replay_gain = ReplayGain {
+        track_gain_db: value("REPLAYGAIN_TRACK_GAIN"),
+        track_peak: value("REPLAYGAIN_TRACK_PEAK"),
+        album_gain_db: value("REPLAYGAIN_ALBUM_GAIN"),
+        album_peak: value("REPLAYGAIN_ALBUM_PEAK"),
+    };
+
+    //OpusはQ7.8固定小数点のR128ゲイン(基準-23LUFS)なので、ReplayGainの基準(-18LUFS)に合わせて5dB足す
+    let r128 = |key: &str| tags.get(key).and_then(|v| v.trim().parse::<i16>().ok()).map(|v| v as f32 / 256.0 + 5.0);
+    replay_gain.track_gain_db = replay_gain.track_gain_db.or_else(|| r128("R128_TRACK_GAIN"));
+    replay_gain.album_gain_db = replay_gain.album_gain_db.or_else(|| r128("R128_ALBUM_GAIN"));
+
+    replay_gain
+
+}
+
+//形式ごとのデコーダー。rodioに無いOpusは自前のデコーダーを使う
+pub enum TrackDecoder {
+    Rodio(Decoder<BufReader<File>>),
+    Opus(OpusDecoder<BufReader<File>>),
+}
+
+impl Iterator for TrackDecoder {
+
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.next(),
+            TrackDecoder::Opus(decoder) => decoder.next(),
+        }
+    }
+
+}
+
+impl Source for TrackDecoder {
+
+    fn current_frame_len(&self) -> Option<usize> {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.current_frame_len(),
+            TrackDecoder::Opus(decoder) => decoder.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.channels(),
+            TrackDecoder::Opus(decoder) => decoder.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.sample_rate(),
+            TrackDecoder::Opus(decoder) => decoder.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.total_duration(),
+            TrackDecoder::Opus(decoder) => decoder.total_duration(),
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        match self {
+            TrackDecoder::Rodio(decoder) => decoder.try_seek(pos),
+            TrackDecoder::Opus(decoder) => decoder.try_seek(pos),
+        }
+    }
+
+}
+
 pub struct AudioTrack {
-    pub decoder: Decoder<BufReader<File>>,
+    pub decoder: TrackDecoder,
     pub metadata: AudioTrackMetaData,
 }
 
@@ -23,17 +214,26 @@ impl AudioTrack {
 
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, FerriaError> {
 
-        //ここは取れなくてもok
-        let mut metadata = read_id3_metadata(&path).unwrap();
+        let reader = open_audio_file(&path)?;
 
-        let reader = load_mp3(&path)?;
+        let format = AudioFormat::detect(&path)?;
 
-        let decoder = decode_audio_from_reader(reader)?;
+        //タグは読めなくてもok
+        let tags = tags::read_tags(&path, format).unwrap_or_default();
+        let mut metadata = AudioTrackMetaData::from_tags(&tags);
+        metadata.path = Some(path.as_ref().to_path_buf());
+        metadata.format = Some(format);
 
-        let calculated_duration  = decoder.total_duration();
-        // println!("DEBUG: Decoder calculated duration: {:?}", calculated_duration);
+        let decoder = decode_audio_from_reader(reader, format)?;
 
-        metadata.duration = calculated_duration;
+        //rodio(0.20)のsymphonia経由のtotal_durationは小数部の変換が壊れているので、コンテナのヘッダーから求める
+        let stream = probe_stream_info(&path, format).unwrap_or_default();
+
+        metadata.duration = stream.duration.or_else(|| decoder.total_duration());
+        metadata.sample_rate = stream.sample_rate.or(Some(decoder.sample_rate()));
+        metadata.channels = stream.channels.or(Some(decoder.channels()));
+        metadata.bit_depth = stream.bit_depth;
+        metadata.bitrate_kbps = average_bitrate_kbps(&path, metadata.duration);
 
         Ok(AudioTrack { decoder, metadata })
 
@@ -41,7 +241,7 @@ impl AudioTrack {
 
 }
 
-pub fn load_mp3<P: AsRef<Path>>(path: P) -> Result<BufReader<File>, FerriaError> {
+pub fn open_audio_file<P: AsRef<Path>>(path: P) -> Result<BufReader<File>, FerriaError> {
 
     let path_ref = path.as_ref();
 
@@ -54,34 +254,176 @@ pub fn load_mp3<P: AsRef<Path>>(path: P) -> Result<BufReader<File>, FerriaError>
     }
 
     let file: File = File::open(path_ref)
-    .map_err(|_e| FerriaError::IOError(Error::new(ErrorKind::Other, format!("Failed to open file: {}", path_ref.display()))))?;
+    .map_err(|_e| FerriaError::IOError(Error::other(format!("Failed to open file: {}", path_ref.display()))))?;
 
     Ok(BufReader::new(file))
 
 }
 
-fn decode_audio_from_reader(reader: BufReader<File>) -> Result<Decoder<BufReader<File>>, crate::error::FerriaError> {
+#[derive(Debug, Default)]
+struct StreamInfo {
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+    bit_depth: Option<u32>,
+    duration: Option<Duration>,
+}
+
+//コンテナのヘッダーからサンプルレートなどと、フレーム数から再生時間を求める
+fn probe_stream_info<P: AsRef<Path>>(path: P, format: AudioFormat) -> Option<StreamInfo> {
+
+    let file = File::open(path.as_ref()).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(format.extension());
+
+    let probed = symphonia::default::get_probe()
+    .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+    .ok()?;
 
-    Decoder::new(reader)
-    .map_err(|e| crate::error::FerriaError::AudioError(format!("Failed to decoder audio: {}", e)))
+    let params = &probed.format.default_track()?.codec_params;
+
+    let duration = params.n_frames.and_then(|frames| match params.time_base {
+        Some(time_base) => {
+            let time = time_base.calc_time(frames);
+            Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        },
+        None => Some(Duration::from_secs_f64(frames as f64 / params.sample_rate? as f64)),
+    });
+
+    Some(StreamInfo {
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count() as u16),
+        bit_depth: params.bits_per_sample,
+        duration,
+    })
 
 }
 
-pub fn read_id3_metadata<P: AsRef<Path>>(path: P) -> Result<AudioTrackMetaData, FerriaError> {
+//ファイルサイズと再生時間から平均ビットレートを求める(タグ分も含む概算)
+fn average_bitrate_kbps<P: AsRef<Path>>(path: P, duration: Option<Duration>) -> Option<u32> {
 
-    //id3タグの有無を問わないようにする(cdからの吸い出し以外では無いことが多々ある)
-    let tag = Tag::read_from_path(&path).ok();
+    let seconds = duration?.as_secs_f64();
+    if seconds <= 0.0 {
+        return None;
+    }
 
-    Ok( AudioTrackMetaData { 
-        title: tag.as_ref().and_then(|t| t.title().map(|s| s.to_string())).unwrap_or_else(|| "Unknown Title".to_string()), 
-        artist: tag.as_ref().and_then(|t| t.artist().map(|s| s.to_string())).unwrap_or_else(|| "Unknown Artist".to_string()),   
-        duration: None, } )
+    let bytes = std::fs::metadata(path.as_ref()).ok()?.len();
+
+    Some((bytes as f64 * 8.0 / seconds / 1000.0).round() as u32)
+
+}
+
+//形式ごとのデコーダーを使う(判定済みなので総当たりで探さない)
+fn decode_audio_from_reader(reader: BufReader<File>, format: AudioFormat) -> Result<TrackDecoder, FerriaError> {
+
+    let decoder = match format {
+        AudioFormat::Mp3 => Decoder::new_mp3(reader),
+        AudioFormat::Flac => Decoder::new_flac(reader),
+        AudioFormat::OggVorbis => Decoder::new_vorbis(reader),
+        AudioFormat::Wav => Decoder::new_wav(reader),
+        AudioFormat::Aac => Decoder::new_aac(reader),
+        AudioFormat::Mp4 => Decoder::new_mp4(reader, Mp4Type::M4a),
+        //AIFFはrodioに専用のコンストラクタが無いので中身から判定させる
+        AudioFormat::Aiff => Decoder::new(reader),
+        //rodio(symphonia)にはOpusのデコーダーが無いのでlibopusでデコードする
+        AudioFormat::Opus => return Ok(TrackDecoder::Opus(OpusDecoder::new(reader)?)),
+    };
+
+    decoder
+    .map(TrackDecoder::Rodio)
+    .map_err(|e| FerriaError::AudioError(format!("Failed to decode {} audio: {}", format.name(), e)))
 
 }
 
 #[cfg(test)]
 mod test_loader {
 
+    use super::*;
+    use std::io::Write;
+
+    //16bitモノラルのWAVにLIST/INFOチャンクを付けて書き出す
+    fn write_wav(path: &Path, samples: &[i16], title: &str) {
+
+        let info = [b"INFO".to_vec(), b"INAM".to_vec(), (title.len() as u32).to_le_bytes().to_vec(), title.as_bytes().to_vec()].concat();
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());//PCM
+        fmt.extend_from_slice(&1u16.to_le_bytes());//チャンネル数
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&16000u32.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", &fmt), (b"LIST", &info), (b"data", &data)] {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+        }
+
+        let mut file = File::create(path).unwrap();
+        file.write_all(b"RIFF").unwrap();
+        file.write_all(&(body.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&body).unwrap();
+    }
+
+    #[test]
+    fn test_load_wav_with_riff_info() {
+        //拡張子に頼らずにマジックバイトで判定できること
+        let path = std::env::temp_dir().join(format!("ferria_loader_{}.bin", std::process::id()));
+        write_wav(&path, &[0, 1000, -1000, 0].repeat(2000), "Sine");
+
+        let track = AudioTrack::new(&path).unwrap();
+        assert_eq!(track.metadata.format, Some(AudioFormat::Wav));
+        assert_eq!(track.metadata.title.as_deref(), Some("Sine"));
+        assert_eq!(track.metadata.display_artist(), "Unknown Artist");
+        assert_eq!(track.decoder.sample_rate(), 8000);
+        assert_eq!(track.metadata.duration, Some(Duration::from_secs(1)));
+        assert_eq!(track.metadata.sample_rate, Some(8000));
+        assert_eq!(track.metadata.channels, Some(1));
+        assert_eq!(track.metadata.bit_depth, Some(16));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_from_tags() {
+        let mut tags = RawTags::default();
+        tags.push("TITLE", "Eine kleine Nachtmusik");
+        tags.push("ALBUM ARTIST", "Mozart");
+        tags.push("TRACKNUMBER", "3/12");
+        tags.push("DISCNUMBER", "1");
+        tags.push("DATE", "1787-08-10");
+        tags.push("REPLAYGAIN_TRACK_GAIN", "-7.20 dB");
+        tags.push("REPLAYGAIN_TRACK_PEAK", "0.988");
+        tags.push("R128_ALBUM_GAIN", "-512");
+
+        let metadata = AudioTrackMetaData::from_tags(&tags);
+        assert_eq!(metadata.display_title(), "Eine kleine Nachtmusik");
+        assert_eq!(metadata.artist, None);
+        assert_eq!(metadata.display_artist(), "Mozart");
+        assert_eq!((metadata.track_number, metadata.track_total), (Some(3), Some(12)));
+        assert_eq!((metadata.disc_number, metadata.disc_total), (Some(1), None));
+        assert_eq!(metadata.year, Some(1787));
+        assert_eq!(metadata.replay_gain.track_gain_db, Some(-7.2));
+        assert_eq!(metadata.replay_gain.track_peak, Some(0.988));
+        assert_eq!(metadata.replay_gain.album_gain_db, Some(3.0));
+    }
+
+    #[test]
+    fn test_display_title_falls_back_to_file_name() {
+        let metadata = AudioTrackMetaData { path: Some(PathBuf::from("music/01 Intro.flac")), ..Default::default() };
+        assert_eq!(metadata.display_title(), "01 Intro");
+        assert_eq!(AudioTrackMetaData::default().display_title(), "Unknown Title");
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(AudioTrack::new("does/not/exist.flac").is_err());
+    }
+
 }
 
 
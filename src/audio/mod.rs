@@ -1,3 +1,14 @@
 pub mod loader;
+pub mod format;
+pub mod opus;
+pub mod tags;
+pub mod cover;
 pub mod player;
-pub mod analyzer;
\ No newline at end of file
+pub mod output;
+pub mod analyzer;
+pub mod window;
+pub mod scaling;
+pub mod offline;
+pub mod bands;
+pub mod playlist;
+pub mod queue;
\ No newline at end of file
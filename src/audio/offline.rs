@@ -0,0 +1,392 @@
+use clap::ValueEnum;
+use rodio::{Sample, Source};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::audio::analyzer::{AnalyzerConfig, AudioAnalyzer, SpectrumData};
+use crate::audio::loader::AudioTrack;
+use crate::error::FerriaError;
+
+//再生せずにファイル全体を解析して、フレームごとのスペクトルを書き出す
+
+//書き出す形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    ///1行目が見出し(time_s, max_amplitude, 各帯域の中心周波数)のCSV
+    Csv,
+    ///1フレーム1行のJSON
+    Jsonl,
+    ///NumPyの.npy(float32の2次元配列)。1行目は[NaN, NaN, 各帯域の中心周波数]
+    Npy,
+}
+
+impl DumpFormat {
+
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "csv" => Some(DumpFormat::Csv),
+            "jsonl" | "json" | "ndjson" => Some(DumpFormat::Jsonl),
+            "npy" => Some(DumpFormat::Npy),
+            _ => None,
+        }
+
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpOptions {
+    pub path: PathBuf,
+    pub format: DumpFormat,
+}
+
+//解析した1フレーム。timeは窓の先頭の時刻
+#[derive(Debug, Clone)]
+pub struct SpectrumFrame {
+    pub time: Duration,
+    pub spectrum: SpectrumData,
+}
+
+//Sourceを最後まで読みながらconfig.hop_sizeごとにスペクトルを返すイテレータ
+pub struct OfflineAnalyzer<S>
+where S: Source,
+      S::Item: Sample,
+{
+    source: S,
+    analyzer: AudioAnalyzer,
+    hop_size: usize,
+    //モノラルにダウンミックスしたサンプル
+    buffer: Vec<f32>,
+    //bufferの先頭のサンプルの位置(フレーム数)
+    buffer_start: u64,
+    finished: bool,
+}
+
+impl<S> OfflineAnalyzer<S>
+where S: Source,
+      S::Item: Sample,
+{
+
+    pub fn new(source: S, config: AnalyzerConfig) -> Result<Self, FerriaError> {
+
+        let analyzer = AudioAnalyzer::with_config(config, source.sample_rate())?;
+
+        Ok(OfflineAnalyzer {
+            source,
+            analyzer,
+            hop_size: config.hop_size,
+            buffer: Vec::with_capacity(config.fft_size * 2),
+            buffer_start: 0,
+            finished: false,
+        })
+
+    }
+
+    //fft_sizeに足りるまでフレーム単位で読み込む
+    fn fill(&mut self) {
+
+        let fft_size = self.analyzer.fft_size();
+        let channels = self.source.channels().max(1) as usize;
+
+        while !self.finished && self.buffer.len() < fft_size {
+
+            let mut sum = 0.0;
+
+            for i in 0..channels {
+                match self.source.next() {
+                    Some(sample) => sum += sample.to_f32(),
+                    None => {
+                        //途中で終わったフレームは捨てる
+                        self.finished = true;
+                        if i > 0 {
+                            return;
+                        }
+                        break;
+                    },
+                }
+            }
+
+            if !self.finished {
+                self.buffer.push(sum / channels as f32);
+            }
+        }
+
+    }
+
+}
+
+impl<S> Iterator for OfflineAnalyzer<S>
+where S: Source,
+      S::Item: Sample,
+{
+
+    type Item = Result<SpectrumFrame, FerriaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+
+        self.fill();
+
+        let fft_size = self.analyzer.fft_size();
+
+        //最後の窓に満たない端数は解析しない
+        if self.buffer.len() < fft_size {
+            return None;
+        }
+
+        let sample_rate = self.analyzer.sample_rate() as u64;
+        let time = Duration::from_secs(self.buffer_start / sample_rate)
+            + Duration::from_nanos((self.buffer_start % sample_rate) * 1_000_000_000 / sample_rate);
+
+        let result = self.analyzer.analyze(&self.buffer[..fft_size]).map(|spectrum| SpectrumFrame { time, spectrum });
+
+        let consumed = self.hop_size.min(self.buffer.len());
+        self.buffer.drain(..consumed);
+        self.buffer_start += self.hop_size as u64;
+
+        //hopがfft_sizeより大きいときは間のサンプルを読み飛ばす
+        let mut skip = self.hop_size - consumed;
+        while skip > 0 && !self.finished {
+            for _ in 0..self.source.channels().max(1) {
+                if self.source.next().is_none() {
+                    self.finished = true;
+                }
+            }
+            skip -= 1;
+        }
+
+        Some(result)
+
+    }
+
+}
+
+//フレームを形式に合わせて書き出す
+pub struct DumpWriter<W: Write> {
+    writer: W,
+    format: DumpFormat,
+    frames: usize,
+    //npyは配列の形をヘッダーに書くので、最後まで溜めてから書き出す
+    npy_rows: Vec<Vec<f32>>,
+}
+
+impl<W: Write> DumpWriter<W> {
+
+    pub fn new(writer: W, format: DumpFormat) -> Self {
+        DumpWriter { writer, format, frames: 0, npy_rows: Vec::new() }
+    }
+
+    pub fn write_frame(&mut self, frame: &SpectrumFrame) -> Result<(), FerriaError> {
+
+        let (frequencies, values) = frame_columns(&frame.spectrum);
+        let time = frame.time.as_secs_f64();
+
+        match self.format {
+            DumpFormat::Csv => {
+                if self.frames == 0 {
+                    let header: Vec<String> = frequencies.iter().map(|f| format!("{}", f)).collect();
+                    writeln!(self.writer, "time_s,max_amplitude,{}", header.join(","))?;
+                }
+
+                let values: Vec<String> = values.iter().map(|v| format!("{}", v)).collect();
+                writeln!(self.writer, "{},{},{}", time, frame.spectrum.max_amplitude, values.join(","))?;
+            },
+            DumpFormat::Jsonl => {
+                writeln!(
+                    self.writer,
+                    "{{\"time\":{},\"sample_rate\":{},\"fft_size\":{},\"max_amplitude\":{},\"frequencies\":{},\"values\":{}}}",
+                    time,
+                    frame.spectrum.sample_rate,
+                    frame.spectrum.fft_size,
+                    json_number(frame.spectrum.max_amplitude),
+                    json_array(&frequencies),
+                    json_array(&values),
+                )?;
+            },
+            DumpFormat::Npy => {
+                if self.frames == 0 {
+                    self.npy_rows.push([f32::NAN, f32::NAN].into_iter().chain(frequencies).collect());
+                }
+
+                self.npy_rows.push([time as f32, frame.spectrum.max_amplitude].into_iter().chain(values).collect());
+            },
+        }
+
+        self.frames += 1;
+
+        Ok(())
+
+    }
+
+    //書き出したフレーム数を返す
+    pub fn finish(mut self) -> Result<usize, FerriaError> {
+
+        if self.format == DumpFormat::Npy {
+            write_npy(&mut self.writer, &self.npy_rows)?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(self.frames)
+
+    }
+
+}
+
+//帯域にまとめてあれば帯域の中心周波数と値、無ければ線形のビン
+fn frame_columns(spectrum: &SpectrumData) -> (Vec<f32>, Vec<f32>) {
+
+    if spectrum.bands.is_empty() {
+        let frequencies = (0..spectrum.bins.len()).map(|i| spectrum.bin_frequency(i)).collect();
+        (frequencies, spectrum.bins.clone())
+    } else {
+        spectrum.bands.iter().map(|band| (band.center_hz, band.value)).unzip()
+    }
+
+}
+
+//JSONにNaNや無限大は書けないのでnullにする
+fn json_number(value: f32) -> String {
+    if value.is_finite() { format!("{}", value) } else { "null".to_string() }
+}
+
+fn json_array(values: &[f32]) -> String {
+    let values: Vec<String> = values.iter().map(|&v| json_number(v)).collect();
+    format!("[{}]", values.join(","))
+}
+
+//NumPy形式(バージョン1.0)のfloat32、C順の2次元配列
+fn write_npy<W: Write>(writer: &mut W, rows: &[Vec<f32>]) -> Result<(), FerriaError> {
+
+    let columns = rows.first().map_or(0, Vec::len);
+
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", rows.len(), columns);
+    //マジック(6) + バージョン(2) + ヘッダー長(2) + ヘッダーを64バイト境界に揃え、改行で終える
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+
+    for row in rows {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+
+}
+
+//ファイルを1つ解析して書き出し、書き出したフレーム数を返す
+pub fn dump_track<P: AsRef<Path>>(path: P, config: AnalyzerConfig, options: &DumpOptions) -> Result<usize, FerriaError> {
+
+    let track = AudioTrack::new(path)?;
+
+    let file = File::create(&options.path)?;
+    let mut writer = DumpWriter::new(BufWriter::new(file), options.format);
+
+    for frame in OfflineAnalyzer::new(track.decoder, config)? {
+        writer.write_frame(&frame?)?;
+    }
+
+    writer.finish()
+
+}
+
+#[cfg(test)]
+mod test_offline {
+
+    use super::*;
+    use crate::audio::bands::{BandLayout, BandScale};
+    use rodio::buffer::SamplesBuffer;
+
+    fn config(hop_size: usize) -> AnalyzerConfig {
+        AnalyzerConfig { fft_size: 256, hop_size, band_layout: BandLayout { scale: BandScale::Linear, count: 4 }, ..Default::default() }
+    }
+
+    fn frames(samples: usize, hop_size: usize) -> Vec<SpectrumFrame> {
+        //ステレオ8000Hzの正弦波
+        let data: Vec<f32> = (0..samples).flat_map(|i| {
+            let s = (i as f32 * 0.3).sin();
+            [s, s]
+        }).collect();
+
+        OfflineAnalyzer::new(SamplesBuffer::new(2, 8000, data), config(hop_size)).unwrap()
+        .collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_overlapping_frames_and_timestamps() {
+        //256サンプルの窓を64サンプルずつずらす
+        let frames = frames(1024, 64);
+        assert_eq!(frames.len(), (1024 - 256) / 64 + 1);
+        assert_eq!(frames[1].time, Duration::from_millis(8));
+        assert_eq!(frames.last().unwrap().time, Duration::from_millis(96));
+
+        //hopが窓より大きいと間を読み飛ばす
+        let frames = self::frames(1024, 512);
+        assert_eq!(frames.iter().map(|f| f.time).collect::<Vec<_>>(), vec![Duration::ZERO, Duration::from_millis(64)]);
+
+        assert!(OfflineAnalyzer::new(SamplesBuffer::new(1, 8000, vec![0.0f32; 10]), config(0)).is_err());
+    }
+
+    #[test]
+    fn test_csv_and_jsonl_output() {
+        let frames = frames(512, 256);
+
+        let mut csv = DumpWriter::new(Vec::new(), DumpFormat::Csv);
+        let mut jsonl = DumpWriter::new(Vec::new(), DumpFormat::Jsonl);
+        for frame in &frames {
+            csv.write_frame(frame).unwrap();
+            jsonl.write_frame(frame).unwrap();
+        }
+        let csv = String::from_utf8(std::mem::take(&mut csv.writer)).unwrap();
+        let jsonl = String::from_utf8(std::mem::take(&mut jsonl.writer)).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + frames.len());
+        assert_eq!(lines[0], "time_s,max_amplitude,517.5,1512.5,2507.5,3502.5");
+        assert!(lines[2].starts_with("0.032,"));
+        assert_eq!(lines[1].split(',').count(), 6);
+
+        assert_eq!(jsonl.lines().count(), frames.len());
+        assert!(jsonl.lines().next().unwrap().starts_with("{\"time\":0,\"sample_rate\":8000,\"fft_size\":256,"));
+        assert!(jsonl.contains("\"frequencies\":[517.5,1512.5,2507.5,3502.5]"));
+    }
+
+    #[test]
+    fn test_npy_output() {
+        let frames = frames(512, 256);
+
+        let mut npy = DumpWriter::new(Vec::new(), DumpFormat::Npy);
+        for frame in &frames {
+            npy.write_frame(frame).unwrap();
+        }
+        let rows = npy.npy_rows.clone();
+        let mut data = Vec::new();
+        write_npy(&mut data, &rows).unwrap();
+
+        assert!(data.starts_with(b"\x93NUMPY\x01\x00"));
+        let header_length = u16::from_le_bytes([data[8], data[9]]) as usize;
+        assert_eq!((10 + header_length) % 64, 0);
+
+        let header = String::from_utf8_lossy(&data[10..10 + header_length]);
+        assert!(header.contains("'shape': (3, 6)"));
+        assert_eq!(data.len(), 10 + header_length + 3 * 6 * 4);
+
+        //1行目は周波数
+        let value = |i: usize| f32::from_le_bytes(data[10 + header_length + i * 4..][..4].try_into().unwrap());
+        assert!(value(0).is_nan());
+        assert_eq!(value(2), 517.5);
+        assert_eq!(value(6), 0.0);
+        assert_eq!(value(12), 0.032);
+    }
+
+}
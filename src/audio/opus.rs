@@ -0,0 +1,351 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use audiopus::coder::Decoder as OpusCodec;
+use audiopus::packet::Packet;
+use audiopus::{Channels, MutSignals, SampleRate};
+use ogg::reading::PacketReader;
+use rodio::source::SeekError;
+use rodio::Source;
+
+use crate::error::FerriaError;
+
+//Opusは入力のサンプルレートに関わらず48kHzでデコードする
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+//1パケットの最大の長さ(120ms)
+const MAX_PACKET_FRAMES: usize = 5760;
+
+//末尾のページを探すときに読む長さ(Oggのページは最大で約64KiB)
+const TAIL_LENGTH: u64 = 65536 + 27 + 255;
+
+//OpusHeadパケットの中身
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusHead {
+    pub channels: u16,
+    //先頭で捨てるフレーム数(48kHz)
+    pub pre_skip: u16,
+    //エンコード前のサンプルレート(表示用)
+    pub input_sample_rate: u32,
+    //出力にかけるゲイン(Q7.8のdB)
+    pub output_gain: i16,
+}
+
+impl OpusHead {
+
+    //"OpusHead"、バージョン、チャンネル数、pre-skip、入力サンプルレート、出力ゲイン、チャンネルマッピングの順に並ぶ
+    pub fn parse(packet: &[u8]) -> Result<Self, FerriaError> {
+
+        if packet.len() < 19 || !packet.starts_with(b"OpusHead") {
+            return Err(opus_error("OpusHead packet not found"));
+        }
+
+        let channels = packet[9] as u16;
+        let mapping_family = packet[18];
+
+        //マッピング0(モノラル/ステレオ)だけに対応する
+        if mapping_family != 0 || !(1..=2).contains(&channels) {
+            return Err(opus_error(&format!("Unsupported Opus channel layout ({} channels, mapping family {})", channels, mapping_family)));
+        }
+
+        Ok(OpusHead {
+            channels,
+            pre_skip: u16::from_le_bytes([packet[10], packet[11]]),
+            input_sample_rate: u32::from_le_bytes([packet[12], packet[13], packet[14], packet[15]]),
+            output_gain: i16::from_le_bytes([packet[16], packet[17]]),
+        })
+
+    }
+
+}
+
+//Ogg Opusのデコーダー。rodioのDecoderと同じくi16のサンプルを返す
+pub struct OpusDecoder<R: Read + Seek> {
+    reader: PacketReader<R>,
+    codec: OpusCodec,
+    head: OpusHead,
+    //最後のページのグラニュール位置(pre-skip込みのフレーム数)。ここより後ろは捨てる
+    end_granule: Option<u64>,
+    //ここより前のフレームは捨てる(シーク先)
+    skip_to: u64,
+    //次にデコードするパケットの先頭のグラニュール位置
+    decoded_frames: u64,
+    buffer: Vec<i16>,
+    buffer_pos: usize,
+    //bufferの先頭のフレームのグラニュール位置
+    buffer_start: u64,
+    decode_buffer: Vec<i16>,
+}
+
+impl<R: Read + Seek> OpusDecoder<R> {
+
+    pub fn new(mut reader: R) -> Result<Self, FerriaError> {
+
+        let end_granule = last_granule(&mut reader)?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut reader = PacketReader::new(reader);
+        let head = read_headers(&mut reader)?;
+        let codec = new_codec(head)?;
+
+        Ok(OpusDecoder {
+            reader,
+            codec,
+            head,
+            end_granule,
+            skip_to: head.pre_skip as u64,
+            decoded_frames: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            buffer_start: 0,
+            decode_buffer: vec![0; MAX_PACKET_FRAMES * head.channels as usize],
+        })
+
+    }
+
+    pub fn head(&self) -> OpusHead {
+        self.head
+    }
+
+    //次のパケットをデコードしてbufferに入れる。ストリームの終わりならfalse
+    fn fill_buffer(&mut self) -> bool {
+
+        let channels = self.head.channels as usize;
+
+        loop {
+
+            let packet = match self.reader.read_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) | Err(_) => return false,
+            };
+
+            let Ok(input) = Packet::try_from(packet.data.as_slice()) else { continue };
+            let Ok(output) = MutSignals::try_from(self.decode_buffer.as_mut_slice()) else { return false };
+
+            //壊れたパケットは飛ばして次へ進む
+            let Ok(frames) = self.codec.decode(Some(input), output, false) else { continue };
+
+            let start = self.decoded_frames;
+            let end = start + frames as u64;
+            self.decoded_frames = end;
+
+            //pre-skipとシーク先より前、最後のページのグラニュール位置より後ろを除く
+            let keep_start = start.max(self.skip_to);
+            let keep_end = self.end_granule.map_or(end, |limit| end.min(limit));
+            if keep_start >= keep_end {
+                if self.end_granule.is_some_and(|limit| start >= limit) {
+                    return false;
+                }
+                continue;
+            }
+
+            let from = (keep_start - start) as usize * channels;
+            let to = (keep_end - start) as usize * channels;
+
+            self.buffer.clear();
+            self.buffer.extend_from_slice(&self.decode_buffer[from..to]);
+            self.buffer_pos = 0;
+            self.buffer_start = keep_start;
+
+            return true;
+        }
+
+    }
+
+    //先頭に戻ってヘッダーを読み直し、デコーダーの状態も作り直す
+    fn rewind(&mut self) -> Result<(), FerriaError> {
+
+        self.reader.seek_bytes(SeekFrom::Start(0))?;
+        read_headers(&mut self.reader)?;
+        self.codec = new_codec(self.head)?;
+        self.decoded_frames = 0;
+
+        Ok(())
+
+    }
+
+    //現在のグラニュール位置(pre-skip込み)
+    fn current_granule(&self) -> u64 {
+        if self.buffer_pos < self.buffer.len() {
+            self.buffer_start + (self.buffer_pos / self.head.channels as usize) as u64
+        } else {
+            self.decoded_frames.max(self.skip_to)
+        }
+    }
+
+}
+
+impl<R: Read + Seek> Iterator for OpusDecoder<R> {
+
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+
+        if self.buffer_pos >= self.buffer.len() && !self.fill_buffer() {
+            return None;
+        }
+
+        let sample = self.buffer[self.buffer_pos];
+        self.buffer_pos += 1;
+
+        Some(sample)
+
+    }
+
+}
+
+impl<R: Read + Seek> Source for OpusDecoder<R> {
+
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.head.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OPUS_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = self.end_granule?.saturating_sub(self.head.pre_skip as u64);
+        Some(frames_to_duration(frames))
+    }
+
+    //前方へはデコードしながら読み飛ばし、後方へは先頭から読み直す
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+
+        let target = self.head.pre_skip as u64 + (pos.as_nanos() * OPUS_SAMPLE_RATE as u128 / 1_000_000_000) as u64;
+        let channels = self.head.channels as usize;
+
+        if target < self.current_granule() {
+            self.rewind().map_err(|e| SeekError::Other(Box::new(e)))?;
+            self.buffer.clear();
+        }
+        else if target < self.buffer_start + (self.buffer.len() / channels) as u64 {
+            //今のbufferの中ならその位置まで進めるだけでよい
+            self.buffer_pos = (target - self.buffer_start) as usize * channels;
+            return Ok(());
+        }
+        else {
+            self.buffer.clear();
+        }
+
+        self.buffer_pos = 0;
+        self.skip_to = target;
+
+        Ok(())
+
+    }
+
+}
+
+//OpusHeadを読み、続くOpusTagsは読み飛ばす
+fn read_headers<R: Read + Seek>(reader: &mut PacketReader<R>) -> Result<OpusHead, FerriaError> {
+
+    let head = reader.read_packet_expected().map_err(|e| opus_error(&e.to_string()))?;
+    let head = OpusHead::parse(&head.data)?;
+
+    let tags = reader.read_packet_expected().map_err(|e| opus_error(&e.to_string()))?;
+    if !tags.data.starts_with(b"OpusTags") {
+        return Err(opus_error("OpusTags packet not found"));
+    }
+
+    Ok(head)
+
+}
+
+fn new_codec(head: OpusHead) -> Result<OpusCodec, FerriaError> {
+
+    let channels = if head.channels == 1 { Channels::Mono } else { Channels::Stereo };
+
+    let codec = OpusCodec::new(SampleRate::Hz48000, channels)
+    .map_err(|e| opus_error(&e.to_string()))?;
+
+    codec.set_gain(head.output_gain as i32)
+    .map_err(|e| opus_error(&e.to_string()))?;
+
+    Ok(codec)
+
+}
+
+//ファイル末尾のページからグラニュール位置を読む。見つからなければNone
+pub fn last_granule<R: Read + Seek>(reader: &mut R) -> Result<Option<u64>, FerriaError> {
+
+    let length = reader.seek(SeekFrom::End(0))?;
+    let start = length.saturating_sub(TAIL_LENGTH);
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut tail = Vec::new();
+    reader.take(TAIL_LENGTH).read_to_end(&mut tail)?;
+
+    //後ろから"OggS"を探し、パケットが終わらないページ(グラニュール位置が-1)は飛ばす
+    let granule = (0..tail.len().saturating_sub(14)).rev()
+    .filter(|&i| tail[i..].starts_with(b"OggS"))
+    .map(|i| u64::from_le_bytes(tail[i + 6..i + 14].try_into().unwrap()))
+    .find(|&granule| granule != u64::MAX);
+
+    Ok(granule)
+
+}
+
+fn frames_to_duration(frames: u64) -> Duration {
+    let rate = OPUS_SAMPLE_RATE as u64;
+    Duration::from_secs(frames / rate) + Duration::from_nanos((frames % rate) * 1_000_000_000 / rate)
+}
+
+fn opus_error(message: &str) -> FerriaError {
+    FerriaError::AudioError(format!("Failed to decode Opus audio: {}", message))
+}
+
+#[cfg(test)]
+mod test_opus {
+
+    use super::*;
+    use std::io::Cursor;
+
+    fn opus_head(channels: u8, pre_skip: u16, gain: i16, mapping_family: u8) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&44100u32.to_le_bytes());
+        head.extend_from_slice(&gain.to_le_bytes());
+        head.push(mapping_family);
+        head
+    }
+
+    fn ogg_page(granule: u64, body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0, 0]);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.push(1);
+        page.push(body.len() as u8);
+        page.extend_from_slice(body);
+        page
+    }
+
+    #[test]
+    fn test_parse_opus_head() {
+        let head = OpusHead::parse(&opus_head(2, 312, -256, 0)).unwrap();
+        assert_eq!(head, OpusHead { channels: 2, pre_skip: 312, input_sample_rate: 44100, output_gain: -256 });
+
+        assert!(OpusHead::parse(&opus_head(6, 312, 0, 1)).is_err());
+        assert!(OpusHead::parse(&opus_head(0, 312, 0, 0)).is_err());
+        assert!(OpusHead::parse(b"OpusTags").is_err());
+    }
+
+    #[test]
+    fn test_last_granule_skips_unfinished_pages() {
+        let mut data = ogg_page(0, b"head");
+        data.extend(ogg_page(48312, b"audio"));
+        data.extend(ogg_page(u64::MAX, b"continued"));
+        assert_eq!(last_granule(&mut Cursor::new(data)).unwrap(), Some(48312));
+
+        assert_eq!(last_granule(&mut Cursor::new(b"not ogg".to_vec())).unwrap(), None);
+        assert_eq!(frames_to_duration(48000 + 24000), Duration::from_millis(1500));
+    }
+
+}
@@ -0,0 +1,268 @@
+use rodio::{OutputStream, Sink};
+use rodio::queue::SourcesQueueOutput;
+use rodio::source::UniformSourceIterator;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::FerriaError;
+
+//デバイスを使わない出力のフォーマット(トラックのフォーマットはここに変換される)
+pub const HEADLESS_SAMPLE_RATE: u32 = 44100;
+pub const HEADLESS_CHANNELS: u16 = 2;
+
+//出力スレッドが1回に取り出す長さ
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+//音の出力先
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputBackend {
+    //既定のサウンドデバイス
+    Device,
+    //サンプルを捨てる。realtimeがfalseなら再生中は実時間を待たずに取り出す
+    Null { realtime: bool },
+    //16bit PCMのWAVファイルに書き出す
+    Wav { path: PathBuf, realtime: bool },
+}
+
+//Sinkと、その先でサンプルを取り出す出力(デバイスか出力スレッド)
+pub struct AudioOutput {
+    sink: Arc<Sink>,
+    _stream: Option<OutputStream>,
+    drain: Option<DrainThread>,
+}
+
+struct DrainThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<(), FerriaError>>,
+}
+
+impl AudioOutput {
+
+    pub fn open(backend: &OutputBackend) -> Result<Self, FerriaError> {
+
+        match backend {
+            OutputBackend::Device => {
+
+                let (stream, stream_handle) = OutputStream::try_default()
+                .map_err(|e| FerriaError::AudioError(format!("Failed to get audio stream: {}", e)))?;
+
+                let sink = Sink::try_new(&stream_handle)
+                .map_err(|e| FerriaError::AudioError(format!("Failed to create audio sink: {}", e)))?;
+
+                Ok(AudioOutput { sink: Arc::new(sink), _stream: Some(stream), drain: None })
+
+            },
+            OutputBackend::Null { realtime } => Self::headless(None, *realtime),
+            OutputBackend::Wav { path, realtime } => {
+                let writer = WavWriter::create(path, HEADLESS_SAMPLE_RATE, HEADLESS_CHANNELS)?;
+                Self::headless(Some(writer), *realtime)
+            },
+        }
+
+    }
+
+    pub fn sink(&self) -> &Sink {
+        &self.sink
+    }
+
+    //デバイスの代わりに出力スレッドでSinkからサンプルを取り出す
+    fn headless(writer: Option<WavWriter>, realtime: bool) -> Result<Self, FerriaError> {
+
+        let (sink, queue_output) = Sink::new_idle();
+        let sink = Arc::new(sink);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = thread::Builder::new()
+        .name("ferria-output".to_string())
+        .spawn({
+            let sink = sink.clone();
+            let stop = stop.clone();
+            move || drain(sink, queue_output, writer, realtime, stop)
+        })?;
+
+        Ok(AudioOutput { sink, _stream: None, drain: Some(DrainThread { stop, handle }) })
+
+    }
+
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+
+        let Some(drain) = self.drain.take() else { return };
+
+        drain.stop.store(true, Ordering::Release);
+
+        match drain.handle.join() {
+            Ok(Err(e)) => eprintln!("Failed to finish audio output: {}", e),
+            Err(_) => eprintln!("Audio output thread panicked"),
+            Ok(Ok(())) => {},
+        }
+
+    }
+}
+
+//サウンドデバイスと同じように、再生していない間も無音を実時間で取り出し続ける
+//(Sinkの一時停止やシークの指示はサンプルを取り出す側で処理されるため)
+fn drain(sink: Arc<Sink>, queue_output: SourcesQueueOutput<f32>, mut writer: Option<WavWriter>, realtime: bool, stop: Arc<AtomicBool>) -> Result<(), FerriaError> {
+
+    let mut output = UniformSourceIterator::<_, f32>::new(queue_output, HEADLESS_CHANNELS, HEADLESS_SAMPLE_RATE);
+
+    let chunk_samples = (HEADLESS_SAMPLE_RATE as u128 * DRAIN_INTERVAL.as_millis() / 1000) as usize * HEADLESS_CHANNELS as usize;
+    let mut chunk = Vec::with_capacity(chunk_samples);
+    let mut next_tick = Instant::now();
+
+    while !stop.load(Ordering::Acquire) {
+
+        let idle = sink.empty() || sink.is_paused();
+
+        chunk.clear();
+        chunk.extend(output.by_ref().take(chunk_samples));
+
+        if !idle && let Some(writer) = writer.as_mut() {
+            writer.write_samples(&chunk)?;
+        }
+
+        if realtime || idle {
+            next_tick += DRAIN_INTERVAL;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                //遅れた分は取り戻さない
+                next_tick = now;
+            }
+        } else {
+            next_tick = Instant::now();
+        }
+    }
+
+    match writer {
+        Some(writer) => writer.finish(),
+        None => Ok(()),
+    }
+
+}
+
+//16bit PCMのWAVファイルを書き出す。サイズはfinishでヘッダーに書き込む
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<Self, FerriaError> {
+
+        let mut file = BufWriter::new(File::create(path.as_ref())?);
+        file.write_all(&wav_header(sample_rate, channels, 0))?;
+
+        Ok(WavWriter { file, data_bytes: 0 })
+
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), FerriaError> {
+
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+
+        self.data_bytes = self.data_bytes.saturating_add(samples.len() as u32 * 2);
+
+        Ok(())
+
+    }
+
+    pub fn finish(mut self) -> Result<(), FerriaError> {
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()?;
+
+        Ok(())
+
+    }
+
+}
+
+//RIFFヘッダー + fmtチャンク + dataチャンクのヘッダー(44バイト)
+fn wav_header(sample_rate: u32, channels: u16, data_bytes: u32) -> Vec<u8> {
+
+    let block_align = channels * 2;
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_bytes).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());//PCM
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_bytes.to_le_bytes());
+
+    header
+
+}
+
+#[cfg(test)]
+mod test_output {
+
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn wait_until_empty(sink: &Sink) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !sink.empty() {
+            assert!(Instant::now() < deadline, "output did not drain the sink");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_null_output_drains_faster_than_realtime() {
+        let output = AudioOutput::open(&OutputBackend::Null { realtime: false }).unwrap();
+
+        //60秒分
+        let started = Instant::now();
+        output.sink().append(SamplesBuffer::new(1, 8000, vec![0.1f32; 8000 * 60]));
+        wait_until_empty(output.sink());
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_wav_output_writes_played_samples() {
+        let path = std::env::temp_dir().join(format!("ferria_output_{}.wav", std::process::id()));
+
+        let output = AudioOutput::open(&OutputBackend::Wav { path: path.clone(), realtime: false }).unwrap();
+        output.sink().set_volume(0.5);
+        output.sink().append(SamplesBuffer::new(HEADLESS_CHANNELS, HEADLESS_SAMPLE_RATE, vec![0.5f32; 4410 * 2]));
+        wait_until_empty(output.sink());
+        drop(output);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize, data.len() - 8);
+
+        //音量が掛かったサンプルが書かれている(無音の間は書かない)
+        let samples: Vec<i16> = data[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert!(samples.len() >= 4410 * 2);
+        assert!(samples.iter().filter(|&&s| s != 0).all(|&s| (s - i16::MAX / 4).abs() <= 1));
+        assert!(samples.iter().filter(|&&s| s != 0).count() >= 4410 * 2 - 2 * HEADLESS_CHANNELS as usize);
+    }
+
+}
@@ -1,13 +1,19 @@
 
 
-use rodio::{OutputStream, Sample, Sink, Source};
+use rodio::{Sample, Sink, Source};
+use rodio::source::SeekError;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::error::FerriaError;
 use crate::audio::loader::{AudioTrack, AudioTrackMetaData};
+use crate::audio::queue::{QueueHandle, TrackQueue};
+use crate::audio::analyzer::SampleBlock;
+use crate::audio::output::{AudioOutput, OutputBackend};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlaybackStatus {
     Stopped,
     Playing,
@@ -16,23 +22,133 @@ pub enum PlaybackStatus {
 
 const VOLUME_MAX: f32 = 1.0;
 const VOLUME_MIN: f32 = 0.0;
-const VOLUME_CHANGE_STEP: f32 = 0.1;
+pub const DEFAULT_VOLUME_STEP: f32 = 0.1;
+
+//プレイヤーの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerConfig {
+    //開始時の音量(0.0〜1.0)
+    pub volume: f32,
+    //音量を上げ下げするときの1回の変化量
+    pub volume_step: f32,
+    pub crossfade: Duration,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        PlayerConfig { volume: VOLUME_MAX, volume_step: DEFAULT_VOLUME_STEP, crossfade: Duration::ZERO }
+    }
+}
+
+//出力側に実際に消費されたサンプル数から再生位置を求めるカウンタ
+//TrackQueueとAudioPlayerで共有する
+#[derive(Debug, Clone, Default)]
+pub struct PositionCounter {
+    inner: Arc<PositionCounterInner>,
+}
+
+#[derive(Debug, Default)]
+struct PositionCounterInner {
+    samples: AtomicU64,
+    sample_rate: AtomicU32,
+    channels: AtomicU16,
+}
+
+impl PositionCounter {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn position(&self) -> Duration {
+
+        let sample_rate = self.inner.sample_rate.load(Ordering::Relaxed) as u64;
+        let channels = self.inner.channels.load(Ordering::Relaxed) as u64;
+
+        if sample_rate == 0 || channels == 0 {
+            return Duration::ZERO;
+        }
+
+        let frames = self.inner.samples.load(Ordering::Relaxed) / channels;
+
+        Duration::from_secs(frames / sample_rate) + Duration::from_nanos((frames % sample_rate) * 1_000_000_000 / sample_rate)
+
+    }
+
+    pub(crate) fn set_format(&self, sample_rate: u32, channels: u16) {
+        self.inner.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.inner.channels.store(channels, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_position(&self, pos: Duration) {
+
+        let sample_rate = self.inner.sample_rate.load(Ordering::Relaxed) as u128;
+        let channels = self.inner.channels.load(Ordering::Relaxed) as u64;
+
+        let frames = (pos.as_nanos() * sample_rate / 1_000_000_000) as u64;
+
+        self.inner.samples.store(frames * channels, Ordering::Relaxed);
+
+    }
+
+    pub(crate) fn advance(&self) {
+        self.inner.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.inner.samples.store(0, Ordering::Relaxed);
+    }
+
+}
+
+//解析スレッドにまとめて送るフレーム数
+const FORWARD_BLOCK_FRAMES: usize = 512;
 
 //rodio::Sourceのサンプルを別のチャネルに転送するためのラッパー
+//サンプルはSampleBlockにまとめ、サンプルレートとチャンネル数と一緒に送る
 pub struct SampleForwarder<T>
 where T: Source + Send + 'static,
       T::Item: rodio::Sample,
 {
     inner: T,
-    sender: mpsc::Sender<f32>,
+    sender: mpsc::Sender<SampleBlock>,
+    buffer: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
 }
 
 impl<T> SampleForwarder<T>
 where T: Source + Send + 'static,
       T::Item: rodio::Sample, {
 
-    pub fn new(inner: T, sender: mpsc::Sender<f32>) -> Self {
-        SampleForwarder { inner, sender }
+    pub fn new(inner: T, sender: mpsc::Sender<SampleBlock>) -> Self {
+
+        let sample_rate = inner.sample_rate();
+        let channels = inner.channels();
+
+        SampleForwarder {
+            inner,
+            sender,
+            buffer: Vec::with_capacity(FORWARD_BLOCK_FRAMES * channels as usize),
+            sample_rate,
+            channels,
+        }
+    }
+
+    fn flush(&mut self) {
+
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let block = SampleBlock {
+            samples: std::mem::replace(&mut self.buffer, Vec::with_capacity(FORWARD_BLOCK_FRAMES * self.channels as usize)),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+        };
+
+        let _ = self.sender.send(block);
+
     }
     
 }
@@ -46,10 +162,24 @@ where T: Source + Send + 'static,
 
     fn next(&mut self) -> Option<Self::Item> {
 
+        //フレームの区切りでフォーマットの変化を確認する
+        if self.buffer.len().is_multiple_of(self.channels.max(1) as usize)
+            && (self.inner.sample_rate() != self.sample_rate || self.inner.channels() != self.channels) {
+            self.flush();
+            self.sample_rate = self.inner.sample_rate();
+            self.channels = self.inner.channels();
+        }
+
         let sample = self.inner.next();
 
-        if let Some(s) = sample {
-            let _ = self.sender.send(s.to_f32());
+        match sample {
+            Some(s) => {
+                self.buffer.push(s.to_f32());
+                if self.buffer.len() >= FORWARD_BLOCK_FRAMES * self.channels as usize {
+                    self.flush();
+                }
+            },
+            None => self.flush(),
         }
 
         sample
@@ -78,6 +208,10 @@ where T: Source + Send + 'static,
         self.inner.total_duration()
     }
 
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+
     //next()はIteratorの実装で提供されるから不要
 
 }
@@ -85,75 +219,126 @@ where T: Source + Send + 'static,
 
 
 pub struct AudioPlayer {
-    _stream: OutputStream,
-    sink: Sink,
+    output: AudioOutput,
     status: Arc<Mutex<PlaybackStatus>>,
-    current_file_path: Arc<Mutex<Option<PathBuf>>>,
-    current_meta_data: Arc<Mutex<Option<AudioTrackMetaData>>>,
+    //再生中のTrackQueueのハンドル。トラック情報や再生位置はここから取得する
+    queue: Mutex<Option<QueueHandle>>,
+    crossfade: Mutex<Duration>,
+    volume_step: Mutex<f32>,
 }
 
 impl AudioPlayer {
 
+    //既定のサウンドデバイスに出力する
     pub fn new() -> Result<Self, FerriaError> {
+        Self::with_output(&OutputBackend::Device)
+    }
 
-        let (_stream, stream_handle)  = OutputStream::try_default()
-        .map_err(|e| FerriaError::AudioError(format!("Failed to get audio stream: {}", e)))?;
+    pub fn with_output(backend: &OutputBackend) -> Result<Self, FerriaError> {
 
-        let sink = Sink::try_new(&stream_handle)
-        .map_err(|e| FerriaError::AudioError(format!("Failed to create audio sink: {}", e)))?;
+        let output = AudioOutput::open(backend)?;
 
         Ok(AudioPlayer {
-            _stream,
-            sink: sink,
+            output,
             status: Arc::new(Mutex::new(PlaybackStatus::Stopped)),
-            current_file_path: Arc::new(Mutex::new(None)), 
-            current_meta_data: Arc::new(Mutex::new(None)),
+            queue: Mutex::new(None),
+            crossfade: Mutex::new(Duration::ZERO),
+            volume_step: Mutex::new(DEFAULT_VOLUME_STEP),
         })
         
     }
 
-    pub fn play(&self, audio_track: AudioTrack, analyzer_sender: Option<mpsc::Sender<f32>>) -> Result<(), FerriaError> {
+    pub fn configure(&self, config: &PlayerConfig) {
+        self.set_volume(config.volume);
+        *self.volume_step.lock().unwrap() = config.volume_step;
+        self.set_crossfade(config.crossfade);
+    }
+
+    pub fn play(&self, audio_track: AudioTrack, analyzer_sender: Option<mpsc::Sender<SampleBlock>>) -> Result<(), FerriaError> {
 
         //再生中あったら停止
-        self.sink.stop();
+        self.sink().stop();
         //キューもクリア
-        self.sink.clear();
+        self.sink().clear();
 
-        
-        //TODO: スキップ、シークは今後実装予定
-
-        let decoder = audio_track.decoder;
+        //以降のトラックはenqueueでこのキューに追加して途切れなく再生する
+        let (track_queue, handle) = TrackQueue::new(audio_track);
+        handle.set_crossfade(*self.crossfade.lock().unwrap());
 
         if let Some(sender) = analyzer_sender {
 
-            let forwarder = SampleForwarder::new(decoder, sender);
+            let forwarder = SampleForwarder::new(track_queue, sender);
 
-            self.sink.append(forwarder);
+            self.sink().append(forwarder);
 
         }
         else {
 
-            self.sink.append(decoder);
+            self.sink().append(track_queue);
 
         }
 
-        self.sink.play();
+        self.sink().play();
 
         *self.status.lock().unwrap() = PlaybackStatus::Playing;
 
-        *self.current_file_path.lock().unwrap() = Some(PathBuf::from(audio_track.metadata.title.clone()));
-
-        *self.current_meta_data.lock().unwrap() = Some(audio_track.metadata);
+        *self.queue.lock().unwrap() = Some(handle);
 
         Ok(())
 
     }
 
+    //再生中のトラックの後に続けて再生するトラックを先読みして追加する
+    //フォーマットが再生中のキューと異なればOk(false)を返す。そのトラックは今のトラックが終わってからplayで再生する
+    pub fn enqueue(&self, audio_track: AudioTrack) -> Result<bool, FerriaError> {
+
+        if self.get_status() == PlaybackStatus::Stopped || self.sink().empty() {
+            return Err(FerriaError::AudioError("Cannot enqueue a track while nothing is playing".to_string()));
+        }
+
+        match self.queue.lock().unwrap().as_ref() {
+            Some(handle) => Ok(handle.enqueue(audio_track)),
+            None => Err(FerriaError::AudioError("Cannot enqueue a track while nothing is playing".to_string())),
+        }
+
+    }
+
+    //先読み済みで再生待ちのトラックがあるか
+    pub fn has_queued_track(&self) -> bool {
+        self.queue.lock().unwrap().as_ref().is_some_and(|h| h.has_pending())
+    }
+
+    //先読み済みのトラックを破棄する(プレイリストの順序が変わったときなど)
+    pub fn clear_queued_tracks(&self) {
+        if let Some(handle) = self.queue.lock().unwrap().as_ref() {
+            handle.clear_pending();
+        }
+    }
+
+    //前回の呼び出しから先読みしたトラックへ切り替わった回数
+    pub fn take_track_changes(&self) -> u64 {
+        self.queue.lock().unwrap().as_ref().map_or(0, |h| h.take_track_changes())
+    }
+
+    //トラック間のクロスフェードの長さ。ゼロならクロスフェードせずにギャップレスで繋ぐ
+    pub fn set_crossfade(&self, crossfade: Duration) {
+
+        *self.crossfade.lock().unwrap() = crossfade;
+
+        if let Some(handle) = self.queue.lock().unwrap().as_ref() {
+            handle.set_crossfade(crossfade);
+        }
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        *self.crossfade.lock().unwrap()
+    }
+
     pub fn pause(&self) {
 
         if *self.status.lock().unwrap() == PlaybackStatus::Playing {
 
-            self.sink.pause();
+            self.sink().pause();
 
             *self.status.lock().unwrap() = PlaybackStatus::Paused;
 
@@ -165,7 +350,7 @@ impl AudioPlayer {
 
         if *self.status.lock().unwrap() == PlaybackStatus::Paused {
 
-            self.sink.play();
+            self.sink().play();
 
             *self.status.lock().unwrap() = PlaybackStatus::Playing;
         }
@@ -173,75 +358,171 @@ impl AudioPlayer {
 
     pub fn stop(&self) {
 
-        self.sink.stop();
+        self.sink().stop();
 
-        self.sink.clear();
+        self.sink().clear();
 
         *self.status.lock().unwrap() = PlaybackStatus::Stopped;
 
-        *self.current_file_path.lock().unwrap() = None;
+        *self.queue.lock().unwrap() = None;
+
+    }
+
+    //現在のトラックの再生位置
+    pub fn position(&self) -> Duration {
+
+        self.queue.lock().unwrap().as_ref().map_or(Duration::ZERO, |h| h.position())
+    }
+
+    //現在のトラックの残り時間(トラックの長さが分からない場合はNone)
+    pub fn remaining(&self) -> Option<Duration> {
+        let duration = self.get_current_metadata()?.duration?;
+        Some(duration.saturating_sub(self.position()))
+    }
+
+    //トラックの先頭からの位置を指定してシークする
+    //トラックの長さが分かる場合はその範囲に収める
+    pub fn seek(&self, pos: Duration) -> Result<(), FerriaError> {
+
+        if self.get_status() == PlaybackStatus::Stopped {
+            return Ok(());
+        }
+
+        let pos = match self.get_current_metadata().and_then(|m| m.duration) {
+            Some(duration) => pos.min(duration),
+            None => pos,
+        };
+
+        self.sink().try_seek(pos)
+        .map_err(|e| FerriaError::AudioError(format!("Failed to seek: {}", e)))
 
-        *self.current_meta_data.lock().unwrap() = None;
+    }
+
+    pub fn seek_forward(&self, offset: Duration) -> Result<(), FerriaError> {
+        self.seek(self.position() + offset)
+    }
 
+    pub fn seek_backward(&self, offset: Duration) -> Result<(), FerriaError> {
+        self.seek(self.position().saturating_sub(offset))
     }
 
     pub fn volume(&self) -> f32 {
-        round_to_one_decimal_place(self.sink.volume())
+        round_volume(self.sink().volume())
     }
 
     pub fn volume_up(&self) {
-        let new_vol = (self.volume() + VOLUME_CHANGE_STEP).min(VOLUME_MAX);
-        self.sink.set_volume(new_vol);
+        let new_vol = (self.volume() + *self.volume_step.lock().unwrap()).min(VOLUME_MAX);
+        self.sink().set_volume(new_vol);
     }
 
     pub fn volume_down(&self) {
-        let new_vol = (self.volume() - VOLUME_CHANGE_STEP).max(VOLUME_MIN);
-        self.sink.set_volume(new_vol);
+        let new_vol = (self.volume() - *self.volume_step.lock().unwrap()).max(VOLUME_MIN);
+        self.sink().set_volume(new_vol);
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.sink().set_volume(volume.clamp(VOLUME_MIN, VOLUME_MAX));
     }
 
     pub fn get_status(&self) -> PlaybackStatus {
 
         let guard = self.status.lock().unwrap();
 
-        (*guard).clone()
+        *guard
+
+    }
 
+    //再生中のトラックが最後まで再生されたか
+    pub fn is_track_finished(&self) -> bool {
+        *self.status.lock().unwrap() == PlaybackStatus::Playing && self.sink().empty()
     }
 
     pub fn get_current_file_path(&self) -> Option<PathBuf> {
-        self.current_file_path.lock().unwrap().clone()
+        self.get_current_metadata().and_then(|m| m.path)
     }
 
     pub fn get_current_metadata(&self) -> Option<AudioTrackMetaData> {
-        self.current_meta_data.lock().unwrap().clone()
+        self.queue.lock().unwrap().as_ref().and_then(|h| h.current_metadata())
+    }
+
+    fn sink(&self) -> &Sink {
+        self.output.sink()
     }
 
 }
 
-fn round_to_one_decimal_place(value: f32) -> f32 {
-    (value *10.0).round() / 10.0
+//足し引きを繰り返しても誤差が溜まらないように、音量の最小の刻み(0.01)に丸める
+fn round_volume(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
-    
-    fn load_mp3() -> AudioTrack {
+    use crate::audio::output::WavWriter;
+    use std::time::Instant;
+
+    //8000Hzモノラルの正弦波のWAVを書き出して読み込む
+    fn load_tone(name: &str, seconds: f32) -> (AudioTrack, PathBuf) {
+
+        let path = std::env::temp_dir().join(format!("ferria_player_{}_{}.wav", name, std::process::id()));
+
+        let samples: Vec<f32> = (0..(8000.0 * seconds) as usize)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 8000.0).sin() * 0.5)
+        .collect();
+
+        let mut writer = WavWriter::create(&path, 8000, 1).unwrap();
+        writer.write_samples(&samples).unwrap();
+        writer.finish().unwrap();
+
+        (AudioTrack::new(&path).unwrap(), path)
 
-        let path = PathBuf::from("assets/eine.mp3");
+    }
 
-        if !path.exists() {
-            panic!("Test MP3 file not found: {:?}" ,path);
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_position_counter() {
+        let counter = PositionCounter::new();
+        counter.set_format(1000, 2);
+        for _ in 0..1000 {
+            counter.advance();
         }
+        assert_eq!(counter.position(), Duration::from_millis(500));
+        counter.set_position(Duration::from_millis(250));
+        assert_eq!(counter.position(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_sample_forwarder_sends_blocks_with_format() {
+
+        let (tx, rx) = mpsc::channel();
 
-        crate::audio::loader::AudioTrack::new(&path).unwrap()
+        let frames = FORWARD_BLOCK_FRAMES + 10;
+        let source = rodio::buffer::SamplesBuffer::new(2, 48000, vec![0.5f32; frames * 2]);
+        let forwarder = SampleForwarder::new(source, tx);
+
+        assert_eq!(forwarder.count(), frames * 2);
+
+        let blocks: Vec<SampleBlock> = rx.try_iter().collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].samples.len(), FORWARD_BLOCK_FRAMES * 2);
+        assert_eq!(blocks[1].samples.len(), 20);
+        assert!(blocks.iter().all(|b| b.sample_rate == 48000 && b.channels == 2));
 
     }
 
     #[test]
     fn test_audio_player_new() {
 
-        let player_result = AudioPlayer::new();
+        let player_result = AudioPlayer::with_output(&OutputBackend::Null { realtime: true });
         assert!(player_result.is_ok());
 
         let player = player_result.unwrap();
@@ -253,27 +534,84 @@ mod test {
     #[test]
     fn test_play_pause_resume_stop() {
 
-        let player = AudioPlayer::new().unwrap();
-        let audio_track = load_mp3();
+        let player = AudioPlayer::with_output(&OutputBackend::Null { realtime: true }).unwrap();
+        let (audio_track, path) = load_tone("state", 2.0);
 
         assert_eq!(player.get_status(), PlaybackStatus::Stopped);
 
         player.play(audio_track, None).unwrap();
         assert_eq!(player.get_status(), PlaybackStatus::Playing);
-        assert!(player.get_current_file_path().is_some());
+        assert_eq!(player.get_current_file_path(), Some(path.clone()));
         assert!(player.get_current_metadata().is_some());
 
         player.pause();
         assert_eq!(player.get_status(), PlaybackStatus::Paused);
 
+        //一時停止中は再生位置が進まない
+        let paused_at = player.position();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(player.position(), paused_at);
+
         player.resume();
         assert_eq!(player.get_status(), PlaybackStatus::Playing);
+        wait_until(|| player.position() > paused_at);
+
+        player.seek(Duration::from_secs(1)).unwrap();
+        assert!(player.position() >= Duration::from_secs(1));
 
         player.stop();
         assert_eq!(player.get_status(), PlaybackStatus::Stopped);
         assert!(player.get_current_file_path().is_none());
         assert!(player.get_current_metadata().is_none());
 
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn test_volume_steps_are_clamped() {
+
+        let player = AudioPlayer::with_output(&OutputBackend::Null { realtime: true }).unwrap();
+
+        player.set_volume(0.95);
+        player.volume_up();
+        assert_eq!(player.volume(), VOLUME_MAX);
+
+        player.set_volume(0.1);
+        player.volume_down();
+        player.volume_down();
+        assert_eq!(player.volume(), VOLUME_MIN);
+
+        player.set_volume(2.0);
+        assert_eq!(player.volume(), VOLUME_MAX);
+
+        //刻みを細かくしても足し引きで誤差が溜まらない
+        player.configure(&PlayerConfig { volume: 0.5, volume_step: 0.05, ..Default::default() });
+        for _ in 0..3 {
+            player.volume_up();
+        }
+        assert_eq!(player.volume(), 0.65);
+
     }
 
-} 
+    #[test]
+    fn test_track_plays_to_end_and_forwards_samples() {
+
+        let player = AudioPlayer::with_output(&OutputBackend::Null { realtime: false }).unwrap();
+        let (audio_track, path) = load_tone("forward", 1.0);
+
+        let (tx, rx) = mpsc::channel();
+        player.play(audio_track, Some(tx)).unwrap();
+
+        wait_until(|| player.is_track_finished());
+
+        //解析スレッドには変換前のトラックのフォーマットのまま全サンプルが届く
+        let blocks: Vec<SampleBlock> = rx.try_iter().collect();
+        assert_eq!(blocks.iter().map(|b| b.samples.len()).sum::<usize>(), 8000);
+        assert!(blocks.iter().all(|b| b.sample_rate == 8000 && b.channels == 1));
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+}
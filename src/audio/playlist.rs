@@ -0,0 +1,320 @@
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use std::path::{Path, PathBuf};
+
+//プレイリスト末尾に到達したときの挙動
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RepeatMode {
+    ///リピートしない
+    Off,
+    ///同じトラックを繰り返す
+    One,
+    ///プレイリスト全体を繰り返す
+    All,
+}
+
+impl RepeatMode {
+
+    pub fn cycle(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+
+}
+
+//再生するトラックの一覧と再生順を管理する
+//tracksは入力順のまま保持し、再生順はorder(tracksへのインデックス)で表す
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    tracks: Vec<PathBuf>,
+    order: Vec<usize>,
+    //シャッフル中に一周したときに使う次の再生順(先読みで次のトラックを決められるように先に作っておく)
+    next_order: Vec<usize>,
+    position: usize,
+    repeat: RepeatMode,
+    shuffle: bool,
+}
+
+impl Playlist {
+
+    pub fn new(tracks: Vec<PathBuf>) -> Self {
+        let order = (0..tracks.len()).collect();
+        Playlist {
+            tracks,
+            order,
+            next_order: Vec::new(),
+            position: 0,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub fn tracks(&self) -> &[PathBuf] {
+        &self.tracks
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    pub fn is_shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    //シャッフルを切り替える。現在のトラックはそのまま再生順の先頭に置く
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+
+        self.shuffle = shuffle;
+
+        let current = self.current_index();
+
+        if shuffle {
+            self.reshuffle(current);
+        }
+        else {
+            self.order = (0..self.tracks.len()).collect();
+            self.position = current.unwrap_or(0);
+        }
+
+    }
+
+    //再生を始める前にシャッフルを設定する。先頭のトラックも含めて全体を並べ替える
+    pub fn start_shuffled(&mut self, shuffle: bool) {
+
+        self.shuffle = shuffle;
+
+        if shuffle {
+            self.reshuffle(None);
+        }
+
+    }
+
+    //tracks上のインデックスで現在のトラックを返す
+    pub fn current_index(&self) -> Option<usize> {
+        self.order.get(self.position).copied()
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.current_index().map(|i| self.tracks[i].as_path())
+    }
+
+    //ユーザー操作による次のトラック。RepeatMode::Oneでも次へ進む
+    pub fn next_track(&mut self) -> Option<&Path> {
+
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+        }
+        else if self.repeat == RepeatMode::Off || self.order.is_empty() {
+            return None;
+        }
+        else {
+            self.wrap_around();
+        }
+
+        self.current()
+
+    }
+
+    //ユーザー操作による前のトラック。先頭ではRepeatMode::Allのときだけ末尾に戻る
+    pub fn previous_track(&mut self) -> Option<&Path> {
+
+        if self.position > 0 {
+            self.position -= 1;
+        }
+        else if self.repeat == RepeatMode::All && !self.order.is_empty() {
+            self.position = self.order.len() - 1;
+        }
+        else {
+            return None;
+        }
+
+        self.current()
+
+    }
+
+    //トラックの再生が終わったときに次に再生するトラックへ進める
+    pub fn advance_on_end(&mut self) -> Option<&Path> {
+
+        if self.repeat == RepeatMode::One {
+            return self.current();
+        }
+
+        self.next_track()
+
+    }
+
+    //advance_on_endで次に再生されるトラックを、状態を変えずに返す
+    pub fn peek_on_end(&self) -> Option<&Path> {
+
+        if self.repeat == RepeatMode::One {
+            return self.current();
+        }
+
+        match self.order.get(self.position + 1) {
+            Some(&i) => Some(self.tracks[i].as_path()),
+            None if self.repeat == RepeatMode::All => {
+                let order = if self.shuffle { &self.next_order } else { &self.order };
+                order.first().map(|&i| self.tracks[i].as_path())
+            },
+            None => None,
+        }
+
+    }
+
+    //tracks上のインデックスを指定して移動する
+    pub fn jump_to(&mut self, index: usize) -> Option<&Path> {
+
+        let position = self.order.iter().position(|&i| i == index)?;
+        self.position = position;
+
+        self.current()
+
+    }
+
+    fn wrap_around(&mut self) {
+
+        self.position = 0;
+
+        //一周したら先に作っておいたシャッフル順に切り替え、その次の周の順序を作る
+        if self.shuffle {
+            self.order = std::mem::take(&mut self.next_order);
+            self.next_order = self.shuffled_order(None);
+        }
+
+    }
+
+    fn reshuffle(&mut self, first: Option<usize>) {
+
+        self.order = self.shuffled_order(first);
+        self.next_order = self.shuffled_order(None);
+        self.position = 0;
+
+    }
+
+    //firstを先頭に置き、残りをランダムに並べた再生順
+    fn shuffled_order(&self, first: Option<usize>) -> Vec<usize> {
+
+        let mut rest: Vec<usize> = (0..self.tracks.len()).filter(|&i| Some(i) != first).collect();
+        rest.shuffle(&mut rand::thread_rng());
+
+        first.into_iter().chain(rest).collect()
+
+    }
+
+}
+
+#[cfg(test)]
+mod test_playlist {
+
+    use super::*;
+
+    fn playlist(n: usize) -> Playlist {
+        Playlist::new((0..n).map(|i| PathBuf::from(format!("{}.mp3", i))).collect())
+    }
+
+    #[test]
+    fn test_next_previous_without_repeat() {
+        let mut list = playlist(3);
+        assert_eq!(list.current(), Some(Path::new("0.mp3")));
+        assert_eq!(list.next_track(), Some(Path::new("1.mp3")));
+        assert_eq!(list.next_track(), Some(Path::new("2.mp3")));
+        assert_eq!(list.next_track(), None);
+        assert_eq!(list.current(), Some(Path::new("2.mp3")));
+        assert_eq!(list.previous_track(), Some(Path::new("1.mp3")));
+        assert_eq!(list.previous_track(), Some(Path::new("0.mp3")));
+        assert_eq!(list.previous_track(), None);
+    }
+
+    #[test]
+    fn test_repeat_modes_on_end() {
+        let mut list = playlist(2);
+
+        list.set_repeat(RepeatMode::One);
+        assert_eq!(list.peek_on_end(), Some(Path::new("0.mp3")));
+        assert_eq!(list.advance_on_end(), Some(Path::new("0.mp3")));
+        //ユーザー操作のnextはRepeatMode::Oneでも進む
+        assert_eq!(list.next_track(), Some(Path::new("1.mp3")));
+
+        list.set_repeat(RepeatMode::All);
+        assert_eq!(list.peek_on_end(), Some(Path::new("0.mp3")));
+        assert_eq!(list.advance_on_end(), Some(Path::new("0.mp3")));
+        assert_eq!(list.previous_track(), Some(Path::new("1.mp3")));
+
+        list.set_repeat(RepeatMode::Off);
+        assert_eq!(list.peek_on_end(), None);
+        assert_eq!(list.advance_on_end(), None);
+    }
+
+    #[test]
+    fn test_shuffle_keeps_current_and_all_tracks() {
+        let mut list = playlist(10);
+        list.jump_to(4);
+        list.set_shuffle(true);
+        assert_eq!(list.current_index(), Some(4));
+
+        let mut seen = vec![list.current_index().unwrap()];
+        while list.next_track().is_some() {
+            seen.push(list.current_index().unwrap());
+        }
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+
+        let last = list.current_index();
+        list.set_shuffle(false);
+        assert_eq!(list.current_index(), last);
+    }
+
+    #[test]
+    fn test_shuffle_before_playback_does_not_pin_first_track() {
+        //20曲を何度か並べ替えれば、先頭が入力の1曲目以外になる回が必ずある
+        let first_tracks: Vec<_> = (0..20).map(|_| {
+            let mut list = playlist(20);
+            list.start_shuffled(true);
+            assert!(list.is_shuffle());
+            list.current_index()
+        })
+        .collect();
+
+        assert!(first_tracks.iter().any(|&i| i != Some(0)));
+    }
+
+    #[test]
+    fn test_peek_matches_advance_when_shuffle_wraps() {
+        let mut list = playlist(10);
+        list.start_shuffled(true);
+        list.set_repeat(RepeatMode::All);
+
+        //何周しても、先読みしたトラックと実際に進んだトラックが一致する
+        for _ in 0..30 {
+            let peeked = list.peek_on_end().map(Path::to_path_buf);
+            assert_eq!(list.advance_on_end().map(Path::to_path_buf), peeked);
+        }
+    }
+
+    #[test]
+    fn test_empty_playlist() {
+        let mut list = playlist(0);
+        assert!(list.is_empty());
+        assert_eq!(list.current(), None);
+        assert_eq!(list.next_track(), None);
+        list.set_repeat(RepeatMode::All);
+        assert_eq!(list.next_track(), None);
+        assert_eq!(list.previous_track(), None);
+    }
+
+}
@@ -0,0 +1,402 @@
+use rodio::source::SeekError;
+use rodio::{cpal::FromSample, Source};
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio::loader::{AudioTrack, AudioTrackMetaData};
+use crate::audio::player::PositionCounter;
+
+pub type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+//先読みされて再生待ちのトラック
+struct PendingTrack {
+    source: BoxedSource,
+    metadata: AudioTrackMetaData,
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: VecDeque<PendingTrack>,
+    current: Option<AudioTrackMetaData>,
+    //キューが次のトラックに切り替わった回数と、そのうちAudioPlayer側が確認済みの回数
+    track_changes: u64,
+    acknowledged_changes: u64,
+}
+
+//TrackQueueをAudioPlayerから操作するためのハンドル
+#[derive(Clone)]
+pub struct QueueHandle {
+    state: Arc<Mutex<QueueState>>,
+    has_pending: Arc<AtomicBool>,
+    crossfade_ms: Arc<AtomicU64>,
+    counter: PositionCounter,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl QueueHandle {
+
+    //再生待ちにトラックを追加する。サンプルレートかチャンネル数がキューと異なるトラックは追加せずにfalseを返す
+    //(変換するとチャンネルが落ちたり、デバイス側と二重にリサンプルされたりするので、新しいキューで再生し直す)
+    pub fn enqueue(&self, audio_track: AudioTrack) -> bool {
+        self.push_source(boxed_source(audio_track.decoder), audio_track.metadata)
+    }
+
+    fn push_source(&self, source: BoxedSource, metadata: AudioTrackMetaData) -> bool {
+
+        if source.channels() != self.channels || source.sample_rate() != self.sample_rate {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.pending.push_back(PendingTrack { source, metadata });
+
+        self.has_pending.store(true, Ordering::Release);
+
+        true
+
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.has_pending.load(Ordering::Acquire)
+    }
+
+    pub fn clear_pending(&self) {
+
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+
+        self.has_pending.store(false, Ordering::Release);
+
+    }
+
+    pub fn current_metadata(&self) -> Option<AudioTrackMetaData> {
+        self.state.lock().unwrap().current.clone()
+    }
+
+    //前回の呼び出しから次のトラックに切り替わった回数を返す
+    pub fn take_track_changes(&self) -> u64 {
+
+        let mut state = self.state.lock().unwrap();
+
+        let changes = state.track_changes - state.acknowledged_changes;
+        state.acknowledged_changes = state.track_changes;
+
+        changes
+    }
+
+    pub fn position(&self) -> Duration {
+        self.counter.position()
+    }
+
+    pub fn set_crossfade(&self, crossfade: Duration) {
+        self.crossfade_ms.store(crossfade.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn crossfade(&self) -> Duration {
+        Duration::from_millis(self.crossfade_ms.load(Ordering::Relaxed))
+    }
+
+}
+
+//クロスフェード中にフェードアウトしていく前のトラック
+struct FadingTrack {
+    source: BoxedSource,
+    total_samples: u64,
+    elapsed_samples: u64,
+}
+
+//複数のトラックを途切れなく連続で再生するSource
+//Sinkに1つだけ追加し、次のトラックはQueueHandle経由で後から追加する
+//crossfadeが設定されていれば、前のトラックの末尾と次のトラックの先頭を重ねて再生する
+pub struct TrackQueue {
+    current: Option<BoxedSource>,
+    current_duration: Option<Duration>,
+    fading: Option<FadingTrack>,
+    handle: QueueHandle,
+    samples_in_frame: u16,
+}
+
+impl TrackQueue {
+
+    //最初のトラックのフォーマットをキュー全体の出力フォーマットにする
+    pub fn new(audio_track: AudioTrack) -> (Self, QueueHandle) {
+        Self::with_source(boxed_source(audio_track.decoder), audio_track.metadata)
+    }
+
+    fn with_source(source: BoxedSource, metadata: AudioTrackMetaData) -> (Self, QueueHandle) {
+
+        let channels = source.channels();
+        let sample_rate = source.sample_rate();
+
+        let counter = PositionCounter::new();
+        counter.set_format(sample_rate, channels);
+
+        let handle = QueueHandle {
+            state: Arc::new(Mutex::new(QueueState {
+                current: Some(metadata.clone()),
+                ..Default::default()
+            })),
+            has_pending: Arc::new(AtomicBool::new(false)),
+            crossfade_ms: Arc::new(AtomicU64::new(0)),
+            counter,
+            channels,
+            sample_rate,
+        };
+
+        let queue = TrackQueue {
+            current: Some(source),
+            current_duration: metadata.duration,
+            fading: None,
+            handle: handle.clone(),
+            samples_in_frame: 0,
+        };
+
+        (queue, handle)
+
+    }
+
+    //再生待ちの先頭を現在のトラックにする
+    fn switch_to_pending(&mut self) -> Option<BoxedSource> {
+
+        let mut state = self.handle.state.lock().unwrap();
+
+        let next = state.pending.pop_front()?;
+
+        self.handle.has_pending.store(!state.pending.is_empty(), Ordering::Release);
+
+        self.current_duration = next.metadata.duration;
+        state.current = Some(next.metadata);
+        state.track_changes += 1;
+
+        self.handle.counter.reset();
+        self.samples_in_frame = 0;
+
+        self.current.replace(next.source)
+
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.current_duration.map(|d| d.saturating_sub(self.handle.counter.position()))
+    }
+
+    //現在のトラックの残りがクロスフェードの長さを切ったら次のトラックとのクロスフェードを始める
+    fn maybe_start_crossfade(&mut self) {
+
+        if self.fading.is_some() || !self.handle.has_pending() {
+            return;
+        }
+
+        let crossfade = self.handle.crossfade();
+        if crossfade.is_zero() {
+            return;
+        }
+
+        //長さが分からないトラックはクロスフェードせず、終わったところでギャップレスに切り替える
+        let Some(remaining) = self.remaining() else { return };
+        if remaining > crossfade {
+            return;
+        }
+
+        let channels = self.handle.channels as u64;
+        let total_frames = (remaining.as_nanos() * self.handle.sample_rate as u128 / 1_000_000_000) as u64;
+
+        if total_frames == 0 {
+            return;
+        }
+
+        if let Some(previous) = self.switch_to_pending() {
+            self.fading = Some(FadingTrack {
+                source: previous,
+                total_samples: total_frames * channels,
+                elapsed_samples: 0,
+            });
+        }
+
+    }
+
+}
+
+impl Iterator for TrackQueue {
+
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+
+        //フレームの途中でトラックを切り替えるとチャンネルがずれるので、フレーム境界でだけ判定する
+        if self.samples_in_frame == 0 {
+            self.maybe_start_crossfade();
+        }
+
+        let sample = loop {
+
+            match self.current.as_mut()?.next() {
+                Some(sample) => break sample,
+                None => {
+                    //次のトラックが無ければキューの再生を終える
+                    self.fading = None;
+                    if self.switch_to_pending().is_none() {
+                        self.current = None;
+                        return None;
+                    }
+                }
+            }
+        };
+
+        self.handle.counter.advance();
+        self.samples_in_frame = (self.samples_in_frame + 1) % self.handle.channels.max(1);
+
+        let Some(fading) = self.fading.as_mut() else {
+            return Some(sample);
+        };
+
+        //等パワーのクロスフェード
+        let progress = fading.elapsed_samples as f32 / fading.total_samples as f32;
+        let fading_sample = fading.source.next().unwrap_or(0.0);
+
+        fading.elapsed_samples += 1;
+        if fading.elapsed_samples >= fading.total_samples {
+            self.fading = None;
+        }
+
+        Some(sample * (progress * FRAC_PI_2).sin() + fading_sample * (progress * FRAC_PI_2).cos())
+
+    }
+
+}
+
+impl Source for TrackQueue {
+
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.handle.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.handle.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    //現在のトラック内でシークする。クロスフェード中なら前のトラックは打ち切る
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+
+        let Some(current) = self.current.as_mut() else { return Ok(()) };
+
+        current.try_seek(pos)?;
+
+        self.fading = None;
+        self.samples_in_frame = 0;
+        self.handle.counter.set_position(pos);
+
+        Ok(())
+    }
+
+}
+
+//デコーダのサンプルをf32に揃えてキューに入れられる形にする
+fn boxed_source<S>(source: S) -> BoxedSource
+where S: Source + Send + 'static,
+      S::Item: rodio::Sample + Send,
+      f32: FromSample<S::Item>,
+{
+    Box::new(source.convert_samples::<f32>())
+}
+
+#[cfg(test)]
+mod test_queue {
+
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn track(title: &str, channels: u16, sample_rate: u32, value: f32, frames: usize) -> (BoxedSource, AudioTrackMetaData) {
+        let source: BoxedSource = Box::new(SamplesBuffer::new(channels, sample_rate, vec![value; frames * channels as usize]));
+        let metadata = AudioTrackMetaData {
+            title: Some(title.to_string()),
+            duration: Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64)),
+            ..Default::default()
+        };
+        (source, metadata)
+    }
+
+    fn queue_with(first: (BoxedSource, AudioTrackMetaData)) -> (TrackQueue, QueueHandle) {
+        TrackQueue::with_source(first.0, first.1)
+    }
+
+    fn push(handle: &QueueHandle, next: (BoxedSource, AudioTrackMetaData)) -> bool {
+        handle.push_source(next.0, next.1)
+    }
+
+    #[test]
+    fn test_gapless_switch() {
+        let (mut queue, handle) = queue_with(track("a", 2, 1000, 0.25, 100));
+        assert!(push(&handle, track("b", 2, 1000, 0.5, 50)));
+
+        let samples: Vec<f32> = queue.by_ref().collect();
+
+        //間に無音を挟まずに次のトラックへ切り替わる
+        assert_eq!(samples.len(), 300);
+        assert!(samples[..200].iter().all(|&s| s == 0.25));
+        assert!(samples[200..].iter().all(|&s| s == 0.5));
+
+        assert_eq!(handle.take_track_changes(), 1);
+        assert_eq!(handle.take_track_changes(), 0);
+        assert_eq!(handle.current_metadata().unwrap().title.as_deref(), Some("b"));
+        assert_eq!(handle.position(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_crossfade_overlaps_tracks() {
+        let (mut queue, handle) = queue_with(track("a", 1, 1000, 1.0, 1000));
+        handle.set_crossfade(Duration::from_millis(200));
+        assert!(push(&handle, track("b", 1, 1000, 1.0, 1000)));
+
+        let samples: Vec<f32> = queue.by_ref().collect();
+
+        //200ms分が重なるので合計は1.8秒分になる
+        assert_eq!(samples.len(), 1800);
+        assert_eq!(handle.take_track_changes(), 1);
+
+        //等パワーなので重なっている区間の振幅は1.0を超え、√2以下に収まる
+        let overlap = &samples[800..1000];
+        assert!(overlap.iter().all(|&s| (1.0..=std::f32::consts::SQRT_2 + 0.001).contains(&s)));
+        assert!(samples[1000..].iter().all(|&s| s == 1.0));
+    }
+
+    #[test]
+    fn test_seek_resets_position() {
+        let (mut queue, handle) = queue_with(track("a", 2, 1000, 0.25, 1000));
+        for _ in 0..400 {
+            queue.next();
+        }
+        assert_eq!(handle.position(), Duration::from_millis(200));
+
+        queue.try_seek(Duration::from_millis(700)).unwrap();
+        assert_eq!(handle.position(), Duration::from_millis(700));
+        assert_eq!(queue.by_ref().count(), 600);
+    }
+
+    #[test]
+    fn test_different_format_is_not_queued() {
+        let (mut queue, handle) = queue_with(track("mono", 1, 1000, 0.25, 100));
+
+        //ステレオのトラックをモノラルのキューに変換して繋ぐと片方のチャンネルが落ちるので、受け付けない
+        assert!(!push(&handle, track("stereo", 2, 1000, 0.5, 100)));
+        assert!(!push(&handle, track("faster", 1, 2000, 0.5, 100)));
+        assert!(!handle.has_pending());
+
+        let samples: Vec<f32> = queue.by_ref().collect();
+        assert_eq!(samples.len(), 100);
+        assert_eq!(handle.take_track_changes(), 0);
+        assert_eq!(handle.current_metadata().unwrap().title.as_deref(), Some("mono"));
+    }
+
+}
@@ -0,0 +1,172 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+
+//デシベルに変換するときの下限(振幅0のときの値)
+pub const DB_FLOOR: f32 = -200.0;
+
+//スペクトルの値の表し方
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AmplitudeScale {
+    //振幅そのもの
+    #[default]
+    Linear,
+    //振幅の2乗
+    Power,
+    //20 log10(振幅 / reference)
+    Decibel { reference: f32 },
+}
+
+impl AmplitudeScale {
+
+    pub fn apply(&self, magnitude: f32) -> f32 {
+        match *self {
+            AmplitudeScale::Linear => magnitude,
+            AmplitudeScale::Power => magnitude * magnitude,
+            AmplitudeScale::Decibel { reference } => amplitude_to_db(magnitude / reference),
+        }
+    }
+
+    //applyの逆。描画するときは振幅に戻してから扱う
+    pub fn to_magnitude(&self, value: f32) -> f32 {
+        match *self {
+            AmplitudeScale::Linear => value,
+            AmplitudeScale::Power => value.max(0.0).sqrt(),
+            AmplitudeScale::Decibel { .. } if value <= DB_FLOOR => 0.0,
+            AmplitudeScale::Decibel { reference } => reference * 10f32.powf(value / 20.0),
+        }
+    }
+
+}
+
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(DB_FLOOR)
+    } else {
+        DB_FLOOR
+    }
+}
+
+impl fmt::Display for AmplitudeScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmplitudeScale::Linear => write!(f, "linear"),
+            AmplitudeScale::Power => write!(f, "power"),
+            AmplitudeScale::Decibel { reference } => write!(f, "db:{}", reference),
+        }
+    }
+}
+
+//"linear", "power", "db", "db:0.5" のような文字列から作る(dBの基準の既定値は1.0)
+impl FromStr for AmplitudeScale {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        match s.to_ascii_lowercase().split_once(':') {
+            None if s.eq_ignore_ascii_case("linear") => Ok(AmplitudeScale::Linear),
+            None if s.eq_ignore_ascii_case("power") => Ok(AmplitudeScale::Power),
+            None if s.eq_ignore_ascii_case("db") => Ok(AmplitudeScale::Decibel { reference: 1.0 }),
+            Some(("db", reference)) => reference.parse::<f32>().ok()
+            .filter(|r| r.is_finite() && *r > 0.0)
+            .map(|reference| AmplitudeScale::Decibel { reference })
+            .ok_or_else(|| format!("dB reference must be a positive number, got `{}`", reference)),
+            _ => Err(format!("Unknown scale `{}` (linear, power, db[:REFERENCE])", s)),
+        }
+
+    }
+
+}
+
+//人の聴感に合わせた周波数の重み付け(IEC 61672)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Weighting {
+    ///重み付けしない
+    #[default]
+    None,
+    ///A特性
+    A,
+    ///C特性
+    C,
+}
+
+impl Weighting {
+
+    //周波数ごとに振幅にかける係数。1kHzで1.0になる
+    pub fn gain(&self, frequency_hz: f32) -> f32 {
+        match self {
+            Weighting::None => 1.0,
+            Weighting::A => a_weighting_response(frequency_hz) / a_weighting_response(1000.0),
+            Weighting::C => c_weighting_response(frequency_hz) / c_weighting_response(1000.0),
+        }
+    }
+
+}
+
+const F1: f64 = 20.598997;
+const F2: f64 = 107.65265;
+const F3: f64 = 737.86223;
+const F4: f64 = 12194.217;
+
+fn a_weighting_response(frequency_hz: f32) -> f32 {
+    let f2 = (frequency_hz as f64).powi(2);
+    let response = F4 * F4 * f2 * f2
+        / ((f2 + F1 * F1) * ((f2 + F2 * F2) * (f2 + F3 * F3)).sqrt() * (f2 + F4 * F4));
+    response as f32
+}
+
+fn c_weighting_response(frequency_hz: f32) -> f32 {
+    let f2 = (frequency_hz as f64).powi(2);
+    let response = F4 * F4 * f2 / ((f2 + F1 * F1) * (f2 + F4 * F4));
+    response as f32
+}
+
+#[cfg(test)]
+mod test_scaling {
+
+    use super::*;
+
+    #[test]
+    fn test_amplitude_scale_round_trip() {
+        let scales = [
+            AmplitudeScale::Linear,
+            AmplitudeScale::Power,
+            AmplitudeScale::Decibel { reference: 1.0 },
+            AmplitudeScale::Decibel { reference: 0.5 },
+        ];
+
+        for scale in scales {
+            for magnitude in [0.001, 0.25, 1.0] {
+                assert!((scale.to_magnitude(scale.apply(magnitude)) - magnitude).abs() < 1e-5, "{}", scale);
+            }
+        }
+
+        assert_eq!(AmplitudeScale::Power.apply(0.5), 0.25);
+        assert!((AmplitudeScale::Decibel { reference: 1.0 }.apply(0.1) + 20.0).abs() < 1e-4);
+        assert_eq!(AmplitudeScale::Decibel { reference: 1.0 }.apply(0.0), DB_FLOOR);
+        assert_eq!(AmplitudeScale::Decibel { reference: 1.0 }.to_magnitude(DB_FLOOR), 0.0);
+    }
+
+    #[test]
+    fn test_parse_amplitude_scale() {
+        assert_eq!("power".parse(), Ok(AmplitudeScale::Power));
+        assert_eq!("dB".parse(), Ok(AmplitudeScale::Decibel { reference: 1.0 }));
+        assert_eq!("db:0.5".parse(), Ok(AmplitudeScale::Decibel { reference: 0.5 }));
+        assert!("db:0".parse::<AmplitudeScale>().is_err());
+        assert!("log".parse::<AmplitudeScale>().is_err());
+    }
+
+    #[test]
+    fn test_weighting_matches_standard_table() {
+        let db = |weighting: Weighting, hz: f32| amplitude_to_db(weighting.gain(hz));
+
+        assert!(db(Weighting::A, 1000.0).abs() < 0.01);
+        assert!((db(Weighting::A, 100.0) + 19.1).abs() < 0.1);
+        assert!((db(Weighting::A, 10000.0) + 2.5).abs() < 0.1);
+        assert!((db(Weighting::C, 100.0) + 0.3).abs() < 0.1);
+        assert!((db(Weighting::C, 31.5) + 3.0).abs() < 0.1);
+        assert_eq!(Weighting::None.gain(50.0), 1.0);
+    }
+
+}
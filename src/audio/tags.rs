@@ -0,0 +1,821 @@
+use id3::{Tag, TagLike};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use base64::Engine;
+
+use crate::audio::cover::{CoverArt, PICTURE_TYPE_FRONT_COVER};
+use crate::audio::format::AudioFormat;
+use crate::error::FerriaError;
+
+//MP4のmoovアトムとして読み込む最大サイズ(カバー画像込みでも収まる程度)
+const MAX_ATOM_SIZE: u64 = 64 * 1024 * 1024;
+
+//WAVのLIST、AIFFのNAME/AUTH/ANNOとして読み込む最大サイズ。これより大きいチャンクは壊れているとみなして読み飛ばす
+const MAX_TEXT_CHUNK_SIZE: u32 = 1024 * 1024;
+
+//各形式のタグから読み出した値
+//キーはVorbisコメントに合わせた大文字の名前(TITLE, ARTISTなど)に揃える
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawTags {
+    fields: Vec<(String, String)>,
+    pub cover: Option<CoverArt>,
+}
+
+impl RawTags {
+
+    pub fn push(&mut self, key: &str, value: &str) {
+
+        let value = value.trim_end_matches('\0').trim();
+
+        if !value.is_empty() {
+            self.fields.push((key.to_ascii_uppercase(), value.to_string()));
+        }
+
+    }
+
+    //同じキーが複数あるときは最初の値
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.as_str())
+    }
+
+    //画像が複数あるときは表紙を優先し、それ以外は最初のものを使う
+    pub fn push_cover(&mut self, cover: CoverArt) {
+
+        let replace = match &self.cover {
+            None => true,
+            Some(current) => cover.is_front_cover() && !current.is_front_cover(),
+        };
+
+        if replace {
+            self.cover = Some(cover);
+        }
+
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.cover.is_none()
+    }
+
+}
+
+//形式に応じたタグリーダーでタグを読む
+pub fn read_tags<P: AsRef<Path>>(path: P, format: AudioFormat) -> Result<RawTags, FerriaError> {
+
+    let path = path.as_ref();
+
+    match format {
+        AudioFormat::Mp3 | AudioFormat::Aac => Ok(read_id3(Tag::read_from_path(path).ok())),
+        AudioFormat::Aiff => {
+            //AIFFはID3チャンクを優先し、無ければNAME/AUTHチャンクを使う
+            let tags = read_id3(Tag::read_from_path(path).ok());
+            if tags.is_empty() { read_aiff_text_chunks(&mut open(path)?) } else { Ok(tags) }
+        },
+        AudioFormat::Wav => {
+            let tags = read_riff_info(&mut open(path)?)?;
+            if tags.is_empty() { Ok(read_id3(Tag::read_from_path(path).ok())) } else { Ok(tags) }
+        },
+        AudioFormat::Flac => read_flac_comments(&mut open(path)?),
+        AudioFormat::OggVorbis | AudioFormat::Opus => read_ogg_comments(&mut open(path)?),
+        AudioFormat::Mp4 => read_mp4_atoms(&mut open(path)?),
+    }
+
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, FerriaError> {
+    Ok(BufReader::new(File::open(path)?))
+}
+
+//ID3タグ(MP3, ADTS, AIFF/WAVのid3チャンク)
+//タグが無くてもエラーにはしない(cdからの吸い出し以外では無いことが多々ある)
+pub fn read_id3(tag: Option<Tag>) -> RawTags {
+
+    let mut tags = RawTags::default();
+
+    let Some(tag) = tag else { return tags };
+
+    let text_fields = [
+        ("TITLE", tag.title()),
+        ("ARTIST", tag.artist()),
+        ("ALBUM", tag.album()),
+        ("ALBUMARTIST", tag.album_artist()),
+        ("COMPOSER", tag.get("TCOM").and_then(|frame| frame.content().text())),
+    ];
+
+    for (key, value) in text_fields {
+        if let Some(value) = value {
+            tags.push(key, value);
+        }
+    }
+
+    //ID3v1のジャンル番号("(17)"など)は名前に直す
+    if let Some(genre) = tag.genre_parsed() {
+        tags.push("GENRE", &genre);
+    }
+
+    let number_fields = [
+        ("TRACKNUMBER", tag.track()),
+        ("TRACKTOTAL", tag.total_tracks()),
+        ("DISCNUMBER", tag.disc()),
+        ("DISCTOTAL", tag.total_discs()),
+    ];
+
+    for (key, value) in number_fields {
+        if let Some(value) = value {
+            tags.push(key, &value.to_string());
+        }
+    }
+
+    if let Some(year) = tag.year().or_else(|| tag.date_recorded().map(|date| date.year)) {
+        tags.push("DATE", &year.to_string());
+    }
+
+    if let Some(comment) = tag.comments().next() {
+        tags.push("COMMENT", &comment.text);
+    }
+
+    if let Some(lyrics) = tag.lyrics().next() {
+        tags.push("LYRICS", &lyrics.text);
+    }
+
+    //ReplayGainはTXXXフレームに"REPLAYGAIN_TRACK_GAIN"などの説明付きで入る
+    for text in tag.extended_texts() {
+        tags.push(&text.description, &text.value);
+    }
+
+    for picture in tag.pictures() {
+        tags.push_cover(CoverArt::new(&picture.mime_type, picture.picture_type.into(), picture.data.clone()));
+    }
+
+    tags
+
+}
+
+//Vorbisコメントの本体(ベンダー文字列 + "KEY=value"の列、長さはリトルエンディアン)
+pub fn parse_vorbis_comments(data: &[u8]) -> Result<RawTags, FerriaError> {
+
+    let mut tags = RawTags::default();
+    let mut cursor = ByteCursor::new(data);
+
+    let vendor_length = cursor.u32_le()? as usize;
+    cursor.skip(vendor_length)?;
+
+    let count = cursor.u32_le()?;
+
+    for _ in 0..count {
+
+        let length = cursor.u32_le()? as usize;
+        let comment = String::from_utf8_lossy(cursor.take(length)?);
+
+        let Some((key, value)) = comment.split_once('=') else { continue };
+
+        //Ogg Vorbis/Opusの画像はFLACのPICTUREブロックをbase64にしたもの
+        if key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE") {
+            if let Some(cover) = base64::engine::general_purpose::STANDARD.decode(value.trim()).ok().and_then(|block| parse_flac_picture(&block)) {
+                tags.push_cover(cover);
+            }
+            continue;
+        }
+
+        tags.push(key, value);
+    }
+
+    Ok(tags)
+
+}
+
+//FLACのPICTUREブロック(長さはビッグエンディアン)
+//画像種別, MIMEタイプ, 説明, 幅, 高さ, 色深度, パレット数, 画像データの順
+pub fn parse_flac_picture(block: &[u8]) -> Option<CoverArt> {
+
+    let mut cursor = ByteCursor::new(block);
+
+    let picture_type = cursor.u32_be().ok()?;
+    let mime_length = cursor.u32_be().ok()? as usize;
+    let mime_type = String::from_utf8_lossy(cursor.take(mime_length).ok()?).into_owned();
+    let description_length = cursor.u32_be().ok()? as usize;
+    cursor.skip(description_length + 16).ok()?;
+    let data_length = cursor.u32_be().ok()? as usize;
+    let data = cursor.take(data_length).ok()?;
+
+    Some(CoverArt::new(&mime_type, picture_type.min(u8::MAX as u32) as u8, data.to_vec()))
+
+}
+
+//FLACのメタデータブロックからVORBIS_COMMENTとPICTUREを探す
+pub fn read_flac_comments<R: Read + Seek>(reader: &mut R) -> Result<RawTags, FerriaError> {
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != b"fLaC" {
+        return Err(tag_error("FLAC stream marker not found"));
+    }
+
+    let mut tags = RawTags::default();
+    let mut covers = Vec::new();
+
+    loop {
+
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        match block_type {
+            //VORBIS_COMMENT
+            4 => {
+                let mut block = vec![0u8; length];
+                reader.read_exact(&mut block)?;
+                tags = parse_vorbis_comments(&block)?;
+            },
+            //PICTURE
+            6 => {
+                let mut block = vec![0u8; length];
+                reader.read_exact(&mut block)?;
+                covers.extend(parse_flac_picture(&block));
+            },
+            _ => {
+                reader.seek(SeekFrom::Current(length as i64))?;
+            },
+        }
+
+        if is_last {
+            break;
+        }
+    }
+
+    for cover in covers {
+        tags.push_cover(cover);
+    }
+
+    Ok(tags)
+
+}
+
+//Oggストリームの2番目のパケット(コメントヘッダー)を読む
+//Vorbisは"\x03vorbis"、Opusは"OpusTags"の後ろにVorbisコメントが続く
+pub fn read_ogg_comments<R: Read>(reader: &mut R) -> Result<RawTags, FerriaError> {
+
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+
+    //コメントヘッダーは複数ページにまたがることがあるので、2番目のパケットが完成するまで読む
+    while packets.len() < 3 {
+
+        let mut header = [0u8; 27];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..4] != b"OggS" {
+            return Err(tag_error("Ogg page marker not found"));
+        }
+
+        let mut lacing = vec![0u8; header[26] as usize];
+        reader.read_exact(&mut lacing)?;
+
+        for &size in &lacing {
+
+            let mut segment = vec![0u8; size as usize];
+            reader.read_exact(&mut segment)?;
+
+            if let Some(packet) = packets.last_mut() {
+                packet.extend_from_slice(&segment);
+            }
+
+            //255未満のセグメントでパケットが終わる
+            if size < 255 {
+                packets.push(Vec::new());
+            }
+        }
+    }
+
+    let comment_packet = &packets[1];
+
+    let body = comment_packet.strip_prefix(b"\x03vorbis")
+    .or_else(|| comment_packet.strip_prefix(b"OpusTags"))
+    .ok_or_else(|| tag_error("Ogg comment header not found"))?;
+
+    parse_vorbis_comments(body)
+
+}
+
+//WAVのLIST/INFOチャンク
+pub fn read_riff_info<R: Read + Seek>(reader: &mut R) -> Result<RawTags, FerriaError> {
+
+    let mut tags = RawTags::default();
+
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(tag_error("RIFF/WAVE header not found"));
+    }
+
+    while let Some((id, size)) = read_chunk_header(reader, u32::from_le_bytes)? {
+
+        if &id == b"LIST" && (4..=MAX_TEXT_CHUNK_SIZE).contains(&size) {
+
+            let mut chunk = vec![0u8; size as usize];
+            reader.read_exact(&mut chunk)?;
+
+            if chunk.starts_with(b"INFO") {
+                parse_info_list(&chunk[4..], &mut tags);
+            }
+        }
+        else {
+            reader.seek(SeekFrom::Current(size as i64))?;
+        }
+
+        //チャンクは2バイト境界に揃えられる
+        if size % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    Ok(tags)
+
+}
+
+fn parse_info_list(data: &[u8], tags: &mut RawTags) {
+
+    let mut cursor = ByteCursor::new(data);
+
+    while let (Ok(id), Ok(size)) = (cursor.take(4).map(<[u8]>::to_vec), cursor.u32_le()) {
+
+        let Ok(value) = cursor.take(size as usize) else { break };
+        let value = String::from_utf8_lossy(value);
+
+        if let Some(key) = riff_info_key(&id) {
+            tags.push(key, &value);
+        }
+
+        if size % 2 == 1 && cursor.skip(1).is_err() {
+            break;
+        }
+    }
+
+}
+
+fn riff_info_key(id: &[u8]) -> Option<&'static str> {
+    match id {
+        b"INAM" => Some("TITLE"),
+        b"IART" => Some("ARTIST"),
+        b"IPRD" => Some("ALBUM"),
+        b"ICRD" => Some("DATE"),
+        b"IGNR" => Some("GENRE"),
+        b"ICMT" => Some("COMMENT"),
+        b"IMUS" => Some("COMPOSER"),
+        b"IPRT" | b"ITRK" => Some("TRACKNUMBER"),
+        _ => None,
+    }
+}
+
+//AIFFのNAME/AUTHチャンク(サイズはビッグエンディアン)
+pub fn read_aiff_text_chunks<R: Read + Seek>(reader: &mut R) -> Result<RawTags, FerriaError> {
+
+    let mut tags = RawTags::default();
+
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != b"FORM" {
+        return Err(tag_error("AIFF FORM header not found"));
+    }
+
+    while let Some((id, size)) = read_chunk_header(reader, u32::from_be_bytes)? {
+
+        let key = match &id {
+            b"NAME" => Some("TITLE"),
+            b"AUTH" => Some("ARTIST"),
+            b"ANNO" => Some("COMMENT"),
+            _ => None,
+        };
+
+        if let Some(key) = key.filter(|_| size <= MAX_TEXT_CHUNK_SIZE) {
+            let mut value = vec![0u8; size as usize];
+            reader.read_exact(&mut value)?;
+            tags.push(key, &String::from_utf8_lossy(&value));
+        }
+        else {
+            reader.seek(SeekFrom::Current(size as i64))?;
+        }
+
+        if size % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    Ok(tags)
+
+}
+
+//チャンクID(4バイト)とサイズを読む。ファイルの終わりならNone
+fn read_chunk_header<R: Read>(reader: &mut R, to_u32: fn([u8; 4]) -> u32) -> Result<Option<([u8; 4], u32)>, FerriaError> {
+
+    let mut header = [0u8; 8];
+
+    match reader.read_exact(&mut header) {
+        Ok(()) => Ok(Some((
+            [header[0], header[1], header[2], header[3]],
+            to_u32([header[4], header[5], header[6], header[7]]),
+        ))),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+
+}
+
+//MP4のmoov/udta/meta/ilstアトムからiTunes形式のタグを読む
+pub fn read_mp4_atoms<R: Read + Seek>(reader: &mut R) -> Result<RawTags, FerriaError> {
+
+    let mut tags = RawTags::default();
+
+    //トップレベルのアトムからmoovを探す(ファイルの末尾にあることもある)
+    while let Some((kind, size)) = read_atom_header(reader)? {
+
+        if &kind != b"moov" {
+            reader.seek(SeekFrom::Current(size as i64))?;
+            continue;
+        }
+
+        if size > MAX_ATOM_SIZE {
+            return Err(tag_error("MP4 moov atom is too large"));
+        }
+
+        let mut moov = vec![0u8; size as usize];
+        reader.read_exact(&mut moov)?;
+
+        let ilst = find_atom(&moov, &[b"udta", b"meta", b"ilst"])
+        .or_else(|| find_atom(&moov, &[b"meta", b"ilst"]));
+
+        if let Some(ilst) = ilst {
+            for (kind, item) in atoms(ilst) {
+                read_mp4_item(&kind, item, &mut tags);
+            }
+        }
+
+        break;
+    }
+
+    Ok(tags)
+
+}
+
+//アトムのヘッダーを読み、(種類, 本体のサイズ)を返す
+fn read_atom_header<R: Read + Seek>(reader: &mut R) -> Result<Option<([u8; 4], u64)>, FerriaError> {
+
+    //RIFFのチャンクとは逆に、サイズ(4バイト)の後に種類(4バイト)が続く
+    let Some((size, kind)) = read_chunk_header(reader, u32::from_be_bytes)? else { return Ok(None) };
+    let size = u32::from_be_bytes(size);
+    let kind = kind.to_be_bytes();
+
+    let body = match size {
+        //サイズ1は64bitの拡張サイズが続く
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            u64::from_be_bytes(large).saturating_sub(16)
+        },
+        //サイズ0はファイルの終わりまで
+        0 => {
+            let position = reader.stream_position()?;
+            let end = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(position))?;
+            end - position
+        },
+        _ => (size as u64).saturating_sub(8),
+    };
+
+    Ok(Some((kind, body)))
+
+}
+
+//メモリ上のアトム列を(種類, 本体)で列挙する
+fn atoms(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+
+        let header = data.get(offset..offset + 8)?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        if size < 8 {
+            return None;
+        }
+
+        let body = data.get(offset + 8..offset + size)?;
+        offset += size;
+
+        Some((kind, body))
+    })
+
+}
+
+fn find_atom<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+
+    let (first, rest) = path.split_first()?;
+    let (_, body) = atoms(data).find(|(kind, _)| kind == *first)?;
+
+    //metaはバージョンとフラグの4バイトの後ろに子アトムが並ぶ
+    let body = if *first == b"meta" { body.get(4..)? } else { body };
+
+    if rest.is_empty() { Some(body) } else { find_atom(body, rest) }
+
+}
+
+fn read_mp4_item(kind: &[u8; 4], item: &[u8], tags: &mut RawTags) {
+
+    let key = match kind {
+        b"\xA9nam" => "TITLE",
+        b"\xA9ART" => "ARTIST",
+        b"\xA9alb" => "ALBUM",
+        b"aART" => "ALBUMARTIST",
+        b"\xA9day" => "DATE",
+        b"\xA9gen" => "GENRE",
+        b"\xA9wrt" => "COMPOSER",
+        b"\xA9cmt" => "COMMENT",
+        b"\xA9lyr" => "LYRICS",
+        //data型 13: JPEG, 14: PNG
+        b"covr" => {
+            if let Some((13 | 14, data)) = mp4_item_data(item) {
+                let mime_type = if data.starts_with(b"\x89PNG") { "image/png" } else { "image/jpeg" };
+                tags.push_cover(CoverArt::new(mime_type, PICTURE_TYPE_FRONT_COVER, data.to_vec()));
+            }
+            return;
+        },
+        //番号と総数が16bitずつ入ったバイナリ
+        b"trkn" | b"disk" => {
+            if let Some((number, total)) = mp4_item_data(item).and_then(|(_, value)| mp4_number_pair(value)) {
+                let (number_key, total_key) = if kind == b"trkn" { ("TRACKNUMBER", "TRACKTOTAL") } else { ("DISCNUMBER", "DISCTOTAL") };
+                tags.push(number_key, &number.to_string());
+                if total > 0 {
+                    tags.push(total_key, &total.to_string());
+                }
+            }
+            return;
+        },
+        //"----"はmean/name/dataで表す自由形式のアイテム(ReplayGainなど)
+        b"----" => {
+            let name = atoms(item).find(|(kind, _)| kind == b"name").and_then(|(_, name)| name.get(4..));
+            if let (Some(name), Some(value)) = (name, mp4_item_text(item)) {
+                tags.push(&String::from_utf8_lossy(name), &value);
+            }
+            return;
+        },
+        _ => return,
+    };
+
+    if let Some(value) = mp4_item_text(item) {
+        tags.push(key, &value);
+    }
+
+}
+
+//アイテムのdataアトム(型4バイト + ロケール4バイト + 値)を(型, 値)で返す
+fn mp4_item_data(item: &[u8]) -> Option<(u32, &[u8])> {
+
+    let (_, data) = atoms(item).find(|(kind, _)| kind == b"data")?;
+    let value_type = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?);
+
+    Some((value_type, data.get(8..)?))
+
+}
+
+fn mp4_item_text(item: &[u8]) -> Option<String> {
+
+    match mp4_item_data(item)? {
+        //1: UTF-8
+        (1, value) => Some(String::from_utf8_lossy(value).into_owned()),
+        _ => None,
+    }
+
+}
+
+fn mp4_number_pair(value: &[u8]) -> Option<(u16, u16)> {
+    let number = u16::from_be_bytes(value.get(2..4)?.try_into().ok()?);
+    let total = value.get(4..6).map(|b| u16::from_be_bytes([b[0], b[1]])).unwrap_or(0);
+    Some((number, total))
+}
+
+fn tag_error(message: &str) -> FerriaError {
+    FerriaError::AudioError(format!("Failed to read tags: {}", message))
+}
+
+//バイト列を先頭から読むための小さなカーソル
+struct ByteCursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+
+    fn new(data: &'a [u8]) -> Self {
+        ByteCursor { data, offset: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], FerriaError> {
+
+        let end = self.offset.checked_add(length).filter(|&end| end <= self.data.len())
+        .ok_or_else(|| tag_error("unexpected end of tag data"))?;
+
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+
+        Ok(bytes)
+
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), FerriaError> {
+        self.take(length).map(|_| ())
+    }
+
+    fn u32_le(&mut self) -> Result<u32, FerriaError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u32_be(&mut self) -> Result<u32, FerriaError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+}
+
+#[cfg(test)]
+mod test_tags {
+
+    use super::*;
+    use std::io::Cursor;
+
+    fn vorbis_comments(comments: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&6u32.to_le_bytes());
+        data.extend_from_slice(b"ferria");
+        data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            data.extend_from_slice(comment.as_bytes());
+        }
+        data
+    }
+
+    fn flac_picture(picture_type: u8, mime_type: &str, image: &[u8]) -> Vec<u8> {
+        let mut data = (picture_type as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+        data.extend_from_slice(mime_type.as_bytes());
+        //説明は空、幅/高さ/色深度/パレット数は使わない
+        data.extend_from_slice(&[0; 4 + 16]);
+        data.extend_from_slice(&(image.len() as u32).to_be_bytes());
+        data.extend_from_slice(image);
+        data
+    }
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn ogg_page(segments: &[&[u8]]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(segments.len() as u8);
+        page.extend(segments.iter().map(|s| s.len() as u8));
+        for segment in segments {
+            page.extend_from_slice(segment);
+        }
+        page
+    }
+
+    #[test]
+    fn test_flac_vorbis_comments() {
+        let comments = vorbis_comments(&["title=Eine", "ARTIST=Ferria", "broken"]);
+
+        let mut data = b"fLaC".to_vec();
+        //STREAMINFO(34バイト)
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 34]);
+        data.extend_from_slice(&[0; 34]);
+        //裏表紙、表紙の順のPICTURE
+        for (picture_type, image) in [(4u8, b"back"), (3u8, b"frnt")] {
+            let picture = flac_picture(picture_type, "image/png", image);
+            data.push(0x06);
+            data.extend_from_slice(&(picture.len() as u32).to_be_bytes()[1..]);
+            data.extend_from_slice(&picture);
+        }
+        //最後のブロックとしてVORBIS_COMMENT
+        data.push(0x84);
+        data.extend_from_slice(&(comments.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&comments);
+
+        let tags = read_flac_comments(&mut Cursor::new(data)).unwrap();
+        assert_eq!(tags.get("TITLE"), Some("Eine"));
+        assert_eq!(tags.get("artist"), Some("Ferria"));
+
+        let cover = tags.cover.unwrap();
+        assert!(cover.is_front_cover());
+        assert_eq!(cover.mime_type, "image/png");
+        assert_eq!(&cover.data[..], b"frnt");
+    }
+
+    #[test]
+    fn test_vorbis_comment_picture() {
+        let picture = base64::engine::general_purpose::STANDARD.encode(flac_picture(3, "image/jpeg", b"jpeg"));
+        let comments = vorbis_comments(&["TITLE=Eine", &format!("METADATA_BLOCK_PICTURE={}", picture)]);
+
+        let tags = parse_vorbis_comments(&comments).unwrap();
+        assert_eq!(tags.get("METADATA_BLOCK_PICTURE"), None);
+        assert_eq!(&tags.cover.unwrap().data[..], b"jpeg");
+    }
+
+    #[test]
+    fn test_ogg_comment_packet_spanning_pages() {
+        let mut comment_packet = b"OpusTags".to_vec();
+        comment_packet.extend(vorbis_comments(&["TITLE=Kleine", &format!("COMMENT={}", "x".repeat(300))]));
+
+        //識別ヘッダーのページの後に、コメントヘッダーを255バイトのセグメントで2ページに分けて置く
+        let mut data = ogg_page(&[b"OpusHead"]);
+        let (first, second) = comment_packet.split_at(255);
+        data.extend(ogg_page(&[first]));
+        data.extend(ogg_page(&[second, b"audio"]));
+
+        let tags = read_ogg_comments(&mut Cursor::new(data)).unwrap();
+        assert_eq!(tags.get("TITLE"), Some("Kleine"));
+        assert_eq!(tags.get("COMMENT").map(str::len), Some(300));
+    }
+
+    #[test]
+    fn test_mp4_ilst_atoms() {
+        let title = atom(b"\xA9nam", &atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], b"Nacht"].concat()));
+        let artist = atom(b"\xA9ART", &atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], b"Mozart"].concat()));
+        let track = atom(b"trkn", &atom(b"data", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 12, 0, 0]));
+        let gain = atom(b"----", &[
+            atom(b"mean", b"\0\0\0\0com.apple.iTunes"),
+            atom(b"name", b"\0\0\0\0replaygain_track_gain"),
+            atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], b"-7.20 dB"].concat()),
+        ].concat());
+        let ilst = atom(b"ilst", &[title, artist, track, gain].concat());
+        let meta = atom(b"meta", &[&[0, 0, 0, 0][..], &atom(b"hdlr", &[0; 25]), &ilst].concat());
+        let moov = atom(b"moov", &[atom(b"mvhd", &[0; 100]), atom(b"udta", &meta)].concat());
+
+        let data = [atom(b"ftyp", b"M4A \0\0\0\0"), atom(b"mdat", &[0; 50]), moov].concat();
+
+        let tags = read_mp4_atoms(&mut Cursor::new(data)).unwrap();
+        assert_eq!(tags.get("TITLE"), Some("Nacht"));
+        assert_eq!(tags.get("ARTIST"), Some("Mozart"));
+        assert_eq!(tags.get("TRACKNUMBER"), Some("3"));
+        assert_eq!(tags.get("TRACKTOTAL"), Some("12"));
+        assert_eq!(tags.get("REPLAYGAIN_TRACK_GAIN"), Some("-7.20 dB"));
+    }
+
+    #[test]
+    fn test_riff_info_and_aiff_text_chunks() {
+        let info = [b"INFO".to_vec(), b"INAM".to_vec(), 5u32.to_le_bytes().to_vec(), b"Musik\0".to_vec(), b"IART".to_vec(), 4u32.to_le_bytes().to_vec(), b"Amad".to_vec()].concat();
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 16]);
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&info);
+
+        let tags = read_riff_info(&mut Cursor::new(wav)).unwrap();
+        assert_eq!(tags.get("TITLE"), Some("Musik"));
+        assert_eq!(tags.get("ARTIST"), Some("Amad"));
+
+        let mut aiff = b"FORM\0\0\0\0AIFF".to_vec();
+        aiff.extend_from_slice(b"NAME");
+        aiff.extend_from_slice(&3u32.to_be_bytes());
+        aiff.extend_from_slice(b"Eau\0");
+        aiff.extend_from_slice(b"AUTH");
+        aiff.extend_from_slice(&2u32.to_be_bytes());
+        aiff.extend_from_slice(b"Me");
+
+        let tags = read_aiff_text_chunks(&mut Cursor::new(aiff)).unwrap();
+        assert_eq!(tags.get("TITLE"), Some("Eau"));
+        assert_eq!(tags.get("ARTIST"), Some("Me"));
+    }
+
+    #[test]
+    fn test_oversized_text_chunks_are_skipped() {
+        //壊れたサイズのチャンクを確保せずに読み飛ばす
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&u32::MAX.to_le_bytes());
+        wav.extend_from_slice(b"INFO");
+        assert!(read_riff_info(&mut Cursor::new(wav)).unwrap().get("TITLE").is_none());
+
+        let mut aiff = b"FORM\0\0\0\0AIFF".to_vec();
+        aiff.extend_from_slice(b"NAME");
+        aiff.extend_from_slice(&(u32::MAX - 1).to_be_bytes());
+        aiff.extend_from_slice(b"Eau");
+        assert!(read_aiff_text_chunks(&mut Cursor::new(aiff)).unwrap().get("TITLE").is_none());
+    }
+
+}
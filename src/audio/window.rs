@@ -0,0 +1,189 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+//Kaiser窓のbetaを省略したときの値(サイドローブ約-70dB)
+pub const DEFAULT_KAISER_BETA: f32 = 8.6;
+
+//FFTの前にサンプルにかける窓関数
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WindowFunction {
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+    //4項のBlackman-Harris
+    BlackmanHarris,
+    //振幅の測定向け。ピークがどのビンの間にあっても振幅がほぼ変わらない
+    FlatTop,
+    Kaiser { beta: f32 },
+    //窓をかけない
+    Rectangular,
+}
+
+impl WindowFunction {
+
+    //長さnの窓の係数(両端を含む対称な窓)
+    pub fn coefficients(&self, n: usize) -> Vec<f32> {
+
+        if n <= 1 {
+            //1サンプル以下なら窓関数は不要
+            return vec![1.0; n];
+        }
+
+        let last = (n - 1) as f32;
+
+        (0..n)
+        .map(|i| {
+
+            let x = i as f32 / last;
+
+            match *self {
+                WindowFunction::Hann => cosine_sum(&[0.5, 0.5], x),
+                WindowFunction::Hamming => cosine_sum(&[0.54, 0.46], x),
+                WindowFunction::Blackman => cosine_sum(&[0.42, 0.5, 0.08], x),
+                WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168], x),
+                WindowFunction::FlatTop => cosine_sum(&[0.21557895, 0.41663158, 0.27726316, 0.08357895, 0.006947368], x),
+                WindowFunction::Kaiser { beta } => {
+                    let t = 2.0 * x - 1.0;
+                    bessel_i0(beta * (1.0 - t * t).max(0.0).sqrt()) / bessel_i0(beta)
+                },
+                WindowFunction::Rectangular => 1.0,
+            }
+
+        })
+        .collect()
+
+    }
+
+}
+
+//a0 - a1 cos(2πx) + a2 cos(4πx) - ... の形の窓
+fn cosine_sum(terms: &[f32], x: f32) -> f32 {
+    terms.iter().enumerate()
+    .map(|(k, a)| {
+        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+        sign * a * (2.0 * PI * k as f32 * x).cos()
+    })
+    .sum()
+}
+
+//第1種変形ベッセル関数I0(級数展開)
+fn bessel_i0(x: f32) -> f32 {
+
+    let half = x / 2.0;
+    let mut term = 1.0f32;
+    let mut sum = 1.0f32;
+
+    for k in 1..64 {
+        term *= (half / k as f32) * (half / k as f32);
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+
+    sum
+
+}
+
+impl fmt::Display for WindowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowFunction::Hann => write!(f, "hann"),
+            WindowFunction::Hamming => write!(f, "hamming"),
+            WindowFunction::Blackman => write!(f, "blackman"),
+            WindowFunction::BlackmanHarris => write!(f, "blackman-harris"),
+            WindowFunction::FlatTop => write!(f, "flat-top"),
+            WindowFunction::Kaiser { beta } => write!(f, "kaiser:{}", beta),
+            WindowFunction::Rectangular => write!(f, "rectangular"),
+        }
+    }
+}
+
+//"hann", "flat-top", "kaiser", "kaiser:5.0" のような文字列から作る
+impl FromStr for WindowFunction {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+
+        let window = match name.to_ascii_lowercase().as_str() {
+            "hann" | "hanning" => WindowFunction::Hann,
+            "hamming" => WindowFunction::Hamming,
+            "blackman" => WindowFunction::Blackman,
+            "blackman-harris" => WindowFunction::BlackmanHarris,
+            "flat-top" | "flattop" => WindowFunction::FlatTop,
+            "rectangular" | "none" => WindowFunction::Rectangular,
+            "kaiser" => {
+                let beta = match parameter {
+                    Some(beta) => beta.parse::<f32>().ok().filter(|b| b.is_finite() && *b >= 0.0)
+                    .ok_or_else(|| format!("Kaiser beta must be a non-negative number, got `{}`", beta))?,
+                    None => DEFAULT_KAISER_BETA,
+                };
+                return Ok(WindowFunction::Kaiser { beta });
+            },
+            _ => return Err(format!("Unknown window function `{}` (hann, hamming, blackman, blackman-harris, flat-top, kaiser[:BETA], rectangular)", s)),
+        };
+
+        if parameter.is_some() {
+            return Err(format!("Window function `{}` takes no parameter", name));
+        }
+
+        Ok(window)
+
+    }
+
+}
+
+#[cfg(test)]
+mod test_window {
+
+    use super::*;
+
+    #[test]
+    fn test_windows_are_symmetric_and_peak_in_the_middle() {
+        let windows = [
+            WindowFunction::Hann,
+            WindowFunction::Hamming,
+            WindowFunction::Blackman,
+            WindowFunction::BlackmanHarris,
+            WindowFunction::FlatTop,
+            WindowFunction::Kaiser { beta: DEFAULT_KAISER_BETA },
+        ];
+
+        for window in windows {
+            let coefficients = window.coefficients(65);
+            for i in 0..coefficients.len() / 2 {
+                assert!((coefficients[i] - coefficients[64 - i]).abs() < 1e-5, "{} is not symmetric", window);
+            }
+            assert!((coefficients[32] - 1.0).abs() < 1e-3, "{} peak is {}", window, coefficients[32]);
+            assert!(coefficients[0] < 0.1, "{} edge is {}", window, coefficients[0]);
+        }
+
+        assert_eq!(WindowFunction::Rectangular.coefficients(4), vec![1.0; 4]);
+        //beta = 0のKaiser窓は矩形窓
+        assert!(WindowFunction::Kaiser { beta: 0.0 }.coefficients(8).iter().all(|&c| (c - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_parse_window_function() {
+        assert_eq!("flat-top".parse(), Ok(WindowFunction::FlatTop));
+        assert_eq!("Blackman-Harris".parse(), Ok(WindowFunction::BlackmanHarris));
+        assert_eq!("kaiser".parse(), Ok(WindowFunction::Kaiser { beta: DEFAULT_KAISER_BETA }));
+        assert_eq!("kaiser:5".parse(), Ok(WindowFunction::Kaiser { beta: 5.0 }));
+
+        assert!("kaiser:-1".parse::<WindowFunction>().is_err());
+        assert!("hann:2".parse::<WindowFunction>().is_err());
+        assert!("triangle".parse::<WindowFunction>().is_err());
+
+        let window = WindowFunction::Kaiser { beta: 6.5 };
+        assert_eq!(window.to_string().parse(), Ok(window));
+    }
+
+}
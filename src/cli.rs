@@ -0,0 +1,584 @@
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::app::AppOptions;
+use crate::audio::analyzer::LevelMode;
+use crate::audio::bands::BandScale;
+use crate::audio::format::AudioFormat;
+use crate::audio::offline::{DumpFormat, DumpOptions};
+use crate::audio::output::OutputBackend;
+use crate::audio::playlist::RepeatMode;
+use crate::audio::scaling::{AmplitudeScale, Weighting};
+use crate::audio::window::WindowFunction;
+use crate::config::Config;
+use crate::error::FerriaError;
+use crate::visualizer::glyphs::BarStyle;
+use crate::visualizer::registry::VisualizerMode;
+use crate::visualizer::visualize_color::Colormap;
+
+///Ferria: CLI Audio Visualizer & Sound Player
+#[derive(Debug, Parser)]
+#[command(name = "ferria", version, about)]
+pub struct Cli {
+
+    ///再生するファイル、ディレクトリ、またはglobパターン(例: "music/*.mp3")
+    #[arg(required = true, value_name = "PATH")]
+    pub inputs: Vec<String>,
+
+    ///ディレクトリを再帰的に探索する
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    ///設定ファイル(省略時は $XDG_CONFIG_HOME/ferria/config.toml があれば読む)
+    ///指定しなかった項目は設定ファイル、FERRIA_<SECTION>_<KEY>の環境変数、既定値の順に決まる
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    ///FFTサイズ(2の累乗)
+    #[arg(long, value_name = "SIZE", value_parser = parse_fft_size)]
+    pub fft_size: Option<usize>,
+
+    ///開始時の音量(0.0〜1.0)
+    #[arg(long, value_name = "VOLUME", value_parser = parse_volume)]
+    pub volume: Option<f32>,
+
+    ///スペクトルを周波数帯域にまとめるときのスケール
+    #[arg(long, value_enum)]
+    pub bands: Option<BandScale>,
+
+    ///帯域の数(linear, log, melのとき)
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u16).range(1..=1024))]
+    pub band_count: Option<u16>,
+
+    ///FFTの窓関数(hann, hamming, blackman, blackman-harris, flat-top, kaiser[:BETA], rectangular)
+    #[arg(long, value_name = "WINDOW")]
+    pub window: Option<WindowFunction>,
+
+    ///スペクトルの値の表し方(linear, power, db[:REFERENCE])
+    #[arg(long, value_name = "SCALE")]
+    pub scale: Option<AmplitudeScale>,
+
+    ///周波数の重み付け
+    #[arg(long, value_enum)]
+    pub weighting: Option<Weighting>,
+
+    ///スペクトルの振幅の基準
+    #[arg(long, value_enum)]
+    pub level: Option<LevelMode>,
+
+    ///`--level agc` で、音量が下がったときに追従する時定数(秒)。0ならフレームごとに合わせる
+    #[arg(long, value_name = "SECONDS", value_parser = parse_agc_release)]
+    pub agc_release: Option<f32>,
+
+    ///ヴィジュアライザーの描画モード
+    #[arg(long, value_enum)]
+    pub mode: Option<VisualizerMode>,
+
+    ///スペクトログラムのカラーマップ
+    #[arg(long, value_enum)]
+    pub colormap: Option<Colormap>,
+
+    ///ヴィジュアライザーの配色。組み込み(rainbow, fire, ocean, mono)か、設定ディレクトリのthemes/NAME.toml
+    #[arg(long, value_name = "NAME")]
+    pub theme: Option<String>,
+
+    ///棒の描き方
+    #[arg(long, value_enum)]
+    pub bar_style: Option<BarStyle>,
+
+    ///棒の幅(brailleのときはドット単位、それ以外はセル単位)
+    #[arg(long, value_name = "WIDTH", value_parser = clap::value_parser!(u16).range(1..=16))]
+    pub bar_width: Option<u16>,
+
+    ///棒の間隔(bar-widthと同じ単位)
+    #[arg(long, value_name = "GAP", value_parser = clap::value_parser!(u16).range(0..=16))]
+    pub bar_gap: Option<u16>,
+
+    ///棒が伸びるときの時定数(秒)
+    #[arg(long, value_name = "SECONDS", value_parser = parse_time_constant)]
+    pub attack: Option<f32>,
+
+    ///棒が縮むときの時定数(秒)
+    #[arg(long, value_name = "SECONDS", value_parser = parse_time_constant)]
+    pub release: Option<f32>,
+
+    ///ピークの印が落ち始めるまでの時間(秒)
+    #[arg(long, value_name = "SECONDS", value_parser = parse_time_constant)]
+    pub peak_hold: Option<f32>,
+
+    ///ピークの印が落ちる加速度(表示の高さ全体を1とした1秒あたりの値)
+    #[arg(long, value_name = "RATE", value_parser = parse_peak_gravity)]
+    pub peak_gravity: Option<f32>,
+
+    ///ピークの印を表示する
+    #[arg(long, overrides_with = "no_peaks")]
+    pub peaks: bool,
+
+    ///ピークの印を表示しない
+    #[arg(long, overrides_with = "peaks")]
+    pub no_peaks: bool,
+
+    ///解析フレームの間を補間して滑らかに動かす(フレームの間隔が描画より長いとき向け)
+    #[arg(long, overrides_with = "no_interpolate")]
+    pub interpolate: bool,
+
+    ///フレームの間を補間しない
+    #[arg(long, overrides_with = "interpolate")]
+    pub no_interpolate: bool,
+
+    ///リピートモード
+    #[arg(long, value_enum)]
+    pub repeat: Option<RepeatMode>,
+
+    ///`--repeat all` の短縮形
+    #[arg(long = "loop", conflicts_with = "repeat")]
+    pub loop_all: bool,
+
+    ///再生順をシャッフルする
+    #[arg(long, overrides_with = "no_shuffle")]
+    pub shuffle: bool,
+
+    ///再生順をシャッフルしない(設定ファイルのshuffleを打ち消す)
+    #[arg(long, overrides_with = "shuffle")]
+    pub no_shuffle: bool,
+
+    ///トラック間のクロスフェードの長さ(秒)。0ならギャップレスで繋ぐ
+    #[arg(long, value_name = "SECONDS", value_parser = parse_crossfade)]
+    pub crossfade: Option<f32>,
+
+    ///音の出力先
+    #[arg(long, value_enum, default_value_t = OutputKind::Device)]
+    pub output: OutputKind,
+
+    ///`--output wav` の書き出し先
+    #[arg(long, value_name = "FILE", required_if_eq("output", "wav"))]
+    pub output_file: Option<PathBuf>,
+
+    ///再生せずにトラック全体を解析して、フレームごとのスペクトルをファイルに書き出す
+    #[arg(long, value_name = "FILE")]
+    pub dump: Option<PathBuf>,
+
+    ///`--dump` の形式(省略時は拡張子から判断する)
+    #[arg(long, value_enum, requires = "dump")]
+    pub dump_format: Option<DumpFormat>,
+
+    ///解析フレームの間隔(サンプル数)。省略時は `--overlap` から決める
+    #[arg(long, value_name = "SAMPLES", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "overlap")]
+    pub hop: Option<u32>,
+
+    ///隣り合う解析フレームの窓の重なり(0.0〜0.95)。再生中の解析と `--dump` の両方に効く
+    #[arg(long, value_name = "RATIO", value_parser = parse_overlap)]
+    pub overlap: Option<f32>,
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputKind {
+    ///既定のサウンドデバイス
+    Device,
+    ///音を出さずに実時間で再生する(サウンドデバイスの無い環境向け)
+    Null,
+    ///WAVファイルに書き出す
+    Wav,
+}
+
+impl Cli {
+
+    //コマンドラインで指定された項目だけを設定に重ねる
+    pub fn apply_to(&self, config: &mut Config) {
+
+        let analyzer = &mut config.analyzer;
+        set(&mut analyzer.fft_size, self.fft_size);
+        set(&mut analyzer.bands, self.bands);
+        set(&mut analyzer.band_count, self.band_count.map(usize::from));
+        set(&mut analyzer.window, self.window);
+        set(&mut analyzer.scale, self.scale);
+        set(&mut analyzer.weighting, self.weighting);
+        set(&mut analyzer.level, self.level);
+        set(&mut analyzer.agc_release, self.agc_release);
+
+        //--hopと--overlapは排他なので、指定された方で設定ファイルの値を置き換える
+        if let Some(hop) = self.hop {
+            analyzer.hop = Some(hop as usize);
+        }
+        if let Some(overlap) = self.overlap {
+            analyzer.hop = None;
+            analyzer.overlap = overlap;
+        }
+
+        let player = &mut config.player;
+        set(&mut player.volume, self.volume);
+        set(&mut player.crossfade, self.crossfade);
+        set(&mut player.repeat, self.repeat);
+        if self.loop_all {
+            player.repeat = RepeatMode::All;
+        }
+        set(&mut player.shuffle, switch(self.shuffle, self.no_shuffle));
+
+        let visualizer = &mut config.visualizer;
+        set(&mut visualizer.mode, self.mode);
+        set(&mut visualizer.colormap, self.colormap);
+        set(&mut visualizer.theme, self.theme.clone());
+        set(&mut visualizer.bar_style, self.bar_style);
+        set(&mut visualizer.bar_width, self.bar_width);
+        set(&mut visualizer.bar_gap, self.bar_gap);
+        set(&mut visualizer.attack, self.attack);
+        set(&mut visualizer.release, self.release);
+        set(&mut visualizer.peak_hold, self.peak_hold);
+        set(&mut visualizer.peak_gravity, self.peak_gravity);
+        set(&mut visualizer.peaks, switch(self.peaks, self.no_peaks));
+        set(&mut visualizer.interpolate, switch(self.interpolate, self.no_interpolate));
+
+    }
+
+    //入力パスを展開し、設定ファイルと環境変数から読んだ設定に引数を重ねてアプリの起動オプションに変換する
+    pub fn into_app_options(self, mut config: Config) -> Result<AppOptions, FerriaError> {
+
+        let tracks = resolve_inputs(&self.inputs, self.recursive)?;
+
+        self.apply_to(&mut config);
+        config.validate()?;
+
+        let dump = match self.dump {
+            Some(path) => {
+                if tracks.len() != 1 {
+                    return Err(cli_error(format!("--dump takes exactly one input track, got {}", tracks.len())));
+                }
+
+                let format = self.dump_format
+                .or_else(|| DumpFormat::from_extension(&path))
+                .ok_or_else(|| cli_error(format!("Cannot tell the dump format from `{}`; use --dump-format", path.display())))?;
+
+                Some(DumpOptions { path, format })
+            },
+            None => None,
+        };
+
+        Ok(AppOptions {
+            tracks,
+            output: match (self.output, self.output_file) {
+                (OutputKind::Device, _) => OutputBackend::Device,
+                (OutputKind::Null, _) => OutputBackend::Null { realtime: true },
+                (OutputKind::Wav, Some(path)) => OutputBackend::Wav { path, realtime: true },
+                (OutputKind::Wav, None) => return Err(cli_error("--output wav requires --output-file".to_string())),
+            },
+            dump,
+            config,
+        })
+
+    }
+
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+//--fooと--no-fooの組。どちらも指定されていなければNone
+fn switch(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_fft_size(s: &str) -> Result<usize, String> {
+
+    let size: usize = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if size == 0 || !size.is_power_of_two() {
+        return Err(format!("FFT size must be a power of two and non-zero, got {}", size));
+    }
+
+    Ok(size)
+}
+
+fn parse_volume(s: &str) -> Result<f32, String> {
+
+    let volume: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !(0.0..=1.0).contains(&volume) {
+        return Err(format!("volume must be between 0.0 and 1.0, got {}", volume));
+    }
+
+    Ok(volume)
+}
+
+fn parse_crossfade(s: &str) -> Result<f32, String> {
+
+    let seconds: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !(0.0..=30.0).contains(&seconds) {
+        return Err(format!("crossfade must be between 0 and 30 seconds, got {}", seconds));
+    }
+
+    Ok(seconds)
+}
+
+fn parse_time_constant(s: &str) -> Result<f32, String> {
+
+    let seconds: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !(0.0..=10.0).contains(&seconds) {
+        return Err(format!("time must be between 0 and 10 seconds, got {}", seconds));
+    }
+
+    Ok(seconds)
+}
+
+fn parse_peak_gravity(s: &str) -> Result<f32, String> {
+
+    let gravity: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !gravity.is_finite() || gravity <= 0.0 {
+        return Err(format!("peak gravity must be a positive number, got {}", gravity));
+    }
+
+    Ok(gravity)
+}
+
+fn parse_agc_release(s: &str) -> Result<f32, String> {
+
+    let seconds: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !(0.0..=60.0).contains(&seconds) {
+        return Err(format!("AGC release must be between 0 and 60 seconds, got {}", seconds));
+    }
+
+    Ok(seconds)
+}
+
+fn parse_overlap(s: &str) -> Result<f32, String> {
+
+    let overlap: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+    if !(0.0..=0.95).contains(&overlap) {
+        return Err(format!("overlap must be between 0.0 and 0.95, got {}", overlap));
+    }
+
+    Ok(overlap)
+}
+
+fn cli_error(message: String) -> FerriaError {
+    FerriaError::CliError(Cli::command().error(ErrorKind::ValueValidation, message))
+}
+
+//ファイル、ディレクトリ、globパターンを再生対象のファイル一覧に展開する
+//入力の順序は保ち、ディレクトリとglobの中身はパス順に並べる
+pub fn resolve_inputs(inputs: &[String], recursive: bool) -> Result<Vec<PathBuf>, FerriaError> {
+
+    let mut tracks = Vec::new();
+
+    for input in inputs {
+
+        let path = Path::new(input);
+
+        let mut found = if path.is_file() {
+            vec![path.to_path_buf()]
+        }
+        else if path.is_dir() {
+            collect_dir(path, recursive)?
+        }
+        else {
+            let entries = glob::glob(input)
+            .map_err(|e| cli_error(format!("Invalid path or glob pattern `{}`: {}", input, e)))?;
+
+            entries
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file() && is_supported_file(p))
+            .collect()
+        };
+
+        if found.is_empty() {
+            return Err(cli_error(format!("No audio files found for `{}`", input)));
+        }
+
+        found.sort();
+        tracks.append(&mut found);
+    }
+
+    Ok(tracks)
+
+}
+
+fn collect_dir(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>, FerriaError> {
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if recursive {
+                files.append(&mut collect_dir(&path, recursive)?);
+            }
+        }
+        else if is_supported_file(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+
+}
+
+pub fn is_supported_file(path: &Path) -> bool {
+    AudioFormat::from_extension(path).is_some()
+}
+
+#[cfg(test)]
+mod test_cli {
+
+    use super::*;
+    use std::fs::File;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ferria_cli_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    //引数を既定の設定に重ねた結果
+    fn parse(args: &[&str]) -> Config {
+        let mut config = Config::default();
+        Cli::try_parse_from(args).unwrap().apply_to(&mut config);
+        config
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let cli = Cli::try_parse_from(["ferria", "a.mp3", "--fft-size", "2048", "--volume", "0.5", "--loop", "--shuffle"]).unwrap();
+        assert_eq!(cli.inputs, vec!["a.mp3".to_string()]);
+        let config = parse(&["ferria", "a.mp3", "--fft-size", "2048", "--volume", "0.5", "--loop", "--shuffle"]);
+        assert_eq!(config.analyzer.fft_size, 2048);
+        assert_eq!(config.player.volume, 0.5);
+        assert_eq!(config.visualizer.mode, VisualizerMode::Bars);
+        assert_eq!(config.analyzer.bands, BandScale::Log);
+        assert_eq!(config.player.repeat, RepeatMode::All);
+        assert!(config.player.shuffle);
+
+        let config = parse(&["ferria", "a.mp3", "--repeat", "one", "--bands", "third-octave", "--mode", "vectorscope", "--colormap", "magma"]);
+        assert_eq!(config.player.repeat, RepeatMode::One);
+        assert_eq!(config.visualizer.mode, VisualizerMode::Vectorscope);
+        assert_eq!(config.visualizer.colormap, Colormap::Magma);
+        assert_eq!(config.visualizer.theme, crate::visualizer::theme::DEFAULT_THEME);
+        assert_eq!(config.analyzer.bands, BandScale::ThirdOctave);
+
+        let cli = Cli::try_parse_from(["ferria", "a.mp3", "--output", "wav", "--output-file", "out.wav"]).unwrap();
+        assert_eq!(cli.output, OutputKind::Wav);
+        assert_eq!(cli.output_file, Some(PathBuf::from("out.wav")));
+
+        let config = parse(&["ferria", "a.mp3", "--window", "kaiser:6", "--scale", "db:0.5", "--weighting", "a"]);
+        assert_eq!(config.analyzer.window, WindowFunction::Kaiser { beta: 6.0 });
+        assert_eq!(config.analyzer.scale, AmplitudeScale::Decibel { reference: 0.5 });
+        assert_eq!(config.analyzer.weighting, Weighting::A);
+        assert_eq!(config.analyzer.level, LevelMode::Absolute);
+
+        let config = parse(&["ferria", "a.mp3", "--level", "agc", "--agc-release", "0.5"]);
+        assert_eq!(config.analyzer.level, LevelMode::Agc);
+        assert_eq!(config.analyzer.agc_release, 0.5);
+
+        let config = parse(&["ferria", "a.mp3", "--release", "0.1", "--peak-hold", "0", "--no-peaks", "--interpolate"]);
+        assert_eq!(config.visualizer.release, 0.1);
+        assert_eq!(config.visualizer.peak_hold, 0.0);
+        assert!(!config.visualizer.peaks && config.visualizer.interpolate);
+
+        let config = parse(&["ferria", "a.mp3", "--bar-style", "braille", "--bar-width", "3", "--bar-gap", "1"]);
+        assert_eq!((config.visualizer.bar_style, config.visualizer.bar_width, config.visualizer.bar_gap), (BarStyle::Braille, 3, 1));
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--bar-width", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_overrides_config() {
+        let mut config = Config::from_sources("[analyzer]\nfft_size = 4096\nhop = 512\n[visualizer]\ntheme = \"fire\"", "config.toml", Vec::new()).unwrap();
+
+        //指定していない項目は設定ファイルの値が残る
+        Cli::try_parse_from(["ferria", "a.mp3", "--theme", "ocean"]).unwrap().apply_to(&mut config);
+        assert_eq!(config.analyzer.fft_size, 4096);
+        assert_eq!(config.hop_size(), 512);
+        assert_eq!(config.visualizer.theme, "ocean");
+
+        //--overlapは設定ファイルのhopより優先する
+        Cli::try_parse_from(["ferria", "a.mp3", "--overlap", "0.5"]).unwrap().apply_to(&mut config);
+        assert_eq!(config.hop_size(), 2048);
+
+        //オン/オフのフラグは設定ファイルの値をどちらの向きにも上書きできる
+        let mut config = Config::from_sources("[player]\nshuffle = true\n[visualizer]\ninterpolate = true\npeaks = false", "config.toml", Vec::new()).unwrap();
+        Cli::try_parse_from(["ferria", "a.mp3"]).unwrap().apply_to(&mut config);
+        assert!(config.player.shuffle && config.visualizer.interpolate && !config.visualizer.peaks);
+        Cli::try_parse_from(["ferria", "a.mp3", "--no-shuffle", "--no-interpolate", "--peaks"]).unwrap().apply_to(&mut config);
+        assert!(!config.player.shuffle && !config.visualizer.interpolate && config.visualizer.peaks);
+        Cli::try_parse_from(["ferria", "a.mp3", "--shuffle", "--interpolate", "--no-peaks"]).unwrap().apply_to(&mut config);
+        assert!(config.player.shuffle && config.visualizer.interpolate && !config.visualizer.peaks);
+
+        //同じ項目を両方指定したら後の方が勝つ
+        Cli::try_parse_from(["ferria", "a.mp3", "--shuffle", "--no-shuffle"]).unwrap().apply_to(&mut config);
+        assert!(!config.player.shuffle);
+    }
+
+    #[test]
+    fn test_parse_invalid_options() {
+        assert!(Cli::try_parse_from(["ferria"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--fft-size", "1000"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--volume", "1.5"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--loop", "--repeat", "one"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--crossfade", "-1"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--output", "wav"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--overlap", "1.0"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--hop", "0"]).is_err());
+        assert!(Cli::try_parse_from(["ferria", "a.mp3", "--dump-format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_dir_and_glob() {
+        let dir = temp_dir("resolve");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.mp3", "a.flac", "cover.jpg", "d.opus", "sub/c.wav"] {
+            File::create(dir.join(name)).unwrap();
+        }
+
+        let flat = resolve_inputs(&[dir.display().to_string()], false).unwrap();
+        assert_eq!(flat, vec![dir.join("a.flac"), dir.join("b.mp3"), dir.join("d.opus")]);
+
+        let nested = resolve_inputs(&[dir.display().to_string()], true).unwrap();
+        assert_eq!(nested.len(), 4);
+
+        let globbed = resolve_inputs(&[format!("{}/*.mp3", dir.display())], false).unwrap();
+        assert_eq!(globbed, vec![dir.join("b.mp3")]);
+
+        //globでも再生できないファイルは除く
+        let mixed = resolve_inputs(&[format!("{}/*", dir.display())], false).unwrap();
+        assert_eq!(mixed, vec![dir.join("a.flac"), dir.join("b.mp3"), dir.join("d.opus")]);
+        assert!(resolve_inputs(&[format!("{}/*.jpg", dir.display())], false).is_err());
+
+        assert!(resolve_inputs(&[format!("{}/*.ogg", dir.display())], false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dump_options() {
+        let dir = temp_dir("dump");
+        let track = dir.join("a.mp3").display().to_string();
+        File::create(&track).unwrap();
+
+        let options = Cli::try_parse_from(["ferria", &track, "--dump", "out.npy", "--overlap", "0.75"]).unwrap().into_app_options(Config::default()).unwrap();
+        assert_eq!(options.dump, Some(DumpOptions { path: PathBuf::from("out.npy"), format: DumpFormat::Npy }));
+        assert_eq!(options.config.analyzer_config().hop_size, 256);
+
+        let options = Cli::try_parse_from(["ferria", &track, "--dump", "out", "--dump-format", "csv", "--hop", "100"]).unwrap().into_app_options(Config::default()).unwrap();
+        assert_eq!(options.config.analyzer_config().hop_size, 100);
+
+        assert!(Cli::try_parse_from(["ferria", &track, "--dump", "out.txt"]).unwrap().into_app_options(Config::default()).is_err());
+        assert!(Cli::try_parse_from(["ferria", &track, &track, "--dump", "out.csv"]).unwrap().into_app_options(Config::default()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,549 @@
+use clap::ValueEnum;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::audio::analyzer::{AnalyzerConfig, LevelMode};
+use crate::audio::bands::{BandLayout, BandScale};
+use crate::audio::player::{PlayerConfig, DEFAULT_VOLUME_STEP};
+use crate::audio::playlist::RepeatMode;
+use crate::audio::scaling::{AmplitudeScale, Weighting};
+use crate::audio::window::WindowFunction;
+use crate::error::FerriaError;
+use crate::action::Action;
+use crate::keys::{KeyPreset, KeySequence, Keymap};
+use crate::visualizer::ballistics::BallisticsConfig;
+use crate::visualizer::glyphs::BarStyle;
+use crate::visualizer::registry::{VisualizerConfig, VisualizerMode};
+use crate::visualizer::theme::DEFAULT_THEME;
+use crate::visualizer::visualize_color::Colormap;
+use crate::visualizer::visualizer::{BarsConfig, LevelRange, DEFAULT_MAX_DB, DEFAULT_MIN_DB};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+//FERRIA_<SECTION>_<KEY> の環境変数で設定ファイルの値を上書きする(例: FERRIA_ANALYZER_FFT_SIZE=2048)
+const ENV_PREFIX: &str = "FERRIA_";
+const SECTIONS: [&str; 4] = ["analyzer", "player", "visualizer", "keys"];
+
+pub const DEFAULT_FFT_SIZE: usize = 1024;
+//窓を75%重ねる(hopはfft_sizeの1/4)
+pub const DEFAULT_OVERLAP: f32 = 0.75;
+
+//設定ファイルを置くディレクトリ($XDG_CONFIG_HOME/ferria、無ければ~/.config/ferria)
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_from_env(|name| std::env::var_os(name).map(PathBuf::from))
+}
+
+pub fn config_dir_from_env<F: Fn(&str) -> Option<PathBuf>>(var: F) -> Option<PathBuf> {
+
+    //XDG Base Directoryの仕様どおり、相対パスは無視する
+    let base = var("XDG_CONFIG_HOME")
+    .filter(|dir| dir.is_absolute())
+    .or_else(|| var("HOME").filter(|home| !home.as_os_str().is_empty()).map(|home| home.join(".config")))?;
+
+    Some(base.join("ferria"))
+
+}
+
+//設定ファイル、環境変数、コマンドライン引数の順に重ねた設定
+//どの項目も省略でき、省略した項目は既定値になる
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub analyzer: AnalyzerSettings,
+    pub player: PlayerSettings,
+    pub visualizer: VisualizerSettings,
+    //presetで土台の割り当てを選び、操作ごとに1つのキーかキーのリストで上書きする
+    #[serde(deserialize_with = "keymap")]
+    pub keys: Keymap,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyzerSettings {
+    pub fft_size: usize,
+    //解析フレームの間隔(サンプル数)。省略時はoverlapから決める
+    pub hop: Option<usize>,
+    pub overlap: f32,
+    #[serde(deserialize_with = "value_enum")]
+    pub bands: BandScale,
+    pub band_count: usize,
+    #[serde(deserialize_with = "from_str")]
+    pub window: WindowFunction,
+    #[serde(deserialize_with = "from_str")]
+    pub scale: AmplitudeScale,
+    #[serde(deserialize_with = "value_enum")]
+    pub weighting: Weighting,
+    #[serde(deserialize_with = "value_enum")]
+    pub level: LevelMode,
+    pub agc_release: f32,
+}
+
+impl Default for AnalyzerSettings {
+    fn default() -> Self {
+        let analyzer = AnalyzerConfig::default();
+        AnalyzerSettings {
+            fft_size: DEFAULT_FFT_SIZE,
+            hop: None,
+            overlap: DEFAULT_OVERLAP,
+            bands: analyzer.band_layout.scale,
+            band_count: analyzer.band_layout.count,
+            window: analyzer.window,
+            scale: analyzer.scale,
+            weighting: analyzer.weighting,
+            level: analyzer.level,
+            agc_release: analyzer.agc_release,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerSettings {
+    pub volume: f32,
+    pub volume_step: f32,
+    //矢印キー1回でシークする秒数
+    pub seek_step: f32,
+    pub crossfade: f32,
+    #[serde(deserialize_with = "value_enum")]
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            volume: 1.0,
+            volume_step: DEFAULT_VOLUME_STEP,
+            seek_step: 5.0,
+            crossfade: 0.0,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VisualizerSettings {
+    #[serde(deserialize_with = "value_enum")]
+    pub mode: VisualizerMode,
+    #[serde(deserialize_with = "value_enum")]
+    pub colormap: Colormap,
+    pub theme: String,
+    pub min_db: f32,
+    pub max_db: f32,
+    //Barsで棒グラフを描く範囲の割合
+    pub width: f32,
+    pub height: f32,
+    #[serde(deserialize_with = "value_enum")]
+    pub bar_style: BarStyle,
+    pub bar_width: u16,
+    pub bar_gap: u16,
+    pub attack: f32,
+    pub release: f32,
+    pub peaks: bool,
+    pub peak_hold: f32,
+    pub peak_gravity: f32,
+    pub interpolate: bool,
+}
+
+impl Default for VisualizerSettings {
+    fn default() -> Self {
+        let ballistics = BallisticsConfig::default();
+        let bars = BarsConfig::default();
+        VisualizerSettings {
+            mode: VisualizerMode::Bars,
+            colormap: Colormap::Viridis,
+            theme: DEFAULT_THEME.to_string(),
+            min_db: DEFAULT_MIN_DB,
+            max_db: DEFAULT_MAX_DB,
+            width: bars.area_width,
+            height: bars.area_height,
+            bar_style: bars.style,
+            bar_width: bars.width,
+            bar_gap: bars.gap,
+            attack: ballistics.attack,
+            release: ballistics.release,
+            peaks: ballistics.peaks,
+            peak_hold: ballistics.peak_hold,
+            peak_gravity: ballistics.peak_gravity,
+            interpolate: ballistics.interpolate,
+        }
+    }
+}
+
+impl Config {
+
+    //設定ファイル(pathか$XDG_CONFIG_HOME/ferria/config.toml)に環境変数を重ねて読み込む
+    //pathを指定したときだけ、ファイルが無いとエラーにする
+    pub fn load(path: Option<&Path>) -> Result<Self, FerriaError> {
+
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => config_dir().map(|dir| dir.join(CONFIG_FILE)).filter(|path| path.is_file()),
+        };
+
+        let text = match &path {
+            Some(path) => fs::read_to_string(path)
+            .map_err(|e| FerriaError::ConfigError(format!("Cannot read {}: {}", path.display(), e)))?,
+            None => String::new(),
+        };
+
+        let source = path.map(|path| path.display().to_string()).unwrap_or_else(|| CONFIG_FILE.to_string());
+
+        Self::from_sources(&text, &source, std::env::vars())
+
+    }
+
+    //sourceはエラーメッセージに出す設定ファイルの名前
+    pub fn from_sources<I: IntoIterator<Item = (String, String)>>(text: &str, source: &str, env: I) -> Result<Self, FerriaError> {
+
+        //ファイルの誤りは行番号付きで報告するため、先にファイルだけで読んでみる
+        toml::from_str::<Config>(text)
+        .map_err(|e| FerriaError::ConfigError(format!("{}: {}", source, e)))?;
+
+        let mut table: toml::Table = toml::from_str(text)
+        .map_err(|e| FerriaError::ConfigError(format!("{}: {}", source, e)))?;
+
+        let mut env: Vec<(String, String)> = env.into_iter().collect();
+        env.sort();
+
+        for (name, value) in env {
+
+            let Some((section, key)) = env_key(&name) else { continue };
+
+            //どの環境変数が誤っているか分かるように1つずつ確かめる
+            let parsed = env_value(&value);
+            let is_string = parsed.is_str();
+            let result = set_env_value(&mut table, &section, &key, parsed);
+
+            //文字列の項目に数値のように読める値(テーマ名の2077やinfなど)を渡した場合は、文字列として読み直す
+            if let Err(e) = result
+                && (is_string || set_env_value(&mut table, &section, &key, toml::Value::String(value)).is_err()) {
+                return Err(FerriaError::ConfigError(format!("{}: {}", name, e.message())));
+            }
+        }
+
+        let config = toml::Value::Table(table).try_into::<Config>()
+        .map_err(|e| FerriaError::ConfigError(format!("{}: {}", source, e.message())))?;
+
+        config.validate()?;
+
+        Ok(config)
+
+    }
+
+    //範囲外の値や、同じキーが複数のコマンドに割り当てられていないかを確かめる
+    pub fn validate(&self) -> Result<(), FerriaError> {
+
+        let analyzer = &self.analyzer;
+        if analyzer.fft_size == 0 || !analyzer.fft_size.is_power_of_two() {
+            return Err(invalid("analyzer.fft_size", "must be a power of two", analyzer.fft_size));
+        }
+        if analyzer.hop == Some(0) {
+            return Err(invalid("analyzer.hop", "must be at least 1", 0));
+        }
+        check_range("analyzer.overlap", analyzer.overlap, 0.0, 0.95)?;
+        if !(1..=1024).contains(&analyzer.band_count) {
+            return Err(invalid("analyzer.band_count", "must be between 1 and 1024", analyzer.band_count));
+        }
+        check_range("analyzer.agc_release", analyzer.agc_release, 0.0, 60.0)?;
+
+        let player = &self.player;
+        check_range("player.volume", player.volume, 0.0, 1.0)?;
+        check_range("player.volume_step", player.volume_step, 0.01, 1.0)?;
+        check_range("player.seek_step", player.seek_step, 0.1, 600.0)?;
+        check_range("player.crossfade", player.crossfade, 0.0, 30.0)?;
+
+        let visualizer = &self.visualizer;
+        if visualizer.theme.is_empty() {
+            return Err(FerriaError::ConfigError("visualizer.theme must not be empty".to_string()));
+        }
+        check_range("visualizer.min_db", visualizer.min_db, -200.0, 0.0)?;
+        check_range("visualizer.max_db", visualizer.max_db, -200.0, 20.0)?;
+        if visualizer.min_db >= visualizer.max_db {
+            return Err(FerriaError::ConfigError(format!("visualizer.min_db ({}) must be lower than visualizer.max_db ({})", visualizer.min_db, visualizer.max_db)));
+        }
+        check_range("visualizer.width", visualizer.width, 0.1, 1.0)?;
+        check_range("visualizer.height", visualizer.height, 0.1, 1.0)?;
+        if !(1..=16).contains(&visualizer.bar_width) {
+            return Err(invalid("visualizer.bar_width", "must be between 1 and 16", visualizer.bar_width));
+        }
+        if visualizer.bar_gap > 16 {
+            return Err(invalid("visualizer.bar_gap", "must be between 0 and 16", visualizer.bar_gap));
+        }
+        check_range("visualizer.attack", visualizer.attack, 0.0, 10.0)?;
+        check_range("visualizer.release", visualizer.release, 0.0, 10.0)?;
+        check_range("visualizer.peak_hold", visualizer.peak_hold, 0.0, 10.0)?;
+        if !visualizer.peak_gravity.is_finite() || visualizer.peak_gravity <= 0.0 {
+            return Err(invalid("visualizer.peak_gravity", "must be a positive number", visualizer.peak_gravity));
+        }
+
+        self.keys.validate().map_err(FerriaError::ConfigError)?;
+
+        Ok(())
+
+    }
+
+    pub fn hop_size(&self) -> usize {
+        self.analyzer.hop.unwrap_or_else(|| AnalyzerConfig::hop_from_overlap(self.analyzer.fft_size, self.analyzer.overlap))
+    }
+
+    pub fn analyzer_config(&self) -> AnalyzerConfig {
+        AnalyzerConfig {
+            fft_size: self.analyzer.fft_size,
+            hop_size: self.hop_size(),
+            band_layout: BandLayout { scale: self.analyzer.bands, count: self.analyzer.band_count },
+            window: self.analyzer.window,
+            scale: self.analyzer.scale,
+            weighting: self.analyzer.weighting,
+            level: self.analyzer.level,
+            agc_release: self.analyzer.agc_release,
+        }
+    }
+
+    pub fn player_config(&self) -> PlayerConfig {
+        PlayerConfig {
+            volume: self.player.volume,
+            volume_step: self.player.volume_step,
+            crossfade: Duration::from_secs_f32(self.player.crossfade),
+        }
+    }
+
+    pub fn seek_step(&self) -> Duration {
+        Duration::from_secs_f32(self.player.seek_step)
+    }
+
+    pub fn visualizer_config(&self) -> VisualizerConfig {
+        let visualizer = &self.visualizer;
+        VisualizerConfig {
+            ballistics: BallisticsConfig {
+                attack: visualizer.attack,
+                release: visualizer.release,
+                peaks: visualizer.peaks,
+                peak_hold: visualizer.peak_hold,
+                peak_gravity: visualizer.peak_gravity,
+                interpolate: visualizer.interpolate,
+            },
+            bars: BarsConfig {
+                style: visualizer.bar_style,
+                width: visualizer.bar_width,
+                gap: visualizer.bar_gap,
+                area_width: visualizer.width,
+                area_height: visualizer.height,
+            },
+            levels: LevelRange { min_db: visualizer.min_db, max_db: visualizer.max_db },
+        }
+    }
+
+}
+
+fn invalid<T: Display>(key: &str, reason: &str, value: T) -> FerriaError {
+    FerriaError::ConfigError(format!("{} {}, got {}", key, reason, value))
+}
+
+fn check_range(key: &str, value: f32, min: f32, max: f32) -> Result<(), FerriaError> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(invalid(key, &format!("must be between {} and {}", min, max), value))
+    }
+}
+
+//FERRIA_ANALYZER_FFT_SIZE -> ("analyzer", "fft_size")。セクション名で始まらないもの(FERRIA_COLORSなど)は無視する
+fn env_key(name: &str) -> Option<(String, String)> {
+
+    let rest = name.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+    let (section, key) = rest.split_once('_')?;
+
+    SECTIONS.contains(&section).then(|| (section.to_string(), key.to_string()))
+
+}
+
+//[section]のkeyに値を入れて、設定全体として読めるか確かめる
+fn set_env_value(table: &mut toml::Table, section: &str, key: &str, value: toml::Value) -> Result<(), toml::de::Error> {
+
+    let section_table = table.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if let toml::Value::Table(section_table) = section_table {
+        section_table.insert(key.to_string(), value);
+    }
+
+    toml::Value::Table(table.clone()).try_into::<Config>().map(|_| ())
+
+}
+
+//環境変数の値をTOMLの値として読む(2048, 0.5, true, ["q", "esc"])。読めなければ文字列として扱う
+fn env_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+    .ok()
+    .and_then(|mut table| table.remove("value"))
+    .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+//コマンドライン引数と同じ名前で列挙型を読む
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(deserializer: D) -> Result<T, D::Error> {
+
+    let value = String::deserialize(deserializer)?;
+
+    T::from_str(&value, true).map_err(|_| {
+        let names: Vec<String> = T::value_variants().iter()
+        .filter_map(|variant| variant.to_possible_value())
+        .map(|possible| possible.get_name().to_string())
+        .collect();
+        de::Error::custom(format!("unknown value `{}` (expected one of: {})", value, names.join(", ")))
+    })
+
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where D: Deserializer<'de>,
+      T: FromStr<Err = String>,
+{
+    String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+}
+
+//[keys]の表。presetを先に当ててから、書かれた操作のキーだけを置き換える
+fn keymap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Keymap, D::Error> {
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Keys {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let mut entries = BTreeMap::<String, Keys>::deserialize(deserializer)?;
+
+    let preset = match entries.remove("preset") {
+        None => KeyPreset::default(),
+        Some(Keys::One(name)) => KeyPreset::from_str(&name, true)
+        .map_err(|_| de::Error::custom(format!("unknown preset `{}` (expected one of: default, vim, emacs)", name)))?,
+        Some(Keys::Many(_)) => return Err(de::Error::custom("keys.preset must be a single name")),
+    };
+
+    let mut keymap = Keymap::preset(preset);
+
+    for (name, keys) in entries {
+        let action: Action = name.parse().map_err(de::Error::custom)?;
+        let names = match keys {
+            Keys::One(name) => vec![name],
+            Keys::Many(names) => names,
+        };
+        let sequences = names.iter()
+        .map(|name| name.parse::<KeySequence>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| de::Error::custom(format!("keys.{}: {}", action, e)))?;
+        keymap.bind(action, sequences);
+    }
+
+    Ok(keymap)
+
+}
+
+#[cfg(test)]
+mod test_config {
+
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_config_dir_from_env() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| move |name: &str| {
+            pairs.iter().find(|(key, _)| *key == name).map(|(_, value)| PathBuf::from(value))
+        };
+
+        assert_eq!(config_dir_from_env(env(&[("XDG_CONFIG_HOME", "/xdg"), ("HOME", "/home/a")])), Some(PathBuf::from("/xdg/ferria")));
+        assert_eq!(config_dir_from_env(env(&[("XDG_CONFIG_HOME", "relative"), ("HOME", "/home/a")])), Some(PathBuf::from("/home/a/.config/ferria")));
+        assert_eq!(config_dir_from_env(env(&[])), None);
+    }
+
+    #[test]
+    fn test_defaults_match_components() {
+        let config = Config::from_sources("", CONFIG_FILE, Vec::new()).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.analyzer_config().hop_size, 256);
+        assert_eq!(config.visualizer_config(), VisualizerConfig::default());
+        assert_eq!(config.player_config(), PlayerConfig::default());
+    }
+
+    #[test]
+    fn test_file_then_env_layers() {
+        let text = r#"
+            [analyzer]
+            fft_size = 4096
+            window = "kaiser:5"
+            bands = "third-octave"
+
+            [visualizer]
+            mode = "spectrogram"
+            min_db = -90
+            theme = "fire"
+
+            [keys]
+            preset = "vim"
+            quit = ["q", "g g"]
+        "#;
+
+        let config = Config::from_sources(text, CONFIG_FILE, env(&[
+            ("FERRIA_ANALYZER_FFT_SIZE", "2048"),
+            ("FERRIA_VISUALIZER_THEME", "ocean"),
+            ("FERRIA_PLAYER_VOLUME", "0.5"),
+            ("FERRIA_KEYS_TOGGLE_PAUSE", "space"),
+            ("FERRIA_COLORS", "256"),
+            ("HOME", "/home/a"),
+        ])).unwrap();
+
+        assert_eq!(config.analyzer.fft_size, 2048);
+        assert_eq!(config.analyzer.window, WindowFunction::Kaiser { beta: 5.0 });
+        assert_eq!(config.analyzer.bands, BandScale::ThirdOctave);
+        assert_eq!(config.visualizer.mode, VisualizerMode::Spectrogram);
+        assert_eq!(config.visualizer.theme, "ocean");
+        assert_eq!(config.visualizer_config().levels.min_db, -90.0);
+        assert_eq!(config.player.volume, 0.5);
+        assert_eq!(config.keys.sequences(Action::Quit), ["q".parse().unwrap(), "g g".parse().unwrap()]);
+        assert_eq!(config.keys.sequences(Action::TogglePause).len(), 1);
+        //vimの割り当てもそのまま残る
+        assert_eq!(config.keys.sequences(Action::VolumeUp).len(), 2);
+    }
+
+    #[test]
+    fn test_env_values_follow_the_field_type() {
+        //文字列の項目は数値のように読める値でも文字列として扱う
+        for theme in ["2077", "inf", "true"] {
+            let config = Config::from_sources("", CONFIG_FILE, env(&[("FERRIA_VISUALIZER_THEME", theme)])).unwrap();
+            assert_eq!(config.visualizer.theme, theme);
+        }
+
+        //数値の項目に数値として読めない値を渡したら、その環境変数の名前で報告する
+        let error = Config::from_sources("", CONFIG_FILE, env(&[("FERRIA_ANALYZER_FFT_SIZE", "big")])).unwrap_err().to_string();
+        assert!(error.contains("FERRIA_ANALYZER_FFT_SIZE"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_config_errors_name_the_source() {
+        let error = |text: &str, pairs: &[(&str, &str)]| Config::from_sources(text, "config.toml", env(pairs)).unwrap_err().to_string();
+
+        assert!(error("[analyzer]\nfft_size = 1000", &[]).contains("analyzer.fft_size"));
+        assert!(error("[analyzer]\nfft_sise = 1024", &[]).contains("config.toml"));
+        assert!(error("[visualizer]\nmode = \"waves\"", &[]).contains("expected one of"));
+        assert!(error("[visualizer]\nmin_db = -10\nmax_db = -20", &[]).contains("min_db"));
+        assert!(error("[keys]\nstop = \"p\"", &[]).contains("keys.toggle_pause and keys.stop"));
+        assert!(error("[keys]\nquit = \"hyper+q\"", &[]).contains("hyper"));
+        assert!(error("[keys]\nplay = \"x\"", &[]).contains("Unknown action"));
+        assert!(error("[keys]\npreset = \"vi\"", &[]).contains("unknown preset"));
+        assert!(error("[keys]\nstop = \"n x\"", &[]).contains("is the start of"));
+        assert!(error("", &[("FERRIA_PLAYER_VOLUME", "loud")]).contains("FERRIA_PLAYER_VOLUME"));
+        assert!(error("", &[("FERRIA_PLAYER_VOLUME", "2")]).contains("player.volume"));
+        assert!(error("", &[("FERRIA_ANALYZER_FFTSIZE", "2048")]).contains("FERRIA_ANALYZER_FFTSIZE"));
+    }
+
+}
@@ -1,6 +1,4 @@
 
-use std::io::ErrorKind;
-use ratatui::crossterm;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,6 +20,9 @@ pub enum FerriaError {
     #[error("Visualizer Error: {0}")]
     VisualizerError(String),
 
+    #[error("Config Error: {0}")]
+    ConfigError(String),
+
     #[error("CLI Error: {0}")]
     CliError(#[from] clap::Error),
 
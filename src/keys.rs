@@ -0,0 +1,490 @@
+use clap::ValueEnum;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::action::{Action, SeekDirection};
+
+//組み合わせの途中でこれより長く間が空いたら、最初から入力し直す
+const CHORD_TIMEOUT: Duration = Duration::from_millis(1500);
+
+//"ctrl+c", "space", "left", "+" のように書くキー1つ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+//名前で書くキー
+const NAMED_KEYS: [(&str, KeyCode); 14] = [
+    ("enter", KeyCode::Enter),
+    ("space", KeyCode::Char(' ')),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("esc", KeyCode::Esc),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("delete", KeyCode::Delete),
+];
+
+impl KeyBinding {
+
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        KeyBinding { code, modifiers }
+    }
+
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        self.same_key(&KeyBinding::from(event))
+    }
+
+    //文字のキーはShiftを押さないと入力できない記号("+"や"?")があるので、Shiftの有無は見ない
+    pub fn same_key(&self, other: &KeyBinding) -> bool {
+
+        let ignored = match self.code {
+            KeyCode::Char(_) => KeyModifiers::SHIFT,
+            _ => KeyModifiers::NONE,
+        };
+
+        self.code == other.code && self.modifiers - ignored == other.modifiers - ignored
+
+    }
+
+    //端末はShift+TabをBackTabとして送ってくる(Shiftが付くかは端末による)ので、BackTab+Shiftにそろえる
+    fn normalized(self) -> Self {
+
+        let back_tab = self.code == KeyCode::BackTab
+            || (self.code == KeyCode::Tab && self.modifiers.contains(KeyModifiers::SHIFT));
+
+        if back_tab {
+            KeyBinding { code: KeyCode::BackTab, modifiers: self.modifiers | KeyModifiers::SHIFT }
+        } else {
+            self
+        }
+
+    }
+
+}
+
+impl From<&KeyEvent> for KeyBinding {
+    fn from(event: &KeyEvent) -> Self {
+        KeyBinding::new(event.code, event.modifiers).normalized()
+    }
+}
+
+impl FromStr for KeyBinding {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        //"+"そのものと"ctrl++"は最後の"+"をキーとして扱う
+        let (prefix, key) = match s.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest.strip_suffix('+').unwrap_or(rest), "+"),
+            _ => match s.rsplit_once('+') {
+                Some((prefix, key)) => (prefix, key),
+                None => ("", s),
+            },
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+
+        for name in prefix.split('+').filter(|name| !name.is_empty()) {
+            modifiers |= match name.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier `{}` in `{}` (ctrl, alt, shift)", name, s)),
+            };
+        }
+
+        let lower = key.to_ascii_lowercase();
+        let mut chars = key.chars();
+
+        let code = if let Some((_, code)) = NAMED_KEYS.iter().find(|(name, _)| *name == lower) {
+            *code
+        } else if lower == "backtab" {
+            KeyCode::BackTab
+        } else if let (Some(c), None) = (chars.next(), chars.next()) {
+            KeyCode::Char(c)
+        } else if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()).filter(|n| (1..=12).contains(n)) {
+            KeyCode::F(n)
+        } else {
+            return Err(format!("Unknown key `{}` in `{}`", key, s));
+        };
+
+        Ok(KeyBinding { code, modifiers }.normalized())
+
+    }
+
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        for (modifier, name) in [(KeyModifiers::CONTROL, "ctrl"), (KeyModifiers::ALT, "alt"), (KeyModifiers::SHIFT, "shift")] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+
+        match NAMED_KEYS.iter().find(|(_, code)| *code == self.code) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => match self.code {
+                KeyCode::Char(c) => write!(f, "{}", c),
+                KeyCode::F(n) => write!(f, "f{}", n),
+                //Shiftは上で書いているので"shift+tab"になる
+                KeyCode::BackTab => write!(f, "tab"),
+                other => write!(f, "{:?}", other),
+            },
+        }
+
+    }
+}
+
+//順に押すキーの組み合わせ。"g t" や "ctrl+x ctrl+c" のように空白で区切って書く
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySequence(Vec<KeyBinding>);
+
+impl KeySequence {
+
+    pub fn keys(&self) -> &[KeyBinding] {
+        &self.0
+    }
+
+    //pressedがこの組み合わせの先頭と一致するか(全体と一致する場合も含む)
+    fn starts_with(&self, pressed: &[KeyBinding]) -> bool {
+        pressed.len() <= self.0.len() && self.0.iter().zip(pressed).all(|(key, pressed)| key.same_key(pressed))
+    }
+
+}
+
+impl FromStr for KeySequence {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        let keys = s.split_whitespace().map(str::parse).collect::<Result<Vec<KeyBinding>, _>>()?;
+
+        if keys.is_empty() {
+            return Err("Key binding must not be empty".to_string());
+        }
+
+        Ok(KeySequence(keys))
+
+    }
+
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self.0.iter().map(KeyBinding::to_string).collect();
+        write!(f, "{}", keys.join(" "))
+    }
+}
+
+//既定のキーに加える割り当て
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum KeyPreset {
+    #[default]
+    Default,
+    ///h/j/k/lでシークと音量、"g t"/"g T"で曲送り、"Z Z"や": q"で終了
+    Vim,
+    ///ctrl+f/ctrl+bでシーク、ctrl+n/ctrl+pで曲送り、"ctrl+x ctrl+c"で終了
+    Emacs,
+}
+
+const DEFAULT_KEYS: [(Action, &[&str]); 15] = [
+    (Action::TogglePause, &["enter", "space", "p"]),
+    (Action::Stop, &["s"]),
+    (Action::VolumeUp, &["+"]),
+    (Action::VolumeDown, &["-"]),
+    (Action::Seek(SeekDirection::Forward), &["right"]),
+    (Action::Seek(SeekDirection::Backward), &["left"]),
+    (Action::NextTrack, &["n"]),
+    (Action::PreviousTrack, &["b"]),
+    (Action::ToggleRepeat, &["r"]),
+    (Action::ToggleShuffle, &["z"]),
+    (Action::CycleVisualizer, &["v"]),
+    (Action::CycleColormap, &["m"]),
+    (Action::CycleTheme, &["t"]),
+    (Action::ToggleHelp, &["?"]),
+    (Action::Quit, &["q", "ctrl+q", "ctrl+c"]),
+];
+
+const VIM_KEYS: [(Action, &[&str]); 7] = [
+    (Action::VolumeUp, &["k"]),
+    (Action::VolumeDown, &["j"]),
+    (Action::Seek(SeekDirection::Forward), &["l"]),
+    (Action::Seek(SeekDirection::Backward), &["h"]),
+    (Action::NextTrack, &["g t"]),
+    (Action::PreviousTrack, &["g T"]),
+    (Action::Quit, &["Z Z", ": q"]),
+];
+
+const EMACS_KEYS: [(Action, &[&str]); 5] = [
+    (Action::Seek(SeekDirection::Forward), &["ctrl+f"]),
+    (Action::Seek(SeekDirection::Backward), &["ctrl+b"]),
+    (Action::NextTrack, &["ctrl+n"]),
+    (Action::PreviousTrack, &["ctrl+p"]),
+    (Action::Quit, &["ctrl+x ctrl+c"]),
+];
+
+//操作ごとのキーの割り当て
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    //Action::ALLの順に並べる
+    bindings: Vec<(Action, Vec<KeySequence>)>,
+}
+
+//押されたキーの組み合わせを引いた結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Matched(Action),
+    //続けて押すキーがある
+    Prefix,
+    Unbound,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset(KeyPreset::Default)
+    }
+}
+
+impl Keymap {
+
+    pub fn preset(preset: KeyPreset) -> Self {
+
+        let mut keymap = Keymap { bindings: Action::ALL.iter().map(|&action| (action, Vec::new())).collect() };
+
+        let extra: &[(Action, &[&str])] = match preset {
+            KeyPreset::Default => &[],
+            KeyPreset::Vim => &VIM_KEYS,
+            KeyPreset::Emacs => &EMACS_KEYS,
+        };
+
+        for (action, keys) in DEFAULT_KEYS.iter().chain(extra) {
+            if let Some((_, sequences)) = keymap.bindings.iter_mut().find(|(bound, _)| bound == action) {
+                sequences.extend(keys.iter().filter_map(|key| key.parse().ok()));
+            }
+        }
+
+        keymap
+
+    }
+
+    //actionのキーを置き換える。空にすると割り当てを外す
+    pub fn bind(&mut self, action: Action, sequences: Vec<KeySequence>) {
+        if let Some((_, bound)) = self.bindings.iter_mut().find(|(bound, _)| *bound == action) {
+            *bound = sequences;
+        }
+    }
+
+    pub fn sequences(&self, action: Action) -> &[KeySequence] {
+        self.bindings.iter()
+        .find(|(bound, _)| *bound == action)
+        .map(|(_, sequences)| sequences.as_slice())
+        .unwrap_or_default()
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (Action, &[KeySequence])> {
+        self.bindings.iter().map(|(action, sequences)| (*action, sequences.as_slice()))
+    }
+
+    //同じキーが2つの操作に割り当てられていたり、ある組み合わせが別の組み合わせの途中までと同じだと区別できない
+    pub fn validate(&self) -> Result<(), String> {
+
+        let all: Vec<(Action, &KeySequence)> = self.bindings()
+        .flat_map(|(action, sequences)| sequences.iter().map(move |sequence| (action, sequence)))
+        .collect();
+
+        for (i, (action, sequence)) in all.iter().enumerate() {
+            for (other_action, other) in &all[i + 1..] {
+                if sequence.keys().len() == other.keys().len() && sequence.starts_with(other.keys()) {
+                    return Err(format!("Key `{}` is bound to both keys.{} and keys.{}", sequence, action, other_action));
+                }
+                let (short, long, short_action, long_action) = if sequence.keys().len() < other.keys().len() {
+                    (sequence, other, action, other_action)
+                } else {
+                    (other, sequence, other_action, action)
+                };
+                if long.starts_with(short.keys()) {
+                    return Err(format!("Key `{}` (keys.{}) is the start of `{}` (keys.{})", short, short_action, long, long_action));
+                }
+            }
+        }
+
+        Ok(())
+
+    }
+
+    pub fn lookup(&self, pressed: &[KeyBinding]) -> Lookup {
+
+        let mut prefix = false;
+
+        for (action, sequences) in self.bindings() {
+            for sequence in sequences {
+                if sequence.starts_with(pressed) {
+                    if sequence.keys().len() == pressed.len() {
+                        return Lookup::Matched(action);
+                    }
+                    prefix = true;
+                }
+            }
+        }
+
+        if prefix { Lookup::Prefix } else { Lookup::Unbound }
+
+    }
+
+}
+
+//キーを1つずつ受け取って、組み合わせが揃ったら操作を返す
+#[derive(Debug, Clone, Default)]
+pub struct ChordReader {
+    pending: Vec<KeyBinding>,
+    last_key: Option<Instant>,
+}
+
+impl ChordReader {
+
+    pub fn feed(&mut self, keymap: &Keymap, event: &KeyEvent, now: Instant) -> Option<Action> {
+
+        if self.last_key.is_some_and(|last| now.saturating_duration_since(last) > CHORD_TIMEOUT) {
+            self.pending.clear();
+        }
+        self.last_key = Some(now);
+
+        self.pending.push(KeyBinding::from(event));
+
+        match keymap.lookup(&self.pending) {
+            Lookup::Matched(action) => {
+                self.pending.clear();
+                Some(action)
+            },
+            Lookup::Prefix => None,
+            Lookup::Unbound => {
+                //組み合わせの途中で続かないキーが押されたら、そのキーだけでもう一度引く
+                let retry = self.pending.len() > 1;
+                self.pending.clear();
+                if retry { self.feed(keymap, event, now) } else { None }
+            },
+        }
+
+    }
+
+    //入力途中の組み合わせ
+    pub fn pending(&self) -> &[KeyBinding] {
+        &self.pending
+    }
+
+}
+
+#[cfg(test)]
+mod test_keys {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_key_binding() {
+        assert_eq!("q".parse(), Ok(KeyBinding::new(KeyCode::Char('q'), KeyModifiers::NONE)));
+        assert_eq!("Ctrl+c".parse(), Ok(KeyBinding::new(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert_eq!("ctrl+alt+left".parse(), Ok(KeyBinding::new(KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT)));
+        assert_eq!("+".parse(), Ok(KeyBinding::new(KeyCode::Char('+'), KeyModifiers::NONE)));
+        assert_eq!("ctrl++".parse(), Ok(KeyBinding::new(KeyCode::Char('+'), KeyModifiers::CONTROL)));
+        assert_eq!("F5".parse(), Ok(KeyBinding::new(KeyCode::F(5), KeyModifiers::NONE)));
+
+        assert!("hyper+x".parse::<KeyBinding>().is_err());
+        assert!("f13".parse::<KeyBinding>().is_err());
+        assert!("ctrl+".parse::<KeyBinding>().is_err());
+
+        for text in ["ctrl+c", "space", "alt+f4", "shift+tab"] {
+            assert_eq!(text.parse::<KeyBinding>().unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_key_binding_matches_event() {
+        let plus: KeyBinding = "+".parse().unwrap();
+        assert!(plus.matches(&KeyEvent::new(KeyCode::Char('+'), KeyModifiers::SHIFT)));
+        assert!(!plus.matches(&KeyEvent::new(KeyCode::Char('+'), KeyModifiers::CONTROL)));
+
+        let quit: KeyBinding = "ctrl+q".parse().unwrap();
+        assert!(quit.matches(&KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL)));
+        assert!(!quit.matches(&KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE)));
+
+        //Shift+TabはBackTabとして届く
+        let back: KeyBinding = "shift+tab".parse().unwrap();
+        assert_eq!("backtab".parse(), Ok(back));
+        assert!(back.matches(&KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT)));
+        assert!(back.matches(&KeyEvent::new(KeyCode::BackTab, KeyModifiers::NONE)));
+        assert!(back.matches(&KeyEvent::new(KeyCode::Tab, KeyModifiers::SHIFT)));
+        assert!(!back.matches(&KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE)));
+    }
+
+    fn press(reader: &mut ChordReader, keymap: &Keymap, keys: &str, now: Instant) -> Vec<Option<Action>> {
+        keys.split_whitespace()
+        .map(|key| {
+            let key: KeyBinding = key.parse().unwrap();
+            reader.feed(keymap, &KeyEvent::new(key.code, key.modifiers), now)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_default_keymap_quits_with_q() {
+        let keymap = Keymap::default();
+        assert!(keymap.validate().is_ok());
+        assert_eq!(keymap.lookup(&["q".parse().unwrap()]), Lookup::Matched(Action::Quit));
+        assert_eq!(keymap.lookup(&["ctrl+c".parse().unwrap()]), Lookup::Matched(Action::Quit));
+        assert_eq!(keymap.lookup(&["?".parse().unwrap()]), Lookup::Matched(Action::ToggleHelp));
+    }
+
+    #[test]
+    fn test_chords() {
+        let now = Instant::now();
+
+        let vim = Keymap::preset(KeyPreset::Vim);
+        assert!(vim.validate().is_ok());
+        let mut reader = ChordReader::default();
+        assert_eq!(press(&mut reader, &vim, "g t", now), vec![None, Some(Action::NextTrack)]);
+        assert_eq!(press(&mut reader, &vim, "Z", now), vec![None]);
+        assert_eq!(reader.pending().len(), 1);
+        assert_eq!(press(&mut reader, &vim, "Z", now), vec![Some(Action::Quit)]);
+
+        //続かないキーが来たら、そのキーだけで引き直す
+        assert_eq!(press(&mut reader, &vim, "g k", now), vec![None, Some(Action::VolumeUp)]);
+
+        //間が空いたら組み合わせは途切れる
+        press(&mut reader, &vim, "g", now);
+        assert_eq!(press(&mut reader, &vim, "t", now + CHORD_TIMEOUT * 2), vec![Some(Action::CycleTheme)]);
+
+        let emacs = Keymap::preset(KeyPreset::Emacs);
+        assert!(emacs.validate().is_ok());
+        assert_eq!(press(&mut reader, &emacs, "ctrl+x ctrl+c", now), vec![None, Some(Action::Quit)]);
+    }
+
+    #[test]
+    fn test_keymap_validation() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Action::Stop, vec!["p".parse().unwrap()]);
+        assert!(keymap.validate().unwrap_err().contains("keys.toggle_pause and keys.stop"));
+
+        let mut keymap = Keymap::default();
+        keymap.bind(Action::NextTrack, vec!["s s".parse().unwrap()]);
+        assert!(keymap.validate().unwrap_err().contains("is the start of"));
+
+        keymap.bind(Action::Stop, Vec::new());
+        assert!(keymap.validate().is_ok());
+        assert!(keymap.sequences(Action::Stop).is_empty());
+    }
+
+}
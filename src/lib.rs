@@ -4,6 +4,10 @@ pub mod audio;
 pub mod error;
 pub mod visualizer;
 pub mod app;
+pub mod cli;
+pub mod action;
+pub mod config;
+pub mod keys;
+pub mod ui;
 
-// pub mod Visualizer;
-// pub mod cli;
\ No newline at end of file
+// pub mod Visualizer;
\ No newline at end of file
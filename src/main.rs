@@ -1,10 +1,21 @@
-use std::{fmt::format, io::Error, path::PathBuf, sync::mpsc, thread, time::Duration};
-use ferria::{app::FerriaApp, visualizer, audio::{analyzer::{AudioAnalyzer, SpectrumData}, loader::AudioTrack, player::{AudioPlayer, PlaybackStatus}} ,error::FerriaError};
+use clap::Parser;
+use ferria::{app::FerriaApp, audio::offline, cli::Cli, config::Config, error::FerriaError};
 
 
 fn main() -> Result<(), FerriaError> {
 
-    let mut app = FerriaApp::new()?;
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+    let options = cli.into_app_options(config)?;
+
+    //--dumpのときは再生せずに解析結果だけを書き出す
+    if let Some(dump) = &options.dump {
+        let frames = offline::dump_track(&options.tracks[0], options.config.analyzer_config(), dump)?;
+        println!("Wrote {} frames to {}", frames, dump.path.display());
+        return Ok(());
+    }
+
+    let mut app = FerriaApp::new(options)?;
     app.run()?;
 
     Ok(())
@@ -0,0 +1,418 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use base64::Engine;
+use image::{imageops::FilterType, RgbImage};
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Block, Borders},
+};
+
+use crate::audio::cover::{self, CoverArt};
+
+//端末のセルのピクセル数が分からないときの値
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+//kittyのグラフィックスプロトコルで1回に送るbase64の長さ
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+//画像を描く方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    //上下2ピクセルを▀の前景色と背景色で描く(truecolorの端末ならどこでも使える)
+    HalfBlocks,
+    Sixel,
+    Kitty,
+}
+
+impl GraphicsProtocol {
+
+    //環境変数から端末が対応している方式を推測する。FERRIA_GRAPHICSで上書きできる
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+
+        match var("FERRIA_GRAPHICS").as_deref() {
+            Some("kitty") => return GraphicsProtocol::Kitty,
+            Some("sixel") => return GraphicsProtocol::Sixel,
+            Some("halfblocks") => return GraphicsProtocol::HalfBlocks,
+            _ => {},
+        }
+
+        //tmuxなどを挟むとエスケープシーケンスがそのまま届かない
+        if var("TMUX").is_some() {
+            return GraphicsProtocol::HalfBlocks;
+        }
+
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+
+        if var("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "WezTerm" || program == "ghostty" {
+            GraphicsProtocol::Kitty
+        }
+        else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") || program == "iTerm.app" {
+            GraphicsProtocol::Sixel
+        }
+        else {
+            GraphicsProtocol::HalfBlocks
+        }
+
+    }
+
+}
+
+//サイドパネルのカバー画像
+//デコードした画像と、そこから作ったヴィジュアライザー用の色をトラックが変わるまで保持する
+pub struct CoverArtView {
+    protocol: GraphicsProtocol,
+    //表示中の画像の元データ(同じトラックなら作り直さない)
+    source: Option<Arc<[u8]>>,
+    image: Option<RgbImage>,
+    palette: Option<Vec<(u8, u8, u8)>>,
+    //sixel/kittyで最後に描いた領域
+    placed: Option<Rect>,
+    //terminal.drawの後に書き出すエスケープシーケンス
+    pending: Option<String>,
+}
+
+impl CoverArtView {
+
+    pub fn new(protocol: GraphicsProtocol) -> Self {
+        CoverArtView { protocol, source: None, image: None, palette: None, placed: None, pending: None }
+    }
+
+    //トラックのカバー画像を差し替える。画像が変わったらtrueを返す
+    //デコードできない画像は無いものとして扱う
+    pub fn set_cover(&mut self, cover: Option<&CoverArt>) -> bool {
+
+        let source = cover.map(|cover| cover.data.clone());
+        if source == self.source {
+            return false;
+        }
+
+        self.image = cover.and_then(|cover| cover.decode().ok());
+        self.palette = self.image.as_ref().map(cover::artwork_palette);
+        self.source = source;
+        //同じ場所でも描き直す
+        self.placed = None;
+
+        true
+
+    }
+
+    pub fn has_image(&self) -> bool {
+        self.image.is_some()
+    }
+
+    pub fn palette(&self) -> Option<Vec<(u8, u8, u8)>> {
+        self.palette.clone()
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame, area: Rect) {
+
+        let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Cover");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let Some(image) = &self.image else {
+            self.clear_placement();
+            return;
+        };
+
+        if inner.width == 0 || inner.height == 0 {
+            return;
+        }
+
+        match self.protocol {
+            GraphicsProtocol::HalfBlocks => {
+                draw_half_blocks(frame, inner, image);
+            },
+            GraphicsProtocol::Sixel | GraphicsProtocol::Kitty => {
+                //ratatuiに上書きされないように、画像を置くセルは描画を飛ばしてもらう
+                for y in inner.top()..inner.bottom() {
+                    for x in inner.left()..inner.right() {
+                        frame.buffer_mut()[(x, y)].set_skip(true);
+                    }
+                }
+
+                if self.placed != Some(inner) {
+                    self.pending = Some(self.graphics_sequence(image, inner));
+                    self.placed = Some(inner);
+                }
+            },
+        }
+
+    }
+
+    //terminal.drawの後に呼んで、sixel/kittyの画像を端末に直接書き出す
+    pub fn write_graphics<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+
+        if let Some(sequence) = self.pending.take() {
+            out.write_all(sequence.as_bytes())?;
+            out.flush()?;
+        }
+
+        Ok(())
+
+    }
+
+    //画面を消したときに呼ぶ。次のdrawで画像を置き直す
+    pub fn invalidate(&mut self) {
+        self.placed = None;
+    }
+
+    fn clear_placement(&mut self) {
+        if self.placed.take().is_some() && self.protocol == GraphicsProtocol::Kitty {
+            self.pending = Some(kitty_delete());
+        }
+    }
+
+    fn graphics_sequence(&self, image: &RgbImage, area: Rect) -> String {
+
+        let (cell_width, cell_height) = cell_size();
+        let fitted = fit_image(image, area.width as u32 * cell_width, area.height as u32 * cell_height);
+
+        //画像を領域の中央に置く
+        let columns = (fitted.width() / cell_width).max(1) as u16;
+        let rows = (fitted.height() / cell_height).max(1) as u16;
+        let x = area.left() + area.width.saturating_sub(columns) / 2;
+        let y = area.top() + area.height.saturating_sub(rows) / 2;
+
+        let mut sequence = String::new();
+
+        if self.protocol == GraphicsProtocol::Kitty {
+            sequence.push_str(&kitty_delete());
+        }
+
+        sequence.push_str("\x1b7");
+        sequence.push_str(&format!("\x1b[{};{}H", y + 1, x + 1));
+
+        match self.protocol {
+            GraphicsProtocol::Kitty => sequence.push_str(&encode_kitty(&fitted, columns, rows)),
+            _ => sequence.push_str(&encode_sixel(&fitted)),
+        }
+
+        sequence.push_str("\x1b8");
+
+        sequence
+
+    }
+
+}
+
+//1セルに縦2ピクセルを描く
+fn draw_half_blocks(frame: &mut Frame, area: Rect, image: &RgbImage) {
+
+    let fitted = fit_image(image, area.width as u32, area.height as u32 * 2);
+
+    let offset_x = area.left() + (area.width - fitted.width() as u16) / 2;
+    let offset_y = area.top() + (area.height - fitted.height().div_ceil(2) as u16) / 2;
+
+    for row in 0..fitted.height().div_ceil(2) {
+        for column in 0..fitted.width() {
+
+            let [r, g, b] = fitted.get_pixel(column, row * 2).0;
+            let mut style = Style::default().fg(Color::Rgb(r, g, b));
+
+            if row * 2 + 1 < fitted.height() {
+                let [r, g, b] = fitted.get_pixel(column, row * 2 + 1).0;
+                style = style.bg(Color::Rgb(r, g, b));
+            }
+
+            frame.buffer_mut().set_string(offset_x + column as u16, offset_y + row as u16, "▀", style);
+        }
+    }
+
+}
+
+//縦横比を保ったまま幅width、高さheightに収める
+fn fit_image(image: &RgbImage, width: u32, height: u32) -> RgbImage {
+
+    let scale = (width as f32 / image.width() as f32).min(height as f32 / image.height() as f32);
+    let fitted_width = ((image.width() as f32 * scale) as u32).clamp(1, width.max(1));
+    let fitted_height = ((image.height() as f32 * scale) as u32).clamp(1, height.max(1));
+
+    image::imageops::resize(image, fitted_width, fitted_height, FilterType::Triangle)
+
+}
+
+//端末が報告するウィンドウのピクセル数からセル1つの大きさを求める
+fn cell_size() -> (u32, u32) {
+    match ratatui::crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            ((size.width / size.columns) as u32, (size.height / size.rows) as u32)
+        },
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+fn kitty_delete() -> String {
+    "\x1b_Ga=d,q=2\x1b\\".to_string()
+}
+
+//RGBの生データをbase64にしてチャンクに分けて送る
+pub fn encode_kitty(image: &RgbImage, columns: u16, rows: u16) -> String {
+
+    let payload = base64::engine::general_purpose::STANDARD.encode(image.as_raw());
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    let mut sequence = String::new();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+
+        if i == 0 {
+            sequence.push_str(&format!("\x1b_Ga=T,f=24,s={},v={},c={},r={},q=2,m={};", image.width(), image.height(), columns, rows, more));
+        } else {
+            sequence.push_str(&format!("\x1b_Gm={};", more));
+        }
+
+        sequence.push_str(&String::from_utf8_lossy(chunk));
+        sequence.push_str("\x1b\\");
+    }
+
+    sequence
+
+}
+
+//6x6x6の固定パレットに減色してsixelにする
+pub fn encode_sixel(image: &RgbImage) -> String {
+
+    let level = |v: u8| (v as u32 * 5 + 127) / 255;
+    let index = |pixel: &image::Rgb<u8>| {
+        let [r, g, b] = pixel.0;
+        (level(r) * 36 + level(g) * 6 + level(b)) as usize
+    };
+
+    let mut sequence = format!("\x1bPq\"1;1;{};{}", image.width(), image.height());
+
+    let mut used = [false; 216];
+    for pixel in image.pixels() {
+        used[index(pixel)] = true;
+    }
+
+    //パレットの値は0〜100のパーセント
+    for (color, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        let percent = |v: usize| v * 100 / 5;
+        sequence.push_str(&format!("#{};2;{};{};{}", color, percent(color / 36), percent(color / 6 % 6), percent(color % 6)));
+    }
+
+    //6行ずつの帯ごとに、色ごとのビットパターンを重ね描きする
+    for band in (0..image.height()).step_by(6) {
+
+        let rows = (image.height() - band).min(6);
+        let mut patterns: Vec<(usize, Vec<u8>)> = Vec::new();
+
+        for x in 0..image.width() {
+            for dy in 0..rows {
+                let color = index(image.get_pixel(x, band + dy));
+                let pattern = match patterns.iter_mut().find(|(c, _)| *c == color) {
+                    Some((_, pattern)) => pattern,
+                    None => {
+                        patterns.push((color, vec![0; image.width() as usize]));
+                        &mut patterns.last_mut().unwrap().1
+                    },
+                };
+                pattern[x as usize] |= 1 << dy;
+            }
+        }
+
+        for (color, pattern) in &patterns {
+            sequence.push_str(&format!("#{}", color));
+            push_sixel_run_length(&mut sequence, pattern);
+            //行頭に戻って次の色
+            sequence.push('$');
+        }
+
+        sequence.push('-');
+    }
+
+    sequence.push_str("\x1b\\");
+
+    sequence
+
+}
+
+//同じ文字が続くところは"!回数文字"にまとめる
+fn push_sixel_run_length(sequence: &mut String, pattern: &[u8]) {
+
+    let mut i = 0;
+
+    while i < pattern.len() {
+
+        let bits = pattern[i];
+        let run = pattern[i..].iter().take_while(|&&b| b == bits).count();
+        let character = (0x3F + bits) as char;
+
+        if run > 3 {
+            sequence.push_str(&format!("!{}{}", run, character));
+        } else {
+            sequence.extend(std::iter::repeat_n(character, run));
+        }
+
+        i += run;
+    }
+
+}
+
+#[cfg(test)]
+mod test_cover_art {
+
+    use super::*;
+    use image::Rgb;
+    use ratatui::{backend::TestBackend, Terminal};
+
+    #[test]
+    fn test_detect_protocol() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+
+        assert_eq!(GraphicsProtocol::from_env(env(&[("TERM", "xterm-kitty")])), GraphicsProtocol::Kitty);
+        assert_eq!(GraphicsProtocol::from_env(env(&[("TERM", "foot")])), GraphicsProtocol::Sixel);
+        assert_eq!(GraphicsProtocol::from_env(env(&[("TERM", "xterm-kitty"), ("TMUX", "1")])), GraphicsProtocol::HalfBlocks);
+        assert_eq!(GraphicsProtocol::from_env(env(&[("TERM", "xterm-256color"), ("FERRIA_GRAPHICS", "sixel")])), GraphicsProtocol::Sixel);
+        assert_eq!(GraphicsProtocol::from_env(env(&[])), GraphicsProtocol::HalfBlocks);
+    }
+
+    #[test]
+    fn test_half_blocks_use_two_pixels_per_cell() {
+        //上の行が赤、下の行が青の2x2(縮小しない)
+        let image = RgbImage::from_fn(2, 2, |_, y| if y == 0 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 255]) });
+
+        let mut terminal = Terminal::new(TestBackend::new(2, 1)).unwrap();
+        terminal.draw(|frame| draw_half_blocks(frame, frame.area(), &image)).unwrap();
+
+        let cell = &terminal.backend().buffer()[(0, 0)];
+        assert_eq!(cell.symbol(), "▀");
+        assert_eq!(cell.fg, Color::Rgb(255, 0, 0));
+        assert_eq!(cell.bg, Color::Rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn test_sixel_encoding() {
+        //白1色の4x6ピクセルは1つの帯で"~"(全ビット)が4つ
+        let image = RgbImage::from_pixel(4, 6, Rgb([255, 255, 255]));
+        assert_eq!(encode_sixel(&image), "\x1bPq\"1;1;4;6#215;2;100;100;100#215!4~$-\x1b\\");
+    }
+
+    #[test]
+    fn test_kitty_encoding_is_chunked() {
+        let image = RgbImage::from_pixel(64, 64, Rgb([1, 2, 3]));
+        let sequence = encode_kitty(&image, 8, 4);
+
+        assert!(sequence.starts_with("\x1b_Ga=T,f=24,s=64,v=64,c=8,r=4,q=2,m=1;"));
+        assert!(sequence.ends_with("\x1b\\"));
+        //12288バイトのbase64は16384文字で、4096文字ずつ4回に分けて送る
+        assert_eq!(sequence.matches("\x1b_G").count(), 4);
+        assert_eq!(sequence.matches("m=1;").count(), 3);
+    }
+
+}
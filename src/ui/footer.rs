@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Gauge, Paragraph},
+};
+
+use crate::audio::player::PlaybackStatus;
+use crate::audio::playlist::RepeatMode;
+use crate::ui::notification::{Notification, NotificationLevel};
+use crate::ui::now_playing::format_duration;
+
+pub const FOOTER_HEIGHT: u16 = 3;
+
+const LABEL_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+//フッターに出す再生の状態
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackSummary {
+    pub status: PlaybackStatus,
+    pub position: Duration,
+    //トラックの長さが分からなければNone
+    pub duration: Option<Duration>,
+    pub volume: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+//(進捗バー, 状態の行)の領域
+pub fn footer_areas(area: Rect) -> [Rect; 2] {
+    let inner = Block::default().borders(Borders::TOP).inner(area);
+    Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(inner)
+}
+
+//画面下部の進捗バーと状態の行
+pub fn draw_footer(frame: &mut Frame, area: Rect, summary: &PlaybackSummary, notification: Option<&Notification>) {
+
+    frame.render_widget(Block::default().borders(Borders::TOP).border_style(LABEL_STYLE), area);
+
+    let [gauge_area, status_area] = footer_areas(area);
+
+    let (ratio, label) = progress(summary.position, summary.duration);
+    let gauge = Gauge::default()
+    .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black))
+    .ratio(ratio)
+    .label(label)
+    .use_unicode(true);
+
+    frame.render_widget(gauge, gauge_area);
+
+    frame.render_widget(Paragraph::new(status_line(summary)), status_area);
+
+    //メッセージは状態の行の右側に重ねる
+    if let Some(notification) = notification {
+        let style = match notification.level {
+            NotificationLevel::Info => Style::default().fg(Color::Yellow),
+            NotificationLevel::Error => Style::default().fg(Color::Red),
+        };
+        frame.render_widget(Paragraph::new(Line::styled(notification.text.clone(), style).right_aligned()), status_area);
+    }
+
+}
+
+//(バーの割合, "経過 / 全体")。長さが分からなければ経過時間だけを出す
+pub fn progress(position: Duration, duration: Option<Duration>) -> (f64, String) {
+    match duration {
+        Some(duration) if !duration.is_zero() => {
+            let position = position.min(duration);
+            (position.as_secs_f64() / duration.as_secs_f64(), format!("{} / {}", format_duration(position), format_duration(duration)))
+        },
+        _ => (0.0, format_duration(position)),
+    }
+}
+
+//進捗バーのcolumn列目をクリックしたときのシーク先。長さが分からないトラックはシークしない
+pub fn seek_position(gauge_area: Rect, column: u16, duration: Option<Duration>) -> Option<Duration> {
+
+    let duration = duration.filter(|duration| !duration.is_zero())?;
+    if gauge_area.width == 0 || column < gauge_area.left() || column >= gauge_area.right() {
+        return None;
+    }
+
+    //クリックしたセルの中央の位置にする
+    let ratio = ((column - gauge_area.left()) as f64 + 0.5) / gauge_area.width as f64;
+    Some(duration.mul_f64(ratio))
+
+}
+
+//"▶ Playing  Vol 80%  Repeat off  Shuffle on"
+pub fn status_line(summary: &PlaybackSummary) -> Line<'static> {
+
+    let status = match summary.status {
+        PlaybackStatus::Playing => "▶ Playing",
+        PlaybackStatus::Paused => "⏸ Paused",
+        PlaybackStatus::Stopped => "■ Stopped",
+    };
+
+    let repeat = match summary.repeat {
+        RepeatMode::Off => "off",
+        RepeatMode::One => "one",
+        RepeatMode::All => "all",
+    };
+
+    Line::from(vec![
+        Span::raw(status),
+        Span::styled("  Vol ", LABEL_STYLE),
+        Span::raw(format!("{:.0}%", summary.volume * 100.0)),
+        Span::styled("  Repeat ", LABEL_STYLE),
+        Span::raw(repeat),
+        Span::styled("  Shuffle ", LABEL_STYLE),
+        Span::raw(if summary.shuffle { "on" } else { "off" }),
+    ])
+
+}
+
+#[cfg(test)]
+mod test_footer {
+
+    use super::*;
+
+    #[test]
+    fn test_progress_label() {
+        let (ratio, label) = progress(Duration::from_secs(65), Some(Duration::from_secs(260)));
+        assert_eq!(ratio, 0.25);
+        assert_eq!(label, "1:05 / 4:20");
+
+        assert_eq!(progress(Duration::from_secs(300), Some(Duration::from_secs(260))).0, 1.0);
+        assert_eq!(progress(Duration::from_secs(5), None), (0.0, "0:05".to_string()));
+    }
+
+    #[test]
+    fn test_seek_position() {
+        let gauge = Rect::new(10, 20, 100, 1);
+        let duration = Some(Duration::from_secs(200));
+        assert_eq!(seek_position(gauge, 10, duration), Some(Duration::from_secs(1)));
+        assert_eq!(seek_position(gauge, 59, duration), Some(Duration::from_secs(99)));
+        assert_eq!(seek_position(gauge, 110, duration), None);
+        assert_eq!(seek_position(gauge, 50, None), None);
+    }
+
+    #[test]
+    fn test_status_line() {
+        let summary = PlaybackSummary {
+            status: PlaybackStatus::Paused,
+            position: Duration::ZERO,
+            duration: None,
+            volume: 0.8,
+            repeat: RepeatMode::All,
+            shuffle: false,
+        };
+        let text: String = status_line(&summary).spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(text, "⏸ Paused  Vol 80%  Repeat all  Shuffle off");
+    }
+
+}
@@ -0,0 +1,55 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+};
+
+use crate::audio::loader::AudioTrackMetaData;
+
+pub const HEADER_HEIGHT: u16 = 3;
+
+const LABEL_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+//画面上部の曲名の行。positionはプレイリスト内での(何曲目, 全体の曲数)
+pub fn draw_header(frame: &mut Frame, area: Rect, metadata: Option<&AudioTrackMetaData>, position: Option<(usize, usize)>) {
+
+    let block = Block::default().borders(Borders::BOTTOM).border_style(LABEL_STYLE);
+
+    let mut title = header_line(metadata);
+    if let Some((current, total)) = position {
+        title.spans.push(Span::styled(format!("  [{}/{}]", current, total), LABEL_STYLE));
+    }
+
+    let details = metadata.map(detail_line).unwrap_or_default();
+
+    frame.render_widget(Paragraph::new(vec![title, details]).block(block), area);
+
+}
+
+//"曲名 - アーティスト"
+pub fn header_line(metadata: Option<&AudioTrackMetaData>) -> Line<'static> {
+    match metadata {
+        Some(metadata) => Line::from(vec![
+            Span::styled(metadata.display_title(), Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(" - ", LABEL_STYLE),
+            Span::raw(metadata.display_artist().to_string()),
+        ]),
+        None => Line::styled("Nothing is playing", LABEL_STYLE),
+    }
+}
+
+//"アルバム (年)"。どちらも無ければ空行
+fn detail_line(metadata: &AudioTrackMetaData) -> Line<'static> {
+
+    let text = match (&metadata.album, metadata.year) {
+        (Some(album), Some(year)) => format!("{} ({})", album, year),
+        (Some(album), None) => album.clone(),
+        (None, Some(year)) => year.to_string(),
+        (None, None) => String::new(),
+    };
+
+    Line::styled(text, LABEL_STYLE)
+
+}
@@ -0,0 +1,102 @@
+use ratatui::{
+    Frame,
+    layout::{Constraint, Flex, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+
+use crate::action::Action;
+use crate::keys::Keymap;
+
+const KEY_STYLE: Style = Style::new().fg(Color::Yellow);
+const LABEL_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+//キーの割り当て一覧を画面の中央に重ねて表示する
+pub fn draw_help(frame: &mut Frame, area: Rect, keymap: &Keymap) {
+
+    let lines = help_lines(keymap);
+
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+
+    let [area] = Layout::horizontal([Constraint::Length(width)]).flex(Flex::Center).areas(area);
+    let [area] = Layout::vertical([Constraint::Length(height)]).flex(Flex::Center).areas(area);
+
+    let block = Block::default()
+    .borders(Borders::ALL)
+    .title("Keys")
+    .title_bottom(Line::styled(close_hint(keymap), LABEL_STYLE));
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+
+}
+
+//ヘルプを閉じるキー。Escはキーの割り当てに関わらず使える
+pub fn close_hint(keymap: &Keymap) -> String {
+
+    let keys: Vec<String> = keymap.sequences(Action::ToggleHelp).iter()
+    .map(ToString::to_string)
+    .chain(std::iter::once("Esc".to_string()))
+    .collect();
+
+    format!(" {} to close ", keys.join(" / "))
+
+}
+
+//割り当てのある操作だけを"キー  説明"の形で並べる
+pub fn help_lines(keymap: &Keymap) -> Vec<Line<'static>> {
+
+    let rows: Vec<(String, &str)> = keymap.bindings()
+    .filter(|(_, sequences)| !sequences.is_empty())
+    .map(|(action, sequences)| {
+        let keys: Vec<String> = sequences.iter().map(ToString::to_string).collect();
+        (keys.join(", "), action.description())
+    })
+    .collect();
+
+    let key_width = rows.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+
+    rows.into_iter()
+    .map(|(keys, description)| Line::from(vec![
+        Span::raw(" "),
+        Span::styled(format!("{:<width$}", keys, width = key_width), KEY_STYLE.add_modifier(Modifier::BOLD)),
+        Span::raw("  "),
+        Span::raw(description),
+    ]))
+    .collect()
+
+}
+
+#[cfg(test)]
+mod test_help {
+
+    use super::*;
+
+    #[test]
+    fn test_help_lists_active_bindings() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Action::Stop, Vec::new());
+        keymap.bind(Action::Quit, vec!["q".parse().unwrap(), "ctrl+x ctrl+c".parse().unwrap()]);
+
+        let lines: Vec<String> = help_lines(&keymap).iter()
+        .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+        .collect();
+
+        assert!(lines.iter().all(|line| !line.contains("Stop")));
+        assert!(lines.iter().any(|line| line.contains("q, ctrl+x ctrl+c") && line.ends_with("Quit")));
+        assert_eq!(lines.len(), Action::ALL.len() - 1);
+    }
+
+    #[test]
+    fn test_close_hint_follows_bindings() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Action::ToggleHelp, vec!["f1".parse().unwrap(), "g h".parse().unwrap()]);
+        assert_eq!(close_hint(&keymap), " f1 / g h / Esc to close ");
+
+        keymap.bind(Action::ToggleHelp, Vec::new());
+        assert_eq!(close_hint(&keymap), " Esc to close ");
+    }
+
+}
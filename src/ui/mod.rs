@@ -0,0 +1,9 @@
+pub mod now_playing;
+pub mod cover_art;
+pub mod help;
+pub mod header;
+pub mod footer;
+pub mod notification;
+pub mod terminal;
+pub mod playlist;
+pub mod tooltip;
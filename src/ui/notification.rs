@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+//メッセージを表示しておく時間
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationLevel {
+    Info,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub text: String,
+    pub level: NotificationLevel,
+    shown_at: Instant,
+}
+
+//フッターに一時的に出すメッセージ。新しいものが来たら古いものは置き換える
+#[derive(Debug, Clone, Default)]
+pub struct Notifications {
+    current: Option<Notification>,
+}
+
+impl Notifications {
+
+    pub fn info<S: Into<String>>(&mut self, text: S) {
+        self.push(text.into(), NotificationLevel::Info, Instant::now());
+    }
+
+    pub fn error<S: Into<String>>(&mut self, text: S) {
+        self.push(text.into(), NotificationLevel::Error, Instant::now());
+    }
+
+    pub fn push(&mut self, text: String, level: NotificationLevel, now: Instant) {
+        self.current = Some(Notification { text, level, shown_at: now });
+    }
+
+    //表示期間が過ぎたメッセージは返さない
+    pub fn current(&self, now: Instant) -> Option<&Notification> {
+        self.current.as_ref()
+        .filter(|notification| now.saturating_duration_since(notification.shown_at) < NOTIFICATION_DURATION)
+    }
+
+}
+
+#[cfg(test)]
+mod test_notification {
+
+    use super::*;
+
+    #[test]
+    fn test_notifications_expire_and_replace() {
+        let now = Instant::now();
+        let mut notifications = Notifications::default();
+        assert!(notifications.current(now).is_none());
+
+        notifications.push("Repeat: all".to_string(), NotificationLevel::Info, now);
+        notifications.push("Failed to load a.mp3".to_string(), NotificationLevel::Error, now);
+        let current = notifications.current(now + Duration::from_secs(1)).unwrap();
+        assert_eq!(current.text, "Failed to load a.mp3");
+        assert_eq!(current.level, NotificationLevel::Error);
+
+        assert!(notifications.current(now + NOTIFICATION_DURATION).is_none());
+    }
+
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+};
+
+use crate::audio::loader::AudioTrackMetaData;
+
+//端末の幅がこれ以上のときだけ右側にパネルを出す
+pub const MIN_WIDTH_FOR_PANEL: u16 = 80;
+pub const PANEL_WIDTH: u16 = 36;
+
+const LABEL_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+//再生中のトラックの情報パネル
+pub fn draw_now_playing(frame: &mut Frame, area: Rect, metadata: Option<&AudioTrackMetaData>) {
+
+    let block = Block::default()
+    .borders(Borders::ALL)
+    .title("Now Playing");
+
+    let lines = match metadata {
+        Some(metadata) => now_playing_lines(metadata),
+        None => vec![Line::styled("Nothing is playing", LABEL_STYLE)],
+    };
+
+    let paragraph = Paragraph::new(lines)
+    .block(block)
+    .wrap(Wrap { trim: false });
+
+    frame.render_widget(paragraph, area);
+
+}
+
+//タグにある項目だけを並べる(無い項目の行は出さない)
+pub fn now_playing_lines(metadata: &AudioTrackMetaData) -> Vec<Line<'static>> {
+
+    let mut lines = vec![
+        Line::styled(metadata.display_title(), Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw(metadata.display_artist().to_string()),
+    ];
+
+    let mut push = |label: &'static str, value: Option<String>| {
+        if let Some(value) = value {
+            lines.push(Line::from(vec![Span::styled(format!("{:<9}", label), LABEL_STYLE), Span::raw(value)]));
+        }
+    };
+
+    push("Album", metadata.album.clone());
+    if metadata.album_artist != metadata.artist {
+        push("Album by", metadata.album_artist.clone());
+    }
+    push("Track", metadata.track_number.map(|n| number_of(n, metadata.track_total)));
+    push("Disc", metadata.disc_number.map(|n| number_of(n, metadata.disc_total)));
+    push("Year", metadata.year.map(|year| year.to_string()));
+    push("Genre", metadata.genre.clone());
+    push("Composer", metadata.composer.clone());
+    push("Length", metadata.duration.map(format_duration));
+    push("Format", stream_summary(metadata));
+    push("Gain", replay_gain_summary(metadata));
+    push("Comment", metadata.comment.clone());
+
+    if let Some(lyrics) = &metadata.lyrics {
+        lines.push(Line::raw(""));
+        lines.extend(lyrics.lines().map(|line| Line::raw(line.to_string())));
+    }
+
+    lines
+
+}
+
+fn number_of(number: u32, total: Option<u32>) -> String {
+    match total {
+        Some(total) => format!("{}/{}", number, total),
+        None => number.to_string(),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+//"FLAC 44.1kHz 16bit stereo 912kbps"
+fn stream_summary(metadata: &AudioTrackMetaData) -> Option<String> {
+
+    let mut parts = Vec::new();
+
+    if let Some(format) = metadata.format {
+        parts.push(format.name().to_string());
+    }
+    if let Some(sample_rate) = metadata.sample_rate {
+        parts.push(format!("{}kHz", sample_rate as f32 / 1000.0));
+    }
+    if let Some(bit_depth) = metadata.bit_depth {
+        parts.push(format!("{}bit", bit_depth));
+    }
+    match metadata.channels {
+        Some(1) => parts.push("mono".to_string()),
+        Some(2) => parts.push("stereo".to_string()),
+        Some(channels) => parts.push(format!("{}ch", channels)),
+        None => {},
+    }
+    if let Some(bitrate) = metadata.bitrate_kbps {
+        parts.push(format!("{}kbps", bitrate));
+    }
+
+    if parts.is_empty() { None } else { Some(parts.join(" ")) }
+
+}
+
+fn replay_gain_summary(metadata: &AudioTrackMetaData) -> Option<String> {
+
+    let replay_gain = &metadata.replay_gain;
+
+    let mut parts = Vec::new();
+    if let Some(gain) = replay_gain.track_gain_db {
+        parts.push(format!("track {:+.2} dB", gain));
+    }
+    if let Some(gain) = replay_gain.album_gain_db {
+        parts.push(format!("album {:+.2} dB", gain));
+    }
+
+    if parts.is_empty() { None } else { Some(parts.join(", ")) }
+
+}
+
+#[cfg(test)]
+mod test_now_playing {
+
+    use super::*;
+    use crate::audio::format::AudioFormat;
+
+    fn text(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect()).collect()
+    }
+
+    #[test]
+    fn test_lines_skip_missing_fields() {
+        let metadata = AudioTrackMetaData {
+            title: Some("Intro".to_string()),
+            album: Some("Demo".to_string()),
+            track_number: Some(1),
+            track_total: Some(9),
+            format: Some(AudioFormat::Flac),
+            sample_rate: Some(44100),
+            bit_depth: Some(16),
+            channels: Some(2),
+            duration: Some(Duration::from_secs(125)),
+            ..Default::default()
+        };
+
+        let lines = text(&now_playing_lines(&metadata));
+        assert_eq!(lines, vec![
+            "Intro",
+            "Unknown Artist",
+            "Album    Demo",
+            "Track    1/9",
+            "Length   2:05",
+            "Format   FLAC 44.1kHz 16bit stereo",
+        ]);
+    }
+
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+};
+
+const LABEL_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+//プレイリストのパネル。クリックした行を調べられるように、表示し始めた位置を返す
+pub fn draw_playlist(frame: &mut Frame, area: Rect, tracks: &[PathBuf], current: Option<usize>) -> usize {
+
+    let block = Block::default()
+    .borders(Borders::ALL)
+    .title(format!("Playlist ({})", tracks.len()));
+    let rows = block.inner(area).height as usize;
+
+    let offset = scroll_offset(current, tracks.len(), rows);
+
+    let lines: Vec<Line> = tracks.iter()
+    .enumerate()
+    .skip(offset)
+    .take(rows)
+    .map(|(i, path)| {
+        let name = path.file_stem().map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned());
+        if Some(i) == current {
+            Line::styled(format!("▶ {}", name), Style::default().add_modifier(Modifier::BOLD))
+        } else {
+            Line::styled(format!("  {}", name), LABEL_STYLE)
+        }
+    })
+    .collect();
+
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+
+    offset
+
+}
+
+//再生中のトラックがなるべく中央に来るように、表示し始める行を決める
+pub fn scroll_offset(current: Option<usize>, len: usize, rows: usize) -> usize {
+    current.unwrap_or(0)
+    .saturating_sub(rows / 2)
+    .min(len.saturating_sub(rows))
+}
+
+//パネルの(枠を含む)areaでy行目に表示しているトラックのインデックス
+pub fn track_at(area: Rect, offset: usize, len: usize, y: u16) -> Option<usize> {
+
+    let inner = Block::default().borders(Borders::ALL).inner(area);
+    if y < inner.top() || y >= inner.bottom() {
+        return None;
+    }
+
+    Some(offset + (y - inner.top()) as usize).filter(|&index| index < len)
+
+}
+
+#[cfg(test)]
+mod test_playlist {
+
+    use super::*;
+
+    #[test]
+    fn test_scroll_and_hit_test() {
+        assert_eq!(scroll_offset(Some(1), 20, 6), 0);
+        assert_eq!(scroll_offset(Some(10), 20, 6), 7);
+        assert_eq!(scroll_offset(Some(19), 20, 6), 14);
+        assert_eq!(scroll_offset(Some(3), 4, 6), 0);
+
+        //枠の内側の1行目が3番目のトラック
+        let area = Rect::new(40, 10, 30, 8);
+        assert_eq!(track_at(area, 2, 20, 11), Some(2));
+        assert_eq!(track_at(area, 2, 20, 16), Some(7));
+        assert_eq!(track_at(area, 2, 20, 10), None);
+        assert_eq!(track_at(area, 0, 3, 14), None);
+    }
+
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Stdout, Write};
+use std::panic;
+use std::sync::{Mutex, Once, PoisonError};
+use std::thread::{self, ThreadId};
+
+use ratatui::crossterm::{
+    cursor::{Hide, Show},
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::prelude::CrosstermBackend;
+use ratatui::Terminal;
+
+use crate::error::FerriaError;
+
+static PANIC_HOOK: Once = Once::new();
+
+//セッションを持っているスレッド。セッションが無いときはNone
+static SESSION_OWNER: Mutex<Option<ThreadId>> = Mutex::new(None);
+
+//raw mode、代替画面、カーソルの非表示、マウスの取得をまとめて切り替える
+//Dropでもパニックでも元の端末の状態に戻す
+pub struct TerminalSession {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl TerminalSession {
+
+    pub fn enter() -> Result<Self, FerriaError> {
+
+        install_panic_hook();
+
+        //途中で失敗したら、それまでに切り替えた分も戻す
+        let setup = || -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
+            terminal::enable_raw_mode()?;
+            let mut stdout = io::stdout();
+            execute!(stdout, EnterAlternateScreen, EnableMouseCapture, Hide)?;
+            let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+            terminal.clear()?;
+            Ok(terminal)
+        };
+
+        match setup() {
+            Ok(terminal) => {
+                set_owner(Some(thread::current().id()));
+                Ok(TerminalSession { terminal })
+            },
+            Err(e) => {
+                let _ = restore();
+                Err(e.into())
+            },
+        }
+
+    }
+
+    pub fn terminal(&mut self) -> &mut Terminal<CrosstermBackend<Stdout>> {
+        &mut self.terminal
+    }
+
+    //端末の大きさが変わったら、前の画面の残りが出ないように全体を描き直す
+    pub fn resize(&mut self) -> Result<(), FerriaError> {
+        self.terminal.autoresize()?;
+        self.terminal.clear()?;
+        Ok(())
+    }
+
+}
+
+impl Drop for TerminalSession {
+    fn drop(&mut self) {
+        set_owner(None);
+        if let Err(e) = restore() {
+            eprintln!("Failed to restore the terminal: {}", e);
+        }
+    }
+}
+
+//どれかが失敗しても残りは戻す
+fn restore() -> io::Result<()> {
+
+    let mut stdout = io::stdout();
+
+    let results = [
+        execute!(stdout, DisableMouseCapture),
+        execute!(stdout, LeaveAlternateScreen),
+        execute!(stdout, Show),
+        terminal::disable_raw_mode(),
+        stdout.flush(),
+    ];
+
+    results.into_iter().collect()
+
+}
+
+fn set_owner(owner: Option<ThreadId>) {
+    *SESSION_OWNER.lock().unwrap_or_else(PoisonError::into_inner) = owner;
+}
+
+//パニックのメッセージが代替画面に消えたり、raw modeで崩れたりしないように、先に端末を戻す
+//描画を続けるスレッドから端末を奪わないように、戻すのはセッションを持つスレッドのパニックだけにする
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if owns_session(thread::current().id()) {
+                set_owner(None);
+                let _ = restore();
+            }
+            previous(info);
+        }));
+    });
+}
+
+fn owns_session(thread: ThreadId) -> bool {
+    *SESSION_OWNER.lock().unwrap_or_else(PoisonError::into_inner) == Some(thread)
+}
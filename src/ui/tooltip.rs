@@ -0,0 +1,29 @@
+use ratatui::{
+    Frame,
+    layout::{Position, Rect},
+    style::{Color, Style},
+    widgets::{Clear, Paragraph},
+};
+
+const TOOLTIP_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Gray);
+
+//マウスの位置の右上に1行の説明を出す。boundsからはみ出す場合は左や下にずらす
+pub fn draw_tooltip(frame: &mut Frame, bounds: Rect, mouse: Position, text: &str) {
+
+    let area = tooltip_area(bounds, mouse, text.chars().count() as u16 + 2);
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(format!(" {} ", text)).style(TOOLTIP_STYLE), area);
+
+}
+
+pub fn tooltip_area(bounds: Rect, mouse: Position, width: u16) -> Rect {
+
+    let width = width.min(bounds.width);
+
+    let x = if mouse.x + 1 + width <= bounds.right() { mouse.x + 1 } else { mouse.x.saturating_sub(width).max(bounds.left()) };
+    let y = if mouse.y > bounds.top() { mouse.y - 1 } else { (mouse.y + 1).min(bounds.bottom().saturating_sub(1)) };
+
+    Rect::new(x, y, width, 1).intersection(bounds)
+
+}
@@ -0,0 +1,228 @@
+use std::time::{Duration, Instant};
+
+//1回の描画で進める時間の上限(止まっていた後に一気に落ちないように)
+const MAX_STEP: Duration = Duration::from_millis(100);
+
+//棒の動き方の設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BallisticsConfig {
+    //棒が伸びるときの時定数(秒)。0なら即座に追従する
+    pub attack: f32,
+    //棒が縮むときの時定数(秒)
+    pub release: f32,
+    //ピークの印を表示するか
+    pub peaks: bool,
+    //ピークの印が落ち始めるまでの時間(秒)
+    pub peak_hold: f32,
+    //ピークの印が落ちる加速度(1秒あたり、表示の高さ全体を1.0とする)
+    pub peak_gravity: f32,
+    //解析フレームの間隔が描画の間隔より長いときに、フレームの間を補間する
+    pub interpolate: bool,
+}
+
+impl Default for BallisticsConfig {
+    fn default() -> Self {
+        BallisticsConfig {
+            attack: 0.0,
+            release: 0.3,
+            peaks: true,
+            peak_hold: 0.6,
+            peak_gravity: 3.0,
+            interpolate: false,
+        }
+    }
+}
+
+//棒ごとの表示の高さ(0.0〜1.0)とピークの印の状態
+#[derive(Debug, Clone)]
+pub struct Ballistics {
+    config: BallisticsConfig,
+    levels: Vec<f32>,
+    peaks: Vec<Peak>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Peak {
+    pub level: f32,
+    //落ち始めるまでの残り時間(秒)
+    hold: f32,
+    velocity: f32,
+}
+
+impl Ballistics {
+
+    pub fn new(config: BallisticsConfig) -> Self {
+        Ballistics { config, levels: Vec::new(), peaks: Vec::new() }
+    }
+
+    pub fn config(&self) -> &BallisticsConfig {
+        &self.config
+    }
+
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+
+    pub fn peaks(&self) -> &[Peak] {
+        &self.peaks
+    }
+
+    //targetsに向けてdt秒分だけ動かす
+    //棒の数が変わったら(リサイズなど)状態を作り直す
+    pub fn step(&mut self, targets: &[f32], dt: Duration) {
+
+        if self.levels.len() != targets.len() {
+            self.levels = targets.to_vec();
+            self.peaks = targets.iter().map(|&level| Peak { level, hold: self.config.peak_hold, velocity: 0.0 }).collect();
+            return;
+        }
+
+        let dt = dt.min(MAX_STEP).as_secs_f32();
+
+        for ((level, peak), &target) in self.levels.iter_mut().zip(self.peaks.iter_mut()).zip(targets) {
+
+            let time_constant = if target > *level { self.config.attack } else { self.config.release };
+            let alpha = smoothing(time_constant, dt);
+            *level = if alpha >= 1.0 { target } else { *level + (target - *level) * alpha };
+
+            if *level >= peak.level {
+                *peak = Peak { level: *level, hold: self.config.peak_hold, velocity: 0.0 };
+            } else if peak.hold > 0.0 {
+                peak.hold -= dt;
+            } else {
+                peak.velocity += self.config.peak_gravity * dt;
+                peak.level = (peak.level - peak.velocity * dt).max(*level);
+            }
+        }
+
+    }
+
+}
+
+//時定数time_constantの1次遅れでdt秒進めたときに、目標との差を縮める割合
+fn smoothing(time_constant: f32, dt: f32) -> f32 {
+    if time_constant <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-dt / time_constant).exp()
+    }
+}
+
+//解析フレームが届いた時刻を覚えておき、前のフレームとの間を直線で補間する
+#[derive(Debug, Clone, Default)]
+pub struct FrameInterpolator {
+    from: Vec<f32>,
+    to: Vec<f32>,
+    arrived: Option<Instant>,
+    //直前のフレームの間隔
+    interval: Duration,
+}
+
+impl FrameInterpolator {
+
+    pub fn push(&mut self, values: Vec<f32>, now: Instant) {
+
+        self.from = if self.from.len() == values.len() { self.current(now) } else { values.clone() };
+
+        if let Some(arrived) = self.arrived {
+            self.interval = now.saturating_duration_since(arrived);
+        }
+
+        self.to = values;
+        self.arrived = Some(now);
+
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.to.is_empty()
+    }
+
+    //nowの時点の値。次のフレームが同じ間隔で届くとみなして、届くまでにtoに着くように進める
+    pub fn current(&self, now: Instant) -> Vec<f32> {
+
+        let Some(arrived) = self.arrived else { return self.to.clone() };
+
+        if self.interval.is_zero() || self.from.len() != self.to.len() {
+            return self.to.clone();
+        }
+
+        let t = (now.saturating_duration_since(arrived).as_secs_f32() / self.interval.as_secs_f32()).min(1.0);
+
+        self.from.iter().zip(&self.to)
+        .map(|(from, to)| from + (to - from) * t)
+        .collect()
+
+    }
+
+}
+
+#[cfg(test)]
+mod test_ballistics {
+
+    use super::*;
+
+    fn step_for(ballistics: &mut Ballistics, targets: &[f32], seconds: f32) {
+        //描画の間隔(10ms)ごとに進める
+        for _ in 0..(seconds * 100.0).round() as usize {
+            ballistics.step(targets, Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_attack_and_release() {
+        let config = BallisticsConfig { attack: 0.0, release: 0.5, ..Default::default() };
+        let mut ballistics = Ballistics::new(config);
+
+        ballistics.step(&[0.0, 0.0], Duration::ZERO);
+        ballistics.step(&[1.0, 0.0], Duration::from_millis(10));
+        //attackが0ならすぐに伸びる
+        assert_eq!(ballistics.levels(), &[1.0, 0.0]);
+
+        //時定数の分だけ経つと差が1/eになる
+        step_for(&mut ballistics, &[0.0, 0.0], 0.5);
+        assert!((ballistics.levels()[0] - (-1.0f32).exp()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_peak_holds_then_falls_with_gravity() {
+        let config = BallisticsConfig { attack: 0.0, release: 0.0, peak_hold: 0.2, peak_gravity: 2.0, ..Default::default() };
+        let mut ballistics = Ballistics::new(config);
+
+        ballistics.step(&[0.0], Duration::ZERO);
+        ballistics.step(&[0.8], Duration::from_millis(10));
+        ballistics.step(&[0.1], Duration::from_millis(10));
+        assert_eq!(ballistics.levels(), &[0.1]);
+        assert_eq!(ballistics.peaks()[0].level, 0.8);
+
+        //holdの間は落ちない
+        step_for(&mut ballistics, &[0.1], 0.15);
+        assert_eq!(ballistics.peaks()[0].level, 0.8);
+
+        //落ち始めると加速する
+        step_for(&mut ballistics, &[0.1], 0.2);
+        let first = 0.8 - ballistics.peaks()[0].level;
+        step_for(&mut ballistics, &[0.1], 0.1);
+        let second = 0.8 - first - ballistics.peaks()[0].level;
+        assert!(first > 0.0 && second > first / 2.0);
+
+        //棒より下には落ちない
+        step_for(&mut ballistics, &[0.1], 2.0);
+        assert_eq!(ballistics.peaks()[0].level, 0.1);
+    }
+
+    #[test]
+    fn test_frame_interpolation() {
+        let start = Instant::now();
+        let mut interpolator = FrameInterpolator::default();
+
+        interpolator.push(vec![0.0], start);
+        assert_eq!(interpolator.current(start + Duration::from_millis(50)), vec![0.0]);
+
+        //100msごとに届くフレームの間を補間する
+        interpolator.push(vec![1.0], start + Duration::from_millis(100));
+        assert_eq!(interpolator.current(start + Duration::from_millis(100)), vec![0.0]);
+        assert!((interpolator.current(start + Duration::from_millis(150))[0] - 0.5).abs() < 1e-4);
+        assert_eq!(interpolator.current(start + Duration::from_millis(300)), vec![1.0]);
+    }
+
+}
@@ -0,0 +1,126 @@
+use clap::ValueEnum;
+
+//下から1/8ずつ埋まるブロック(0/8〜8/8)
+const EIGHTH_BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+//点字の1セル(横2 x 縦4ドット)の各ドットのビット。[x][上からのy]
+const BRAILLE_DOTS: [[u8; 4]; 2] = [
+    [0x01, 0x02, 0x04, 0x40],
+    [0x08, 0x10, 0x20, 0x80],
+];
+
+pub const BRAILLE_DOTS_X: usize = 2;
+pub const BRAILLE_DOTS_Y: usize = 4;
+
+//棒の描き方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BarStyle {
+    ///セル単位の背景色
+    Blocks,
+    ///1/8ブロック文字で縦8倍の分解能
+    #[default]
+    Eighths,
+    ///点字で横2倍、縦4倍の分解能
+    Braille,
+}
+
+impl BarStyle {
+
+    //1セルあたりの(横, 縦)の分解能
+    pub fn resolution(&self) -> (usize, usize) {
+        match self {
+            BarStyle::Blocks => (1, 1),
+            BarStyle::Eighths => (1, 8),
+            BarStyle::Braille => (BRAILLE_DOTS_X, BRAILLE_DOTS_Y),
+        }
+    }
+
+}
+
+//eighths/8だけ下から埋まったブロック文字
+pub fn eighth_block(eighths: usize) -> char {
+    EIGHTH_BLOCKS[eighths.min(8)]
+}
+
+//点字のドットを並べた描画面。座標はドット単位で、yは下から数える
+#[derive(Debug, Clone)]
+pub struct BrailleCanvas {
+    columns: usize,
+    rows: usize,
+    cells: Vec<u8>,
+}
+
+impl BrailleCanvas {
+
+    pub fn new(columns: usize, rows: usize) -> Self {
+        BrailleCanvas { columns, rows, cells: vec![0; columns * rows] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.columns * BRAILLE_DOTS_X
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows * BRAILLE_DOTS_Y
+    }
+
+    //範囲外は無視する
+    pub fn set(&mut self, x: usize, y: usize) {
+
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+
+        let from_top = self.height() - 1 - y;
+        let cell = (from_top / BRAILLE_DOTS_Y) * self.columns + x / BRAILLE_DOTS_X;
+
+        self.cells[cell] |= BRAILLE_DOTS[x % BRAILLE_DOTS_X][from_top % BRAILLE_DOTS_Y];
+
+    }
+
+    //上からrow行目、左からcolumn列目のセルの文字。ドットが無ければNone
+    pub fn cell(&self, column: usize, row: usize) -> Option<char> {
+        match self.cells[row * self.columns + column] {
+            0 => None,
+            dots => char::from_u32(0x2800 + dots as u32),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test_glyphs {
+
+    use super::*;
+
+    #[test]
+    fn test_eighth_block() {
+        assert_eq!(eighth_block(0), ' ');
+        assert_eq!(eighth_block(3), '▃');
+        assert_eq!(eighth_block(8), '█');
+        assert_eq!(eighth_block(12), '█');
+    }
+
+    #[test]
+    fn test_braille_canvas() {
+        let mut canvas = BrailleCanvas::new(2, 2);
+        assert_eq!((canvas.width(), canvas.height()), (4, 8));
+
+        //左下のセルの左の列を下から3ドット
+        for y in 0..3 {
+            canvas.set(0, y);
+        }
+        assert_eq!(canvas.cell(0, 1), Some('⡆'));
+        assert_eq!(canvas.cell(0, 0), None);
+
+        //右上のセルの右上の角
+        canvas.set(3, 7);
+        assert_eq!(canvas.cell(1, 0), Some('⠈'));
+
+        //範囲外
+        canvas.set(4, 0);
+        canvas.set(0, 8);
+        assert_eq!(canvas.cell(1, 1), None);
+    }
+
+}
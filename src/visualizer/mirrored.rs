@@ -0,0 +1,79 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Style,
+};
+
+use crate::audio::analyzer::SpectrumData;
+use crate::visualizer::theme::Theme;
+use crate::visualizer::visualizer::{visualizer_block, LevelRange, SpectrumVisualizer, Visualizer};
+
+//中央の水平線から上下対称に伸びる棒グラフ
+#[derive(Debug, Default)]
+pub struct MirroredVisualizer {
+    levels: LevelRange,
+    theme: Theme,
+}
+
+impl MirroredVisualizer {
+
+    pub fn new(levels: LevelRange) -> Self {
+        MirroredVisualizer { levels, theme: Theme::default() }
+    }
+
+}
+
+impl Visualizer for MirroredVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Mirrored"
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        let block = visualizer_block(self.name());
+        let inner = block.inner(area);
+        frame.render_widget(&block, area);
+
+        let Some(data) = spectrum_data else { return };
+
+        if inner.width == 0 || inner.height == 0 {
+            return;
+        }
+
+        let values = SpectrumVisualizer::display_values(data, inner.width as usize);
+
+        //中央の行から上下にhalf_height行ずつ伸ばす
+        let center = inner.top() + inner.height / 2;
+        let half_height = (inner.height / 2).max(1) as f32;
+
+        for (i, &magnitude) in values.iter().enumerate() {
+
+            let x = inner.left() + i as u16;
+            if x >= inner.right() { continue; }
+
+            let mut bar_height = (self.levels.level(magnitude) * half_height) as u16;
+            if bar_height == 0 && magnitude > 0.0 {
+                bar_height = 1;
+            }
+
+            let top = center.saturating_sub(bar_height).max(inner.top());
+            let bottom = (center + bar_height).min(inner.bottom());
+
+            let position = i as f32 / values.len() as f32;
+
+            for y in top..bottom {
+                //高さで色を付けるときは中央からの距離を高さとみなす
+                let distance = if y < center { center - y } else { y - center + 1 };
+                let color = self.theme.bar_color(position, distance as f32 / half_height);
+                frame.buffer_mut().set_style(Rect::new(x, y, 1, 1), Style::default().bg(color));
+            }
+        }
+
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+    }
+
+}
@@ -1,2 +1,12 @@
 pub mod visualize_color;
+#[allow(clippy::module_inception)]
 pub mod visualizer;
+pub mod registry;
+pub mod theme;
+pub mod ballistics;
+pub mod glyphs;
+pub mod mirrored;
+pub mod oscilloscope;
+pub mod spectrogram;
+pub mod radial;
+pub mod vectorscope;
@@ -0,0 +1,164 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Color,
+    symbols::Marker,
+    widgets::canvas::{Canvas, Line},
+};
+
+use crate::audio::analyzer::{SpectrumData, WaveformData};
+use crate::visualizer::theme::Theme;
+use crate::visualizer::visualizer::{visualizer_block, Visualizer};
+
+//トリガーの前に一度この値より下がっている必要がある(ノイズで何度もトリガーしないように)
+const TRIGGER_HYSTERESIS: f32 = 0.01;
+
+//チャンネルごとの線の色(3チャンネル目以降は最後の色を使う)
+const CHANNEL_COLORS: [Color; 2] = [Color::Cyan, Color::Magenta];
+
+//解析区間の時間波形をBrailleで描くオシロスコープ
+//立ち上がりのゼロクロスにトリガーをかけて、フレームごとに波形が揺れないようにする
+#[derive(Debug, Default)]
+pub struct OscilloscopeVisualizer {
+    theme: Theme,
+}
+
+impl OscilloscopeVisualizer {
+
+    pub fn new() -> Self {
+        OscilloscopeVisualizer::default()
+    }
+
+    //表示する区間(開始フレーム, フレーム数)
+    //トリガーを探す範囲を残すため、表示するのは解析区間の半分の長さ
+    //トリガーが見つからない(無音など)ときは先頭から表示する
+    pub(crate) fn display_range(&self, waveform: &WaveformData) -> (usize, usize) {
+
+        let len = waveform.len();
+        let window = len / 2;
+
+        let start = trigger_index(&waveform.mono(), len - window).unwrap_or(0);
+
+        (start, window)
+
+    }
+
+}
+
+//samples[..search_len]の中で、負から0以上に変わる最初の位置
+pub(crate) fn trigger_index(samples: &[f32], search_len: usize) -> Option<usize> {
+
+    let mut armed = false;
+
+    for (i, &sample) in samples.iter().take(search_len).enumerate() {
+        if sample < -TRIGGER_HYSTERESIS {
+            armed = true;
+        }
+        else if armed && sample >= 0.0 {
+            return Some(i);
+        }
+    }
+
+    None
+
+}
+
+impl Visualizer for OscilloscopeVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Oscilloscope"
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        let block = visualizer_block(self.name());
+
+        let (start, window) = spectrum_data
+        .map(|data| self.display_range(&data.waveform))
+        .unwrap_or((0, 0));
+
+        //軸の線はテーマの枠線の色
+        let axis_color = self.theme.border().unwrap_or(Color::DarkGray);
+
+        let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::Braille)
+        .background_color(self.theme.background().unwrap_or(Color::Reset))
+        .x_bounds([0.0, window.saturating_sub(1).max(1) as f64])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+
+            ctx.draw(&Line::new(0.0, 0.0, window as f64, 0.0, axis_color));
+
+            let Some(data) = spectrum_data else { return };
+
+            for (channel_index, channel) in data.waveform.channels.iter().enumerate() {
+
+                ctx.layer();
+
+                let color = CHANNEL_COLORS[channel_index.min(CHANNEL_COLORS.len() - 1)];
+                let samples = &channel[start..start + window];
+
+                for (i, pair) in samples.windows(2).enumerate() {
+                    ctx.draw(&Line::new(
+                        i as f64,
+                        pair[0].clamp(-1.0, 1.0) as f64,
+                        (i + 1) as f64,
+                        pair[1].clamp(-1.0, 1.0) as f64,
+                        color,
+                    ));
+                }
+            }
+        });
+
+        frame.render_widget(canvas, area);
+
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+    }
+
+}
+
+#[cfg(test)]
+mod test_oscilloscope {
+
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(phase: f32) -> Vec<f32> {
+        (0..1024).map(|i| (2.0 * PI * i as f32 / 100.0 + phase).sin()).collect()
+    }
+
+    #[test]
+    fn test_trigger_index_finds_rising_zero_crossing() {
+        let samples = [0.5, 0.2, -0.3, -0.5, 0.1, 0.4, -0.2];
+        assert_eq!(trigger_index(&samples, samples.len()), Some(4));
+
+        //ヒステリシスより浅い落ち込みではトリガーしない
+        assert_eq!(trigger_index(&[0.5, -0.001, 0.2], 3), None);
+        assert_eq!(trigger_index(&samples, 3), None);
+    }
+
+    #[test]
+    fn test_triggered_display_is_stable_across_phases() {
+        let oscilloscope = OscilloscopeVisualizer::new();
+
+        //位相がずれていても表示の先頭は同じ立ち上がりの位置になる
+        let starts: Vec<f32> = [0.0, 1.0, 2.5, 4.0].iter()
+        .map(|&phase| {
+            let samples = sine(phase);
+            let waveform = WaveformData { channels: vec![samples.clone()], sample_rate: 44100 };
+            let (start, window) = oscilloscope.display_range(&waveform);
+            assert_eq!(window, 512);
+            samples[start]
+        })
+        .collect();
+
+        for value in starts {
+            assert!((0.0..0.1).contains(&value));
+        }
+    }
+
+}
@@ -0,0 +1,95 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Color,
+    symbols::Marker,
+    widgets::canvas::{Canvas, Line},
+};
+use std::f64::consts::{FRAC_PI_2, TAU};
+
+use crate::audio::analyzer::SpectrumData;
+use crate::visualizer::theme::Theme;
+use crate::visualizer::visualizer::{visualizer_block, LevelRange, SpectrumVisualizer, Visualizer};
+
+//円周上に並べる帯域の数
+const SPOKES: usize = 96;
+//内側の円の半径と、棒の最大の長さ(縦方向の半分を1.0とする)
+const INNER_RADIUS: f64 = 0.3;
+const SPOKE_LENGTH: f64 = 0.65;
+
+//中心から放射状に伸びる円形のスペクトラム表示
+#[derive(Debug, Default)]
+pub struct RadialVisualizer {
+    levels: LevelRange,
+    theme: Theme,
+}
+
+impl RadialVisualizer {
+
+    pub fn new(levels: LevelRange) -> Self {
+        RadialVisualizer { levels, theme: Theme::default() }
+    }
+
+}
+
+impl Visualizer for RadialVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Radial"
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        let block = visualizer_block(self.name());
+        let inner = block.inner(area);
+
+        let values = spectrum_data
+        .map(|data| SpectrumVisualizer::display_values(data, SPOKES))
+        .unwrap_or_default();
+
+        //セルは縦長なので、横方向の範囲を広げて円が潰れないようにする
+        let aspect = aspect_ratio(inner);
+
+        let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::Braille)
+        .background_color(self.theme.background().unwrap_or(Color::Reset))
+        .x_bounds([-aspect, aspect])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+            for (i, &magnitude) in values.iter().enumerate() {
+
+                let angle = FRAC_PI_2 - TAU * i as f64 / values.len() as f64;
+                let level = self.levels.level(magnitude);
+                let length = INNER_RADIUS + SPOKE_LENGTH * level as f64;
+
+                ctx.draw(&Line::new(
+                    INNER_RADIUS * angle.cos(),
+                    INNER_RADIUS * angle.sin(),
+                    length * angle.cos(),
+                    length * angle.sin(),
+                    self.theme.bar_color(i as f32 / values.len() as f32, level),
+                ));
+            }
+        });
+
+        frame.render_widget(canvas, area);
+
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+    }
+
+}
+
+//Canvasの座標で横幅÷高さ。1セルの縦横比をおおよそ2:1として計算する
+pub(crate) fn aspect_ratio(area: Rect) -> f64 {
+
+    if area.height == 0 {
+        return 1.0;
+    }
+
+    area.width as f64 / (area.height as f64 * 2.0)
+
+}
@@ -0,0 +1,316 @@
+use clap::ValueEnum;
+use ratatui::{Frame, layout::Rect, style::Style};
+
+use crate::audio::analyzer::SpectrumData;
+use crate::visualizer::{
+    ballistics::BallisticsConfig,
+    mirrored::MirroredVisualizer,
+    oscilloscope::OscilloscopeVisualizer,
+    radial::RadialVisualizer,
+    spectrogram::SpectrogramVisualizer,
+    theme::{Theme, ThemeSet},
+    vectorscope::VectorscopeVisualizer,
+    visualize_color::{Colormap, Palette},
+    visualizer::{BarsConfig, LevelRange, SpectrumVisualizer, Visualizer},
+};
+
+//ヴィジュアライザーの描画モード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VisualizerMode {
+    ///縦棒のスペクトラム表示
+    Bars,
+    ///中央から上下対称に伸びる棒グラフ
+    Mirrored,
+    ///時間波形
+    Oscilloscope,
+    ///流れるスペクトログラム(ウォーターフォール)
+    Spectrogram,
+    ///円形のスペクトラム表示
+    Radial,
+    ///ステレオのベクトルスコープ
+    Vectorscope,
+}
+
+//ヴィジュアライザーを作るときの設定
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VisualizerConfig {
+    pub ballistics: BallisticsConfig,
+    pub bars: BarsConfig,
+    pub levels: LevelRange,
+}
+
+impl VisualizerMode {
+
+    pub fn create(self, palette: &Palette, config: &VisualizerConfig) -> Box<dyn Visualizer> {
+        match self {
+            VisualizerMode::Bars => Box::new(SpectrumVisualizer::new(config.ballistics, config.bars, config.levels)),
+            VisualizerMode::Mirrored => Box::new(MirroredVisualizer::new(config.levels)),
+            VisualizerMode::Oscilloscope => Box::new(OscilloscopeVisualizer::new()),
+            VisualizerMode::Spectrogram => Box::new(SpectrogramVisualizer::new(palette.clone(), config.levels)),
+            VisualizerMode::Radial => Box::new(RadialVisualizer::new(config.levels)),
+            VisualizerMode::Vectorscope => Box::new(VectorscopeVisualizer::new()),
+        }
+    }
+
+}
+
+//全モードのヴィジュアライザーを保持して、実行中に切り替える
+//切り替えても状態(スペクトログラムの履歴など)が消えないように全て作っておく
+pub struct VisualizerRegistry {
+    visualizers: Vec<(VisualizerMode, Box<dyn Visualizer>)>,
+    current: usize,
+    colormap: Colormap,
+    //カバー画像から作った色(Colormap::Artworkのときに使う)
+    artwork: Option<Vec<(u8, u8, u8)>>,
+    themes: ThemeSet,
+}
+
+impl VisualizerRegistry {
+
+    pub fn new(initial: VisualizerMode, colormap: Colormap, config: &VisualizerConfig) -> Self {
+
+        let palette = Palette::Colormap(colormap);
+
+        let visualizers: Vec<(VisualizerMode, Box<dyn Visualizer>)> = VisualizerMode::value_variants()
+        .iter()
+        .map(|&mode| (mode, mode.create(&palette, config)))
+        .collect();
+
+        let current = visualizers.iter().position(|(mode, _)| *mode == initial).unwrap_or(0);
+
+        let mut registry = VisualizerRegistry { visualizers, current, colormap, artwork: None, themes: ThemeSet::default() };
+        registry.apply_theme();
+
+        registry
+
+    }
+
+    pub fn mode(&self) -> VisualizerMode {
+        self.visualizers[self.current].0
+    }
+
+    //次のモードに切り替える。最後のモードの次は最初に戻る
+    pub fn cycle(&mut self) -> VisualizerMode {
+        self.current = (self.current + 1) % self.visualizers.len();
+        self.mode()
+    }
+
+    pub fn colormap(&self) -> Colormap {
+        self.colormap
+    }
+
+    //次のカラーマップに切り替える
+    pub fn cycle_colormap(&mut self) -> Colormap {
+
+        self.colormap = self.colormap.cycle();
+        self.apply_palette();
+
+        self.colormap
+
+    }
+
+    //トラックが変わったらカバー画像の色を差し替える(画像が無ければNone)
+    pub fn set_artwork_palette(&mut self, artwork: Option<Vec<(u8, u8, u8)>>) {
+
+        self.artwork = artwork;
+
+        if self.colormap == Colormap::Artwork {
+            self.apply_palette();
+        }
+
+    }
+
+    pub fn palette(&self) -> Palette {
+        match (&self.colormap, &self.artwork) {
+            (Colormap::Artwork, Some(stops)) => Palette::Custom(stops.clone()),
+            (colormap, _) => Palette::Colormap(*colormap),
+        }
+    }
+
+    fn apply_palette(&mut self) {
+
+        let palette = self.palette();
+
+        for (_, visualizer) in self.visualizers.iter_mut() {
+            visualizer.set_palette(&palette);
+        }
+
+    }
+
+    //ユーザーのテーマを読み込んだテーマの一覧に差し替える
+    pub fn set_themes(&mut self, themes: ThemeSet) {
+        self.themes = themes;
+        self.apply_theme();
+    }
+
+    pub fn theme(&self) -> &Theme {
+        self.themes.current()
+    }
+
+    //次のテーマに切り替えて、その名前を返す
+    pub fn cycle_theme(&mut self) -> &str {
+        self.themes.cycle();
+        self.apply_theme();
+        &self.themes.current().name
+    }
+
+    fn apply_theme(&mut self) {
+
+        let theme = self.themes.current();
+
+        for (_, visualizer) in self.visualizers.iter_mut() {
+            visualizer.set_theme(theme);
+        }
+
+    }
+
+    pub fn update(&mut self, spectrum_data: &SpectrumData) {
+        for (_, visualizer) in self.visualizers.iter_mut() {
+            visualizer.update(spectrum_data);
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        //テーマの背景色と枠線の色を先に塗っておく(枠線は前景色をそのまま使う)
+        let theme = self.themes.current();
+        let mut style = Style::default();
+        if let Some(background) = theme.background() {
+            style = style.bg(background);
+        }
+        if let Some(border) = theme.border() {
+            style = style.fg(border);
+        }
+        frame.buffer_mut().set_style(area, style);
+
+        self.visualizers[self.current].1.draw(frame, area, spectrum_data);
+
+    }
+
+    //表示中のヴィジュアライザーで、マウスを重ねたセルの説明を返す
+    pub fn inspect(&self, area: Rect, column: u16, row: u16, spectrum_data: &SpectrumData) -> Option<String> {
+        self.visualizers[self.current].1.inspect(area, column, row, spectrum_data)
+    }
+
+}
+
+#[cfg(test)]
+mod test_registry {
+
+    use super::*;
+    use crate::audio::analyzer::AudioAnalyzer;
+    use crate::audio::scaling::AmplitudeScale;
+    use crate::visualizer::vectorscope::VectorscopeVisualizer;
+    use ratatui::{backend::TestBackend, Terminal};
+    use std::f32::consts::PI;
+
+    fn spectrum() -> SpectrumData {
+        let samples: Vec<f32> = (0..1024).map(|i| (2.0 * PI * 1000.0 * i as f32 / 44100.0).sin()).collect();
+        let mut data = AudioAnalyzer::new(1024, 44100).unwrap().analyze(&samples).unwrap();
+        data.waveform.channels = vec![samples.clone(), samples.iter().map(|s| -s).collect()];
+        data
+    }
+
+    #[test]
+    fn test_cycle_visits_every_mode() {
+        let mut registry = VisualizerRegistry::new(VisualizerMode::Radial, Colormap::Viridis, &VisualizerConfig::default());
+        assert_eq!(registry.mode(), VisualizerMode::Radial);
+
+        let mut seen = vec![registry.mode()];
+        while registry.cycle() != VisualizerMode::Radial {
+            seen.push(registry.mode());
+        }
+        assert_eq!(seen.len(), VisualizerMode::value_variants().len());
+    }
+
+    #[test]
+    fn test_every_mode_draws_in_any_size() {
+        let data = spectrum();
+        let mut registry = VisualizerRegistry::new(VisualizerMode::Bars, Colormap::Inferno, &VisualizerConfig::default());
+        registry.update(&data);
+
+        //枠線しか入らない狭さでもpanicしないこと
+        for (width, height) in [(80, 24), (3, 3), (1, 1)] {
+            let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+            for _ in VisualizerMode::value_variants() {
+                terminal.draw(|frame| registry.draw(frame, frame.area(), Some(&data))).unwrap();
+                terminal.draw(|frame| registry.draw(frame, frame.area(), None)).unwrap();
+                registry.cycle();
+            }
+        }
+    }
+
+    #[test]
+    fn test_artwork_palette_falls_back_to_colormap() {
+        let mut registry = VisualizerRegistry::new(VisualizerMode::Spectrogram, Colormap::Grayscale, &VisualizerConfig::default());
+        registry.set_artwork_palette(Some(vec![(0, 0, 0), (255, 0, 0)]));
+        assert_eq!(registry.palette(), Palette::Colormap(Colormap::Grayscale));
+
+        assert_eq!(registry.cycle_colormap(), Colormap::Artwork);
+        assert_eq!(registry.palette(), Palette::Custom(vec![(0, 0, 0), (255, 0, 0)]));
+
+        registry.set_artwork_palette(None);
+        assert_eq!(registry.palette(), Palette::Colormap(Colormap::Artwork));
+    }
+
+    #[test]
+    fn test_theme_colors_the_background() {
+        let mut registry = VisualizerRegistry::new(VisualizerMode::Radial, Colormap::Viridis, &VisualizerConfig::default());
+        assert_eq!(registry.theme().name, "rainbow");
+
+        let mut themes = ThemeSet::builtin().unwrap();
+        themes.select("ocean").unwrap();
+        registry.set_themes(themes);
+        let background = registry.theme().background().unwrap();
+
+        //Canvasを使うモードでも背景色が残ること
+        let mut terminal = Terminal::new(TestBackend::new(20, 10)).unwrap();
+        terminal.draw(|frame| registry.draw(frame, frame.area(), Some(&spectrum()))).unwrap();
+        assert_eq!(terminal.backend().buffer()[(10, 5)].bg, background);
+
+        assert_eq!(registry.cycle_theme(), "mono");
+    }
+
+    #[test]
+    fn test_inspect_describes_the_hovered_bar() {
+        let mut registry = VisualizerRegistry::new(VisualizerMode::Bars, Colormap::Viridis, &VisualizerConfig::default());
+        let data = spectrum();
+        //100x40の枠に対して、棒グラフは(10, 10)から幅80、高さ20
+        let area = Rect::new(0, 0, 100, 40);
+
+        let tooltip = registry.inspect(area, 13, 20, &data).unwrap();
+        assert!(tooltip.ends_with("dBFS"), "{}", tooltip);
+        assert!(tooltip.contains(" Hz "), "{}", tooltip);
+        assert!(registry.inspect(area, 5, 20, &data).is_none());
+        assert!(registry.inspect(area, 13, 35, &data).is_none());
+
+        //線形のビンはDC成分を除いているので、最初の棒は1番目のビンの周波数になる
+        let mut linear = data.clone();
+        linear.bands.clear();
+        linear.bins = vec![1.0, 0.5, 0.25, 0.125];
+        linear.fft_size = 8;
+        linear.scale = AmplitudeScale::Linear;
+        assert_eq!(registry.inspect(area, 10, 20, &linear).unwrap(), "5.5k Hz  0.0 dBFS");
+
+        //AGCの倍率はかかる前の値で表す
+        linear.agc_gain = Some(4.0);
+        assert_eq!(registry.inspect(area, 10, 20, &linear).unwrap(), "5.5k Hz  -12.0 dBFS");
+
+        //オシロスコープには周波数ごとの値が無い
+        registry.cycle();
+        registry.cycle();
+        assert_eq!(registry.mode(), VisualizerMode::Oscilloscope);
+        assert!(registry.inspect(area, 13, 20, &data).is_none());
+    }
+
+    #[test]
+    fn test_vectorscope_points() {
+        //同相(モノラル)は縦軸上、逆相は横軸上に並ぶ
+        let mono = VectorscopeVisualizer::points(&[vec![0.5]]);
+        assert!(mono[0].0.abs() < 1e-6 && mono[0].1 > 0.0);
+
+        let inverted = VectorscopeVisualizer::points(&[vec![0.5], vec![-0.5]]);
+        assert!(inverted[0].1.abs() < 1e-6 && inverted[0].0 < 0.0);
+    }
+
+}
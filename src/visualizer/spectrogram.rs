@@ -0,0 +1,186 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Style},
+};
+use std::collections::VecDeque;
+
+use crate::audio::analyzer::SpectrumData;
+use crate::visualizer::theme::{ColorDepth, Theme};
+use crate::visualizer::visualize_color::{Colormap, Palette};
+use crate::visualizer::visualizer::{format_frequency, visualizer_block, LevelRange, SpectrumVisualizer, Visualizer};
+
+//保持するフレーム数の上限(これより広い端末では左側が空く)
+const HISTORY_LENGTH: usize = 512;
+
+//左端の周波数ラベルの幅と、ラベルを置く行の間隔
+const LABEL_WIDTH: u16 = 5;
+const LABEL_SPACING: u16 = 3;
+
+//横軸を時間(右端が最新)、縦軸を周波数(下が低域)にして流れるスペクトログラム
+//1セルを上下2つに分けて(▀)縦方向の解像度を2倍にする
+#[derive(Debug)]
+pub struct SpectrogramVisualizer {
+    //直近のフレームの帯域の値(リングバッファ。古いものから順に捨てる)
+    history: VecDeque<Vec<f32>>,
+    //historyの値が帯域(true)か線形ビン(false)か
+    grouped: bool,
+    //最新のフレームの各値の中心周波数(ラベル用)
+    frequencies: Vec<f32>,
+    palette: Palette,
+    levels: LevelRange,
+    //色はテーマではなくカラーマップで決めるが、端末の色数には合わせる
+    depth: ColorDepth,
+}
+
+impl Default for SpectrogramVisualizer {
+    fn default() -> Self {
+        Self::new(Palette::Colormap(Colormap::Viridis), LevelRange::default())
+    }
+}
+
+impl SpectrogramVisualizer {
+
+    pub fn new(palette: Palette, levels: LevelRange) -> Self {
+        SpectrogramVisualizer {
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            grouped: false,
+            frequencies: Vec::new(),
+            palette,
+            levels,
+            depth: ColorDepth::default(),
+        }
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    fn color(&self, magnitude: f32) -> Color {
+        self.depth.color(self.palette.rgb(self.levels.level(magnitude)))
+    }
+
+    //周波数ラベルを描画して、残りのスペクトログラムを描く領域を返す
+    fn draw_labels(&self, frame: &mut Frame, inner: Rect, sub_rows: usize) -> Rect {
+
+        //狭いときはラベルを省略する
+        if self.frequencies.is_empty() || inner.width <= LABEL_WIDTH * 2 {
+            return inner;
+        }
+
+        for row in (0..inner.height).step_by(LABEL_SPACING as usize) {
+
+            //fit_valuesでその行の下半分に入る最初の値の周波数
+            let index = (row as usize * 2) * self.frequencies.len() / sub_rows;
+            let Some(&hz) = self.frequencies.get(index) else { continue };
+
+            let label = format!("{:>width$}", format_frequency(hz), width = LABEL_WIDTH as usize - 1);
+            frame.buffer_mut().set_string(inner.left(), inner.bottom() - 1 - row, label, Style::default().fg(Color::Gray));
+        }
+
+        Rect::new(inner.left() + LABEL_WIDTH, inner.top(), inner.width - LABEL_WIDTH, inner.height)
+
+    }
+
+}
+
+impl Visualizer for SpectrogramVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Spectrogram"
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.depth = theme.depth;
+    }
+
+    fn update(&mut self, spectrum_data: &SpectrumData) {
+
+        let grouped = !spectrum_data.bands.is_empty();
+
+        let (values, frequencies): (Vec<f32>, Vec<f32>) = if grouped {
+            spectrum_data.bands.iter().map(|b| (spectrum_data.scale.to_magnitude(b.value), b.center_hz)).unzip()
+        } else {
+            spectrum_data.bins.iter().enumerate().map(|(i, &v)| (spectrum_data.scale.to_magnitude(v), spectrum_data.bin_frequency(i))).unzip()
+        };
+
+        //帯域の並びが変わったら過去のフレームとは縦軸が合わないので捨てる
+        if grouped != self.grouped || frequencies != self.frequencies {
+            self.history.clear();
+            self.grouped = grouped;
+            self.frequencies = frequencies;
+        }
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(values);
+
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, _spectrum_data: Option<&SpectrumData>) {
+
+        let block = visualizer_block(self.name());
+        let inner = block.inner(area);
+        frame.render_widget(&block, area);
+
+        if inner.width == 0 || inner.height == 0 {
+            return;
+        }
+
+        let sub_rows = inner.height as usize * 2;
+        let plot = self.draw_labels(frame, inner, sub_rows);
+
+        let visible = self.history.len().min(plot.width as usize);
+        //最新のフレームを右端に揃える
+        let first_x = plot.right() - visible as u16;
+
+        for (i, values) in self.history.iter().skip(self.history.len() - visible).enumerate() {
+
+            let column = SpectrumVisualizer::fit_values(values, sub_rows, self.grouped);
+            let x = first_x + i as u16;
+
+            //下半分が低い方の値、上半分が高い方の値
+            for (row, pair) in column.chunks(2).enumerate() {
+
+                let lower = self.color(pair[0]);
+                let upper = pair.get(1).map(|&v| self.color(v)).unwrap_or(lower);
+                let y = plot.bottom() - 1 - row as u16;
+
+                frame.buffer_mut().set_string(x, y, "▀", Style::default().fg(upper).bg(lower));
+            }
+        }
+
+    }
+
+}
+
+#[cfg(test)]
+mod test_spectrogram {
+
+    use super::*;
+    use crate::audio::analyzer::{AnalyzerConfig, AudioAnalyzer};
+    use crate::audio::bands::{BandLayout, BandScale};
+
+    #[test]
+    fn test_history_is_bounded_ring_buffer() {
+        let mut spectrogram = SpectrogramVisualizer::new(Palette::Colormap(Colormap::Magma), LevelRange::default());
+        let data = AudioAnalyzer::new(256, 44100).unwrap().analyze(&[0.0; 256]).unwrap();
+
+        for _ in 0..HISTORY_LENGTH + 10 {
+            spectrogram.update(&data);
+        }
+        assert_eq!(spectrogram.history_len(), HISTORY_LENGTH);
+
+        //帯域の並びが変わったら履歴は捨てる
+        let config = AnalyzerConfig { fft_size: 256, hop_size: 256, band_layout: BandLayout { scale: BandScale::Mel, count: 16 }, ..Default::default() };
+        let other = AudioAnalyzer::with_config(config, 44100).unwrap().analyze(&[0.0; 256]).unwrap();
+        spectrogram.update(&other);
+        assert_eq!(spectrogram.history_len(), 1);
+    }
+
+}
@@ -0,0 +1,434 @@
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::error::FerriaError;
+use crate::visualizer::visualize_color::{get_grayish_color, interpolate_stops};
+
+pub const DEFAULT_THEME: &str = "rainbow";
+
+//peakが無いときに棒の色を残す割合
+const DEFAULT_PEAK_TINT: f32 = 0.4;
+
+//組み込みのテーマ。ユーザーのテーマと同じ形式
+const BUILTIN_THEMES: [&str; 4] = [
+    include_str!("themes/rainbow.toml"),
+    include_str!("themes/fire.toml"),
+    include_str!("themes/ocean.toml"),
+    include_str!("themes/mono.toml"),
+];
+
+//xtermの16色の既定値
+const ANSI_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+//256色の6x6x6のカラーキューブの各段階
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+//端末が表示できる色数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    #[default]
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+
+    //環境変数から推測する。FERRIA_COLORSで上書きできる
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+
+        match var("FERRIA_COLORS").as_deref() {
+            Some("truecolor" | "24bit") => return ColorDepth::TrueColor,
+            Some("256") => return ColorDepth::Ansi256,
+            Some("16") => return ColorDepth::Ansi16,
+            _ => {},
+        }
+
+        if matches!(var("COLORTERM").as_deref(), Some("truecolor" | "24bit")) {
+            return ColorDepth::TrueColor;
+        }
+
+        let term = var("TERM").unwrap_or_default();
+
+        if term.contains("256color") || var("TERM_PROGRAM").as_deref() == Some("Apple_Terminal") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+
+    }
+
+    //表示できる一番近い色にする
+    pub fn color(&self, (r, g, b): (u8, u8, u8)) -> Color {
+        match self {
+            ColorDepth::TrueColor => Color::Rgb(r, g, b),
+            ColorDepth::Ansi256 => Color::Indexed(nearest_256(r, g, b)),
+            ColorDepth::Ansi16 => nearest_16(r, g, b),
+        }
+    }
+
+    //Color::Rgb以外(名前付きの色など)はそのまま
+    pub fn convert(&self, color: Color) -> Color {
+        match color {
+            Color::Rgb(r, g, b) => self.color((r, g, b)),
+            other => other,
+        }
+    }
+
+}
+
+fn distance((r0, g0, b0): (u8, u8, u8), (r1, g1, b1): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r0, r1) + d(g0, g1) + d(b0, b1)
+}
+
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+
+    let level = |v: u8| (0..CUBE_LEVELS.len()).min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs()).unwrap_or(0);
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+
+    //232〜255は8から10刻みのグレー
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = ((average.saturating_sub(8) + 5) / 10).min(23);
+    let gray_value = (8 + gray_index * 10) as u8;
+
+    if distance((r, g, b), (gray_value, gray_value, gray_value)) < distance((r, g, b), cube) {
+        232 + gray_index as u8
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+
+}
+
+fn nearest_16(r: u8, g: u8, b: u8) -> Color {
+    ANSI_COLORS.iter()
+    .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+    .map(|(color, _)| *color)
+    .unwrap_or(Color::Reset)
+}
+
+//グラデーションをどの値で選ぶか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorBy {
+    //棒の高さ(下から上へグラデーション)
+    #[default]
+    Height,
+    //棒の周波数(左から右へグラデーション)
+    Frequency,
+}
+
+//ヴィジュアライザーの配色
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub gradient: Vec<(u8, u8, u8)>,
+    pub color_by: ColorBy,
+    pub background: Option<(u8, u8, u8)>,
+    pub border: Option<(u8, u8, u8)>,
+    //無ければ棒の色を灰色に寄せた色
+    pub peak: Option<(u8, u8, u8)>,
+    //peakが無いときに棒の色を残す割合(0.0で灰色、1.0で棒と同じ色)
+    pub peak_tint: f32,
+    pub depth: ColorDepth,
+}
+
+//TOMLのテーマファイル
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: Option<String>,
+    gradient: Vec<String>,
+    #[serde(default)]
+    color_by: ColorBy,
+    background: Option<String>,
+    border: Option<String>,
+    peak: Option<String>,
+    peak_tint: Option<f32>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::from_toml(BUILTIN_THEMES[0], DEFAULT_THEME).unwrap_or_else(|_| Theme {
+            name: DEFAULT_THEME.to_string(),
+            gradient: vec![(255, 255, 255)],
+            color_by: ColorBy::Frequency,
+            background: None,
+            border: None,
+            peak: None,
+            peak_tint: DEFAULT_PEAK_TINT,
+            depth: ColorDepth::TrueColor,
+        })
+    }
+}
+
+impl Theme {
+
+    //nameはファイルにnameが無いときに使う名前
+    pub fn from_toml(text: &str, name: &str) -> Result<Self, FerriaError> {
+
+        let file: ThemeFile = toml::from_str(text)
+        .map_err(|e| FerriaError::VisualizerError(format!("Invalid theme `{}`: {}", name, e.message())))?;
+
+        if file.gradient.is_empty() {
+            return Err(FerriaError::VisualizerError(format!("Theme `{}` needs at least one gradient colour", name)));
+        }
+
+        let peak_tint = file.peak_tint.unwrap_or(DEFAULT_PEAK_TINT);
+        if !(0.0..=1.0).contains(&peak_tint) {
+            return Err(FerriaError::VisualizerError(format!("Theme `{}`: peak_tint must be between 0.0 and 1.0", name)));
+        }
+
+        let color = |value: &String| parse_hex_color(value)
+        .ok_or_else(|| FerriaError::VisualizerError(format!("Theme `{}`: `{}` is not a colour like \"#1e90ff\"", name, value)));
+
+        Ok(Theme {
+            name: file.name.unwrap_or_else(|| name.to_string()),
+            gradient: file.gradient.iter().map(color).collect::<Result<_, _>>()?,
+            color_by: file.color_by,
+            background: file.background.as_ref().map(color).transpose()?,
+            border: file.border.as_ref().map(color).transpose()?,
+            peak: file.peak.as_ref().map(color).transpose()?,
+            peak_tint,
+            depth: ColorDepth::TrueColor,
+        })
+
+    }
+
+    //positionは棒の左からの位置、heightは棒の高さ(どちらも0.0〜1.0)
+    pub fn bar_color(&self, position: f32, height: f32) -> Color {
+        let f = match self.color_by {
+            ColorBy::Height => height,
+            ColorBy::Frequency => position,
+        };
+        self.depth.color(interpolate_stops(&self.gradient, f))
+    }
+
+    pub fn peak_color(&self, bar_color: Color) -> Color {
+        match self.peak {
+            Some(rgb) => self.depth.color(rgb),
+            None => self.depth.convert(get_grayish_color(bar_color, self.peak_tint)),
+        }
+    }
+
+    pub fn background(&self) -> Option<Color> {
+        self.background.map(|rgb| self.depth.color(rgb))
+    }
+
+    pub fn border(&self) -> Option<Color> {
+        self.border.map(|rgb| self.depth.color(rgb))
+    }
+
+}
+
+//"#rrggbb" か "rrggbb"
+pub fn parse_hex_color(value: &str) -> Option<(u8, u8, u8)> {
+
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+    Some((channel(0)?, channel(2)?, channel(4)?))
+
+}
+
+//組み込みとユーザーのテーマの一覧と、選ばれているテーマ
+#[derive(Debug, Clone)]
+pub struct ThemeSet {
+    themes: Vec<Theme>,
+    current: usize,
+}
+
+impl Default for ThemeSet {
+    fn default() -> Self {
+        Self::builtin().unwrap_or_else(|_| ThemeSet { themes: vec![Theme::default()], current: 0 })
+    }
+}
+
+impl ThemeSet {
+
+    pub fn builtin() -> Result<Self, FerriaError> {
+
+        let themes = BUILTIN_THEMES.iter()
+        .map(|text| Theme::from_toml(text, "builtin"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ThemeSet { themes, current: 0 })
+
+    }
+
+    //組み込みのテーマに、dir/*.tomlのテーマを加える(同じ名前なら置き換える)
+    pub fn load(dir: Option<&Path>) -> Result<Self, FerriaError> {
+
+        let mut set = Self::builtin()?;
+
+        let Some(dir) = dir.filter(|dir| dir.is_dir()) else { return Ok(set) };
+
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+        paths.sort();
+
+        for path in paths {
+
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let text = fs::read_to_string(&path)?;
+            let theme = Theme::from_toml(&text, &stem)
+            .map_err(|e| FerriaError::VisualizerError(format!("{} ({})", e, path.display())))?;
+
+            set.add(theme);
+        }
+
+        Ok(set)
+
+    }
+
+    pub fn add(&mut self, theme: Theme) {
+        match self.themes.iter_mut().find(|existing| existing.name == theme.name) {
+            Some(existing) => *existing = theme,
+            None => self.themes.push(theme),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.themes.iter().map(|theme| theme.name.as_str()).collect()
+    }
+
+    pub fn select(&mut self, name: &str) -> Result<(), FerriaError> {
+
+        self.current = self.themes.iter().position(|theme| theme.name == name)
+        .ok_or_else(|| FerriaError::VisualizerError(format!("Unknown theme `{}` (available: {})", name, self.names().join(", "))))?;
+
+        Ok(())
+
+    }
+
+    pub fn set_depth(&mut self, depth: ColorDepth) {
+        for theme in self.themes.iter_mut() {
+            theme.depth = depth;
+        }
+    }
+
+    pub fn current(&self) -> &Theme {
+        &self.themes[self.current]
+    }
+
+    //次のテーマに切り替える
+    pub fn cycle(&mut self) -> &Theme {
+        self.current = (self.current + 1) % self.themes.len();
+        self.current()
+    }
+
+}
+
+#[cfg(test)]
+mod test_theme {
+
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes_parse() {
+        let mut themes = ThemeSet::builtin().unwrap();
+        assert_eq!(themes.names(), vec!["rainbow", "fire", "ocean", "mono"]);
+        assert_eq!(themes.current().name, DEFAULT_THEME);
+
+        themes.select("ocean").unwrap();
+        assert_eq!(themes.cycle().name, "mono");
+        assert_eq!(themes.cycle().name, "rainbow");
+        assert!(themes.select("missing").is_err());
+    }
+
+    #[test]
+    fn test_theme_from_toml() {
+        let theme = Theme::from_toml(r##"
+            gradient = ["#000000", "#ff0000", "ffffff"]
+            color_by = "frequency"
+            peak = "#00ff00"
+        "##, "custom").unwrap();
+
+        assert_eq!(theme.name, "custom");
+        assert_eq!(theme.color_by, ColorBy::Frequency);
+        assert_eq!(theme.bar_color(0.5, 0.0), Color::Rgb(255, 0, 0));
+        assert_eq!(theme.peak_color(Color::Red), Color::Rgb(0, 255, 0));
+        assert_eq!(theme.background(), None);
+
+        //peakが無ければ棒の色をpeak_tintの割合だけ残して灰色に寄せる
+        let theme = Theme::from_toml("gradient = [\"#ffffff\"]\npeak_tint = 0.0", "tint").unwrap();
+        assert_eq!(theme.peak_color(Color::Rgb(255, 0, 0)), Color::Rgb(128, 128, 128));
+        let theme = Theme::from_toml("gradient = [\"#ffffff\"]\npeak_tint = 1.0", "tint").unwrap();
+        assert_eq!(theme.peak_color(Color::Rgb(255, 0, 0)), Color::Rgb(255, 0, 0));
+        assert!(Theme::from_toml("gradient = [\"#ffffff\"]\npeak_tint = 1.5", "tint").is_err());
+
+        assert!(Theme::from_toml("gradient = []", "empty").is_err());
+        assert!(Theme::from_toml("gradient = [\"#12345\"]", "short").is_err());
+        assert!(Theme::from_toml("gradient = [\"#123456\"]\nunknown = 1", "unknown").is_err());
+        assert!(Theme::from_toml("gradient = [\"#123456\"]\ncolor_by = \"angle\"", "color_by").is_err());
+    }
+
+    #[test]
+    fn test_user_themes_override_builtin() {
+        let dir = std::env::temp_dir().join(format!("ferria_themes_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("fire.toml"), "gradient = [\"#ffffff\"]").unwrap();
+        fs::write(dir.join("neon.toml"), "gradient = [\"#ff00ff\", \"#00ffff\"]").unwrap();
+
+        let mut themes = ThemeSet::load(Some(&dir)).unwrap();
+        assert_eq!(themes.names(), vec!["rainbow", "fire", "ocean", "mono", "neon"]);
+        themes.select("fire").unwrap();
+        assert_eq!(themes.current().gradient, vec![(255, 255, 255)]);
+
+        fs::write(dir.join("broken.toml"), "gradient = 1").unwrap();
+        let error = ThemeSet::load(Some(&dir)).unwrap_err().to_string();
+        assert!(error.contains("broken.toml"), "{}", error);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_color_depth() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| move |name: &str| {
+            pairs.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        };
+
+        assert_eq!(ColorDepth::from_env(env(&[("COLORTERM", "truecolor"), ("TERM", "xterm")])), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_env(env(&[("TERM", "xterm-256color")])), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::from_env(env(&[("TERM", "linux")])), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_env(env(&[("COLORTERM", "truecolor"), ("FERRIA_COLORS", "16")])), ColorDepth::Ansi16);
+
+        assert_eq!(ColorDepth::Ansi256.color((255, 0, 0)), Color::Indexed(196));
+        assert_eq!(ColorDepth::Ansi256.color((128, 128, 128)), Color::Indexed(244));
+        assert_eq!(ColorDepth::Ansi16.color((250, 10, 10)), Color::LightRed);
+        assert_eq!(ColorDepth::Ansi16.color((0, 0, 200)), Color::Blue);
+        assert_eq!(ColorDepth::Ansi16.convert(Color::DarkGray), Color::DarkGray);
+    }
+
+}
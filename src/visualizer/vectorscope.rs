@@ -0,0 +1,133 @@
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Color,
+    symbols::Marker,
+    widgets::canvas::{Canvas, Line, Points},
+};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::audio::analyzer::SpectrumData;
+use crate::visualizer::radial::aspect_ratio;
+use crate::visualizer::theme::Theme;
+use crate::visualizer::visualizer::{visualizer_block, Visualizer};
+
+//点の色を分ける段階の数
+const COLOR_LEVELS: usize = 8;
+
+//左右チャンネルの相関をリサージュ図形で表示するベクトルスコープ
+//モノラルは縦の線、逆相成分が多いほど横に広がる
+#[derive(Debug, Default)]
+pub struct VectorscopeVisualizer {
+    theme: Theme,
+}
+
+impl VectorscopeVisualizer {
+
+    pub fn new() -> Self {
+        VectorscopeVisualizer::default()
+    }
+
+    //L/Rのサンプルを45度回転させた座標(x = サイド成分, y = ミッド成分)
+    //モノラルの場合はL = Rとして扱う
+    pub(crate) fn points(waveform: &[Vec<f32>]) -> Vec<(f64, f64)> {
+
+        let (left, right) = match waveform {
+            [] => return Vec::new(),
+            [mono] => (mono, mono),
+            [left, right, ..] => (left, right),
+        };
+
+        left.iter()
+        .zip(right)
+        .map(|(&l, &r)| (((r - l) * FRAC_1_SQRT_2) as f64, ((l + r) * FRAC_1_SQRT_2) as f64))
+        .collect()
+
+    }
+
+    //中心からの距離ごとに点を分け、テーマのグラデーションで色を付ける(外側ほど音が大きい)
+    pub(crate) fn color_layers(&self, points: &[(f64, f64)]) -> Vec<(Color, Vec<(f64, f64)>)> {
+
+        let mut layers = vec![Vec::new(); COLOR_LEVELS];
+
+        for &(x, y) in points {
+            let level = ((x * x + y * y).sqrt().min(1.0) * (COLOR_LEVELS - 1) as f64).round() as usize;
+            layers[level].push((x, y));
+        }
+
+        layers.into_iter()
+        .enumerate()
+        .filter(|(_, coords)| !coords.is_empty())
+        .map(|(level, coords)| {
+            let f = level as f32 / (COLOR_LEVELS - 1) as f32;
+            (self.theme.bar_color(f, f), coords)
+        })
+        .collect()
+
+    }
+
+}
+
+impl Visualizer for VectorscopeVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Vectorscope"
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        let block = visualizer_block(self.name());
+        let aspect = aspect_ratio(block.inner(area));
+
+        let points = spectrum_data
+        .map(|data| Self::points(&data.waveform.channels))
+        .unwrap_or_default();
+        let layers = self.color_layers(&points);
+
+        //軸の線はテーマの枠線の色
+        let axis_color = self.theme.border().unwrap_or(Color::DarkGray);
+
+        let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::Braille)
+        .background_color(self.theme.background().unwrap_or(Color::Reset))
+        .x_bounds([-aspect, aspect])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+            //L(左上)とR(右上)の軸
+            ctx.draw(&Line::new(-FRAC_1_SQRT_2 as f64, -FRAC_1_SQRT_2 as f64, FRAC_1_SQRT_2 as f64, FRAC_1_SQRT_2 as f64, axis_color));
+            ctx.draw(&Line::new(FRAC_1_SQRT_2 as f64, -FRAC_1_SQRT_2 as f64, -FRAC_1_SQRT_2 as f64, FRAC_1_SQRT_2 as f64, axis_color));
+            for (color, coords) in &layers {
+                ctx.layer();
+                ctx.draw(&Points { coords, color: *color });
+            }
+        });
+
+        frame.render_widget(canvas, area);
+
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+    }
+
+}
+
+#[cfg(test)]
+mod test_vectorscope {
+
+    use super::*;
+
+    #[test]
+    fn test_color_layers_follow_theme() {
+        let mut vectorscope = VectorscopeVisualizer::new();
+        vectorscope.set_theme(&Theme::from_toml("gradient = [\"#000000\", \"#ffffff\"]\ncolor_by = \"height\"", "gray").unwrap());
+
+        let layers = vectorscope.color_layers(&[(0.0, 0.0), (0.0, 1.0), (0.6, 0.8), (2.0, 0.0)]);
+
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0], (Color::Rgb(0, 0, 0), vec![(0.0, 0.0)]));
+        assert_eq!(layers[1], (Color::Rgb(255, 255, 255), vec![(0.0, 1.0), (0.6, 0.8), (2.0, 0.0)]));
+    }
+
+}
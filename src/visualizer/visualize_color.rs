@@ -1,61 +1,146 @@
-use palette::encoding::srgb;
-use palette::{hsv, Hsv, Srgb};
-use palette::convert::IntoColorUnclamped;
+use clap::ValueEnum;
 use ratatui::style::Color;
 
-const GRAY_FACTOR: f32 = 0.4;
+//灰色に寄せた色。factorは元の色を残す割合(0.0で灰色、1.0で元の色)
+pub fn get_grayish_color(color: Color, factor: f32) -> Color {
+    match color {
+        Color::Rgb(r, g, b) => {
+            Color::Rgb(
+                (r as f32 * factor + 128.0 * (1.0 - factor)) as u8,
+                (g as f32 * factor + 128.0 * (1.0 - factor)) as u8,
+                (b as f32 * factor + 128.0 * (1.0 - factor)) as u8,)
+        },
+        _ => Color::DarkGray,
+    }
+}
+
+//スペクトログラムの強さを色に変換するカラーマップ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Grayscale,
+    ///再生中のトラックのカバー画像の色(画像が無ければViridis)
+    Artwork,
+}
+
+//matplotlibのカラーマップを等間隔に10点サンプリングしたもの。間は線形補間する
+const VIRIDIS: [(u8, u8, u8); 10] = [
+    (0x44, 0x01, 0x54), (0x48, 0x28, 0x78), (0x3e, 0x49, 0x89), (0x31, 0x68, 0x8e), (0x26, 0x82, 0x8e),
+    (0x1f, 0x9e, 0x89), (0x35, 0xb7, 0x79), (0x6e, 0xce, 0x58), (0xb5, 0xde, 0x2b), (0xfd, 0xe7, 0x25),
+];
 
-pub fn float_to_rgb_palette(f: f32) -> (u8, u8, u8) {
-    //正規化
-    let clamped_value = f.max(0.1).min(1.0);
-    let normalized_value = (clamped_value - 0.1) / (1.0 - 0.1);
+const MAGMA: [(u8, u8, u8); 10] = [
+    (0x00, 0x00, 0x04), (0x18, 0x0f, 0x3d), (0x44, 0x0f, 0x76), (0x72, 0x1f, 0x81), (0x9e, 0x2f, 0x7f),
+    (0xcd, 0x40, 0x71), (0xf1, 0x60, 0x5d), (0xfd, 0x96, 0x68), (0xfe, 0xca, 0x8d), (0xfc, 0xfd, 0xbf),
+];
 
-    //Hue 0.0 -> 300.0度にマッピング
-    //0.0は赤、60.0は黄、120.0は緑、180.0はシアン、240.0は青、300.0はマゼンタの手前
-    let hue = normalized_value * 300.0;
-    
-    //彩度 max
-    let saturation = 1.0;
-    //明度 max
-    let value = 1.0;
-     
-    let hsv_color = Hsv::new(hue, saturation, value);
-    
-    //HSVからsRGBに変換
-    let srgb_color: Srgb = hsv_color.into_color_unclamped();
+const INFERNO: [(u8, u8, u8); 10] = [
+    (0x00, 0x00, 0x04), (0x1b, 0x0c, 0x41), (0x4a, 0x0c, 0x6b), (0x78, 0x1c, 0x6d), (0xa5, 0x2c, 0x60),
+    (0xcf, 0x44, 0x46), (0xed, 0x69, 0x25), (0xfb, 0x9b, 0x06), (0xf7, 0xd1, 0x3d), (0xfc, 0xff, 0xa4),
+];
 
-    let r = (srgb_color.red * 255.0).round() as u8;
-    let g = (srgb_color.green * 255.0).round() as u8;
-    let b = (srgb_color.blue * 255.0).round() as u8;
+const GRAYSCALE: [(u8, u8, u8); 2] = [(0, 0, 0), (255, 255, 255)];
 
-    (r, g, b)
+impl Colormap {
+
+    pub fn cycle(self) -> Self {
+        match self {
+            Colormap::Viridis => Colormap::Magma,
+            Colormap::Magma => Colormap::Inferno,
+            Colormap::Inferno => Colormap::Grayscale,
+            Colormap::Grayscale => Colormap::Artwork,
+            Colormap::Artwork => Colormap::Viridis,
+        }
+    }
+
+    fn stops(self) -> &'static [(u8, u8, u8)] {
+        match self {
+            Colormap::Viridis | Colormap::Artwork => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Grayscale => &GRAYSCALE,
+        }
+    }
 
 }
 
-pub fn get_lighter_color(color: Color) -> Color {
-    match color {
-        Color::Rgb(r, g, b) => {
-            let factor = 0.5; // この値を小さくするとより薄く、大きくすると元の色に近くなる
-            Color::Rgb(
-                (r as f32 * factor + 255.0 * (1.0 - factor)) as u8,
-                (g as f32 * factor + 255.0 * (1.0 - factor)) as u8,
-                (b as f32 * factor + 255.0 * (1.0 - factor)) as u8,)
-        },
-        // その他の色タイプの場合、デフォルトの色を返すか、エラー処理
-        _ => Color::Gray, // RGB以外の色には単純な灰色を返す
+//ヴィジュアライザーに渡す色の並び
+//組み込みのカラーマップか、カバー画像から作った色
+#[derive(Debug, Clone, PartialEq)]
+pub enum Palette {
+    Colormap(Colormap),
+    Custom(Vec<(u8, u8, u8)>),
+}
+
+impl Palette {
+
+    pub fn rgb(&self, f: f32) -> (u8, u8, u8) {
+        match self {
+            Palette::Colormap(colormap) => colormap_to_rgb(*colormap, f),
+            Palette::Custom(stops) => interpolate_stops(stops, f),
+        }
     }
+
 }
 
-//灰色成分
-pub fn get_grayish_color(color: Color) -> Color {
-    match color {
-        Color::Rgb(r, g, b) => {
-            let factor = GRAY_FACTOR;
-            Color::Rgb(
-                (r as f32 * factor + 128.0 * (1.0 - factor)) as u8,
-                (g as f32 * factor + 128.0 * (1.0 - factor)) as u8,
-                (b as f32 * factor + 128.0 * (1.0 - factor)) as u8,)
-        },
-        _ => Color::DarkGray,
+//0.0〜1.0の値をカラーマップの色に変換する
+pub fn colormap_to_rgb(colormap: Colormap, f: f32) -> (u8, u8, u8) {
+    interpolate_stops(colormap.stops(), f)
+}
+
+//色の列を等間隔に並べて線形補間する
+pub(crate) fn interpolate_stops(stops: &[(u8, u8, u8)], f: f32) -> (u8, u8, u8) {
+
+    match stops {
+        [] => return (0, 0, 0),
+        [only] => return *only,
+        _ => {},
+    }
+
+    let position = f.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let index = (position.floor() as usize).min(stops.len() - 2);
+    let fraction = position - index as f32;
+
+    let (r0, g0, b0) = stops[index];
+    let (r1, g1, b1) = stops[index + 1];
+
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * fraction).round() as u8;
+
+    (lerp(r0, r1), lerp(g0, g1), lerp(b0, b1))
+
+}
+
+#[cfg(test)]
+mod test_visualize_color {
+
+    use super::*;
+
+    #[test]
+    fn test_colormap_endpoints() {
+        assert_eq!(colormap_to_rgb(Colormap::Viridis, 0.0), VIRIDIS[0]);
+        assert_eq!(colormap_to_rgb(Colormap::Viridis, 1.0), VIRIDIS[9]);
+        assert_eq!(colormap_to_rgb(Colormap::Magma, -1.0), MAGMA[0]);
+        assert_eq!(colormap_to_rgb(Colormap::Inferno, 2.0), INFERNO[9]);
+        assert_eq!(colormap_to_rgb(Colormap::Grayscale, 0.5), (128, 128, 128));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_custom_palette() {
+        let palette = Palette::Custom(vec![(0, 0, 0), (200, 100, 0)]);
+        assert_eq!(palette.rgb(0.5), (100, 50, 0));
+        assert_eq!(Palette::Custom(vec![(1, 2, 3)]).rgb(0.9), (1, 2, 3));
+        assert_eq!(Palette::Colormap(Colormap::Magma).rgb(0.0), MAGMA[0]);
+    }
+
+    #[test]
+    fn test_colormap_cycle_returns_to_start() {
+        let mut colormap = Colormap::Viridis;
+        for _ in 0..5 {
+            colormap = colormap.cycle();
+        }
+        assert_eq!(colormap, Colormap::Viridis);
+    }
+
+}
@@ -1,151 +1,393 @@
 
 use ratatui::{
     Frame,
-    layout::Rect,
-    widgets::{Block, Borders, Widget},
+    layout::{Position, Rect},
+    widgets::{Block, Borders},
     style::{Style, Color},
 };
 
-use crate::{audio::analyzer::SpectrumData, visualizer::{self, visualize_color::get_grayish_color}};
-use crate::visualizer::visualize_color;
+use crate::audio::{analyzer::SpectrumData, bands::Band};
+use crate::visualizer::visualize_color::Palette;
+use crate::visualizer::theme::Theme;
+use crate::visualizer::ballistics::{Ballistics, BallisticsConfig, FrameInterpolator};
+use crate::visualizer::glyphs::{eighth_block, BarStyle, BrailleCanvas, BRAILLE_DOTS_X};
+use std::time::{Duration, Instant};
 
+//解析結果をratatuiのFrameに描画するヴィジュアライザー
+//描画モードごとに実装し、VisualizerRegistryで切り替える
+pub trait Visualizer {
+
+    //ブロックのタイトルに表示する名前
+    fn name(&self) -> &'static str;
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>);
+
+    //解析スレッドから新しいフレームが届くたびに呼ばれる
+    //履歴を持つヴィジュアライザーだけが実装する(drawは同じフレームで何度も呼ばれるため)
+    fn update(&mut self, _spectrum_data: &SpectrumData) {}
+
+    //カラーマップを使うヴィジュアライザーだけが実装する
+    fn set_palette(&mut self, _palette: &Palette) {}
+
+    //テーマが切り替わったときに呼ばれる。テーマの色を使うヴィジュアライザーだけが実装する
+    fn set_theme(&mut self, _theme: &Theme) {}
+
+    //マウスを重ねたセル(column, row)の説明。ツールチップに出す
+    //周波数ごとの値を描くヴィジュアライザーだけが実装する
+    fn inspect(&self, _area: Rect, _column: u16, _row: u16, _spectrum_data: &SpectrumData) -> Option<String> {
+        None
+    }
+
+}
+
+//縦棒のスペクトラム表示
 pub struct SpectrumVisualizer {
-    prev_bar_heights: Vec<u16>,
+    ballistics: Ballistics,
+    bars: BarsConfig,
+    levels: LevelRange,
+    theme: Theme,
+    //BallisticsConfig::interpolateのときだけ使う
+    interpolator: FrameInterpolator,
+    last_draw: Option<Instant>,
 }
 
-const MIN_DB: f32 = -60.0;
-const MAX_DB: f32 = 0.0;
-const DB_RANGE: f32 = MAX_DB - MIN_DB;
+//棒の描き方と並べ方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarsConfig {
+    pub style: BarStyle,
+    //1本の棒の幅と棒の間隔。Brailleのときはドット単位、それ以外はセル単位
+    pub width: u16,
+    pub gap: u16,
+    //枠の内側に対して棒グラフを描く範囲の幅と高さの割合(0.0〜1.0)
+    pub area_width: f32,
+    pub area_height: f32,
+}
 
+impl Default for BarsConfig {
+    fn default() -> Self {
+        BarsConfig { style: BarStyle::default(), width: 1, gap: 0, area_width: 0.8, area_height: 0.5 }
+    }
+}
 
-impl SpectrumVisualizer {
+pub const DEFAULT_MIN_DB: f32 = -60.0;
+pub const DEFAULT_MAX_DB: f32 = 0.0;
 
-    pub fn new() -> Self {
-        SpectrumVisualizer {
-            prev_bar_heights: Vec::new(),
+//表示の高さ0.0〜1.0に対応させるレベルの範囲(dBFS)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelRange {
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+impl Default for LevelRange {
+    fn default() -> Self {
+        LevelRange { min_db: DEFAULT_MIN_DB, max_db: DEFAULT_MAX_DB }
+    }
+}
+
+impl LevelRange {
+
+    //振幅(1.0がフルスケール)をデシベルに変換し、min_db〜max_dbの範囲で0.0〜1.0に正規化する
+    pub fn level(&self, magnitude: f32) -> f32 {
+
+        if magnitude <= 0.0 {
+            return 0.0;
         }
+
+        let db_value = 20.0 * magnitude.log10();
+        ((db_value - self.min_db) / (self.max_db - self.min_db)).clamp(0.0, 1.0)
+
     }
 
-    pub fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+}
 
-        //ヴィジュアライザーのブロックを作成
-        let block = Block::default()
-        .borders(Borders::ALL)
-        .title("Audio Visualizer");
+//ピークの印
+const PEAK_CAP: &str = "▔";
 
-        frame.render_widget(&block, area);
 
-        let full_area = frame.area();
+impl Default for SpectrumVisualizer {
+    fn default() -> Self {
+        Self::new(BallisticsConfig::default(), BarsConfig::default(), LevelRange::default())
+    }
+}
+
+impl SpectrumVisualizer {
+
+    pub fn new(ballistics: BallisticsConfig, bars: BarsConfig, levels: LevelRange) -> Self {
+        SpectrumVisualizer {
+            ballistics: Ballistics::new(ballistics),
+            bars: BarsConfig { width: bars.width.max(1), ..bars },
+            levels,
+            theme: Theme::default(),
+            interpolator: FrameInterpolator::default(),
+            last_draw: None,
+        }
+    }
 
-        //棒グラフを描画する内部の描画エリアを計算
-        let visualizer_width_percentage = 0.80;
-        let visualizer_height_percentage = 0.50;
+    //棒グラフを描画する内部の描画エリア
+    fn bars_area(&self, full_area: Rect) -> Rect {
 
-        let visualizer_width = (full_area.width as f32 * visualizer_width_percentage) as u16;
-        let visualizer_height = (full_area.height as f32 * visualizer_height_percentage) as u16;
+        let visualizer_width = (full_area.width as f32 * self.bars.area_width) as u16;
+        let visualizer_height = (full_area.height as f32 * self.bars.area_height) as u16;
 
         let visualizer_x = full_area.left() + (full_area.width.saturating_sub(visualizer_width)) / 2;
         let visualizer_y = full_area.top() + (full_area.height.saturating_sub(visualizer_height)) / 2;
 
-        let visualizer_area = Rect::new(visualizer_x, visualizer_y, visualizer_width, visualizer_height);
+        Rect::new(visualizer_x, visualizer_y, visualizer_width, visualizer_height)
+
+    }
+
+    //描画エリアに並ぶ棒の本数
+    fn bar_count(&self, visualizer_area: Rect) -> usize {
+        let (dots_x, _) = self.bars.style.resolution();
+        let pitch = (self.bars.width + self.bars.gap) as usize;
+        (visualizer_area.width as usize * dots_x + self.bars.gap as usize) / pitch
+    }
+
+    fn draw_bars(&mut self, frame: &mut Frame, full_area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        let visualizer_area = self.bars_area(full_area);
 
-        let num_display_bars = visualizer_area.width as usize;
-        let mut current_bar_heights = vec![0u16; num_display_bars];
+        let (dots_x, dots_y) = self.bars.style.resolution();
+        let pitch = (self.bars.width + self.bars.gap) as usize;
+        let num_display_bars = self.bar_count(visualizer_area);
 
-        if let Some(data) = spectrum_data {
+        let Some(data) = spectrum_data else { return };
 
-            let raw_bins = &data.bins;
-            if raw_bins.is_empty() {
-                return;
+        let now = Instant::now();
+        let dt = self.last_draw.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_draw = Some(now);
+
+        let (raw_values, stretch) = if self.ballistics.config().interpolate && !self.interpolator.is_empty() {
+            (self.interpolator.current(now), !data.bands.is_empty())
+        } else {
+            Self::raw_values(data)
+        };
+
+        let targets: Vec<f32> = Self::fit_values(&raw_values, num_display_bars, stretch)
+        .into_iter()
+        .map(|magnitude| self.levels.level(magnitude))
+        .collect();
+
+        if targets.is_empty() {
+            return;
+        }
+
+        self.ballistics.step(&targets, dt);
+
+        //縦の分解能(ドットや1/8ブロック)単位の棒とピークの高さ
+        let max_height = visualizer_area.height as usize * dots_y;
+        let to_units = |level: f32| ((level * max_height as f32) as usize).min(max_height);
+        let show_peaks = self.ballistics.config().peaks;
+
+        //棒の左からの位置(0.0〜1.0)は周波数で色を付けるときに使う
+        let bars: Vec<(usize, Option<usize>, f32)> = self.ballistics.levels().iter()
+        .zip(self.ballistics.peaks())
+        .enumerate()
+        .map(|(i, (&level, peak))| {
+            let peak = Some(to_units(peak.level)).filter(|&peak| show_peaks && peak > to_units(level));
+            (to_units(level), peak, i as f32 / targets.len() as f32)
+        })
+        .collect();
+
+        match self.bars.style {
+            BarStyle::Blocks | BarStyle::Eighths => self.draw_cell_bars(frame, visualizer_area, &bars),
+            BarStyle::Braille => self.draw_braille_bars(frame, visualizer_area, &bars),
+        }
+
+        //棒グラフの下の行に帯域の中心周波数を表示(枠線に重なる場合は表示しない)
+        if visualizer_area.bottom() + 1 < full_area.bottom() {
+            let used_columns = (bars.len() * pitch - self.bars.gap as usize).div_ceil(dots_x);
+            for (offset, label) in Self::band_labels(&data.bands, used_columns) {
+                frame.buffer_mut().set_string(visualizer_area.left() + offset, visualizer_area.bottom(), label, Style::default().fg(self.label_color()));
             }
+        }
 
-            let bins_to_process = if raw_bins.len() <= num_display_bars {
-                raw_bins.clone()
-            } else {
+    }
 
-                let mut aggregated_bins = vec![0.0f32; num_display_bars];
-                let raw_bins_per_display_bin = raw_bins.len() as f32 / num_display_bars as f32;
+    //セル単位(Blocks)か1/8ブロック(Eighths)で描く。高さはresolutionの縦の単位
+    fn draw_cell_bars(&self, frame: &mut Frame, area: Rect, bars: &[(usize, Option<usize>, f32)]) {
 
-                for i in 0..num_display_bars {
+        let (_, dots_y) = self.bars.style.resolution();
+        let pitch = self.bars.width + self.bars.gap;
 
-                    let start_index = (i as f32 * raw_bins_per_display_bin) as usize;
-                    let end_index = (((i + 1) as f32 * raw_bins_per_display_bin) as usize).min(raw_bins.len());
+        for (i, &(height, peak, position)) in bars.iter().enumerate() {
 
-                    let mut sum_magnitude = 0.0;
-                    let mut count = 0;
+            let left = area.left() + i as u16 * pitch;
+            let full_rows = (height / dots_y) as u16;
+            let remainder = height % dots_y;
 
-                    for j in start_index..end_index {
-                        sum_magnitude += raw_bins[j];
-                        count += 1;
-                    }
+            for x in left..(left + self.bars.width).min(area.right()) {
 
-                    if count > 0 {
-                        aggregated_bins[i] = sum_magnitude / count as f32;
+                //角棒を描画
+                for row in 0..full_rows {
+                    let color = self.cell_color(position, row, area.height);
+                    let cell = Rect::new(x, area.bottom() - 1 - row, 1, 1);
+                    match self.bars.style {
+                        BarStyle::Blocks => frame.buffer_mut().set_style(cell, Style::default().bg(color)),
+                        _ => frame.buffer_mut().set_string(cell.x, cell.y, eighth_block(8).to_string(), Style::default().fg(color)),
                     }
                 }
-                aggregated_bins
-            };
 
-            let max_height = visualizer_area.height as f32;
+                //一番上の半端な高さは1/8ブロックで描く
+                if remainder > 0 && full_rows < area.height {
+                    let color = self.cell_color(position, full_rows, area.height);
+                    frame.buffer_mut().set_string(x, area.bottom() - 1 - full_rows, eighth_block(remainder).to_string(), Style::default().fg(color));
+                }
 
-            if self.prev_bar_heights.len() != num_display_bars {
-                self.prev_bar_heights.resize(num_display_bars, 0);
+                //ピークの印は棒の上端より上のセルにだけ描く
+                let bar_rows = full_rows + (remainder > 0) as u16;
+                if let Some(peak) = peak {
+                    let peak_row = (peak.div_ceil(dots_y) as u16).min(area.height);
+                    if peak_row > bar_rows {
+                        let color = self.theme.peak_color(self.cell_color(position, peak_row - 1, area.height));
+                        frame.buffer_mut().set_string(x, area.bottom() - peak_row, PEAK_CAP, Style::default().fg(color));
+                    }
+                }
             }
+        }
+
+    }
+
+    //点字のドットで描く。同じセルに複数の棒が入るときは右の棒の色になる
+    fn draw_braille_bars(&self, frame: &mut Frame, area: Rect, bars: &[(usize, Option<usize>, f32)]) {
+
+        let mut canvas = BrailleCanvas::new(area.width as usize, area.height as usize);
+        let mut positions = vec![0.0; area.width as usize];
+        let pitch = (self.bars.width + self.bars.gap) as usize;
 
-            for i in 0..num_display_bars {
-                let x = visualizer_area.left() + i as u16;
-                if x >= visualizer_area.right() { continue; }
+        for (i, &(height, peak, position)) in bars.iter().enumerate() {
 
-                let prev_height = self.prev_bar_heights[i];
-                let y_prev = visualizer_area.bottom().saturating_sub(prev_height);
-                let original_color = get_bar_color(i, num_display_bars);
-                let grayish_color = get_grayish_color(original_color);
+            let left = i * pitch;
 
-                for h in 0..prev_height {
-                    frame.buffer_mut().set_style(Rect::new(x, y_prev + h, 1, 1), Style::default().bg(grayish_color));
+            for x in left..left + self.bars.width as usize {
+                for y in 0..height {
+                    canvas.set(x, y);
+                }
+                if let Some(peak) = peak {
+                    canvas.set(x, peak - 1);
+                }
+                if let Some(slot) = positions.get_mut(x / BRAILLE_DOTS_X) {
+                    *slot = position;
                 }
             }
+        }
+
+        for row in 0..area.height {
+            for (column, &position) in positions.iter().enumerate() {
+                if let Some(glyph) = canvas.cell(column, row as usize) {
+                    let color = self.cell_color(position, area.height - 1 - row, area.height);
+                    frame.buffer_mut().set_string(area.left() + column as u16, area.top() + row, glyph.to_string(), Style::default().fg(color));
+                }
+            }
+        }
 
-            for (i, &magnitude) in bins_to_process.iter().enumerate() {
+    }
 
-                let x = visualizer_area.left() + i as u16; //各種1文字幅
-                if x >= visualizer_area.right() { continue; } //描画領域超えたらスキップ
+    //下からrow行目のセルの色。高さで色を付けるときはセルの上端の高さを使う
+    fn cell_color(&self, position: f32, row: u16, rows: u16) -> Color {
+        self.theme.bar_color(position, (row + 1) as f32 / rows.max(1) as f32)
+    }
 
-                let mut scaled_magnitude = 0.0;
+    fn label_color(&self) -> Color {
+        self.theme.border().unwrap_or(Color::Gray)
+    }
 
-                if magnitude > 0.0 {
-                    //デシベル値に変換
-                    let db_value = 20.0 * magnitude.log10();
-                    //デシベル値をminからmaxの範囲で正規化
-                    scaled_magnitude = ((db_value - MIN_DB) /DB_RANGE).max(0.0).min(1.0);
-                }
+    //表示する列数に合わせたスペクトルの値(振幅)
+    //周波数帯域にまとめられていればそちらを使い、無ければ線形のビンをそのまま使う
+    pub(crate) fn display_values(data: &SpectrumData, columns: usize) -> Vec<f32> {
 
-                //正規化された振幅を最大高さにマッピング
-                let bar_height_float = (scaled_magnitude * max_height).min(max_height);
+        let (raw_bins, stretch) = Self::raw_values(data);
 
-                let mut bar_height = bar_height_float as u16;
-                if bar_height == 0 && magnitude > 0.0 {
-                    bar_height = 1;
-                }
+        Self::fit_values(&raw_bins, columns, stretch)
 
-                current_bar_heights[i] = bar_height;
+    }
 
-                let y = visualizer_area.bottom().saturating_sub(bar_height);
+    //表示する列数に合わせる前の値(振幅)と、帯域にまとめられているか
+    pub(crate) fn raw_values(data: &SpectrumData) -> (Vec<f32>, bool) {
 
-                let color = get_bar_color(i, bins_to_process.len());
+        if data.bands.is_empty() {
+            (data.bins.iter().map(|&v| data.scale.to_magnitude(v)).collect(), false)
+        } else {
+            (data.bands.iter().map(|b| data.scale.to_magnitude(b.value)).collect(), true)
+        }
 
-                //角棒を描画
-                for h in 0..bar_height {
-                    frame.buffer_mut().set_style(Rect::new(x, y + h, 1, 1), Style::default().bg(color));
-                }
+    }
+
+    //値の列を表示する列数に合わせる
+    //帯域(stretch = true)が表示幅より少なければ1帯域を複数列に引き伸ばす
+    pub(crate) fn fit_values(raw_bins: &[f32], columns: usize, stretch: bool) -> Vec<f32> {
+
+        if stretch && raw_bins.len() < columns {
+            Self::stretched_bins(raw_bins, columns)
+        } else {
+            Self::aggregated_bins(raw_bins, columns)
+        }
+
+    }
+
+    //各列に対応する帯域の値を並べて、帯域数を表示列数まで引き伸ばす
+    pub(crate) fn stretched_bins(raw_bins: &[f32], target_count: usize) -> Vec<f32> {
+
+        if raw_bins.is_empty() {
+            return Vec::new();
+        }
+
+        (0..target_count)
+        .map(|i| raw_bins[i * raw_bins.len() / target_count])
+        .collect()
+
+    }
+
+    //i本目の棒にまとめられた値の範囲(raw_valuesのインデックス)。fit_valuesと同じ対応にする
+    pub(crate) fn value_range(count: usize, columns: usize, stretch: bool, i: usize) -> (usize, usize) {
+
+        if stretch && count < columns {
+            let index = i * count / columns;
+            (index, index + 1)
+        } else if count <= columns {
+            (i, i + 1)
+        } else {
+            let per_column = count as f32 / columns as f32;
+            let start = (i as f32 * per_column) as usize;
+            (start, (((i + 1) as f32 * per_column) as usize).min(count))
+        }
+
+    }
+
+    //帯域ラベルを表示する列とラベル文字列を返す
+    //帯域の始まりの列にだけ置き、前のラベルと重ならないように間引く
+    pub(crate) fn band_labels(bands: &[Band], columns: usize) -> Vec<(u16, String)> {
+
+        let mut labels = Vec::new();
+
+        if bands.is_empty() || columns == 0 {
+            return labels;
+        }
+
+        let mut next_free = 0;
+        let mut prev_band = None;
+
+        for column in 0..columns {
+
+            let band_index = column * bands.len() / columns;
+            if prev_band == Some(band_index) {
+                continue;
+            }
+            prev_band = Some(band_index);
+
+            let label = format_frequency(bands[band_index].center_hz);
+
+            if column >= next_free && column + label.len() <= columns {
+                next_free = column + label.len() + 1;
+                labels.push((column as u16, label));
             }
         }
 
-        self.prev_bar_heights = current_bar_heights;
+        labels
 
     }
 
-    #[cfg(test)]
     pub(crate) fn aggregated_bins(raw_bins: &[f32], target_count: usize) -> Vec<f32> {
 
         if raw_bins.is_empty() || target_count == 0 {
@@ -158,19 +400,13 @@ impl SpectrumVisualizer {
         let mut aggregated_bins = vec![0.0f32; target_count];
         let raw_bins_per_display_bin = raw_bins.len() as f32 / target_count as f32;
 
-        for i in 0..target_count {
+        for (i, aggregated) in aggregated_bins.iter_mut().enumerate() {
             let start_index = (i as f32 * raw_bins_per_display_bin) as usize;
             let end_index = (((i + 1) as f32 * raw_bins_per_display_bin) as usize).min(raw_bins.len());
 
-            let mut sum_magnitude = 0.0;
-            let mut count = 0;
-            for j in start_index..end_index {
-                sum_magnitude += raw_bins[j];
-                count += 1; 
-            }
-
-            if count > 0 {
-                aggregated_bins[i] = sum_magnitude / count as f32;
+            let range = &raw_bins[start_index..end_index];
+            if !range.is_empty() {
+                *aggregated = range.iter().sum::<f32>() / range.len() as f32;
             }
         }
         aggregated_bins
@@ -179,11 +415,99 @@ impl SpectrumVisualizer {
 
 }
 
-fn get_bar_color(index: usize, total_bars: usize) -> Color {
-    let ratio = index as f32 / total_bars as f32;
-    let rgb = visualize_color::float_to_rgb_palette(ratio);
-    
-    Color::Rgb(rgb.0, rgb.1, rgb.2)
+impl Visualizer for SpectrumVisualizer {
+
+    fn name(&self) -> &'static str {
+        "Bars"
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect, spectrum_data: Option<&SpectrumData>) {
+
+        //ヴィジュアライザーのブロックを作成
+        let block = visualizer_block(self.name());
+
+        frame.render_widget(&block, area);
+
+        self.draw_bars(frame, area, spectrum_data);
+
+    }
+
+    fn update(&mut self, spectrum_data: &SpectrumData) {
+        if self.ballistics.config().interpolate {
+            self.interpolator.push(Self::raw_values(spectrum_data).0, Instant::now());
+        }
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+    }
+
+    //"1.6k Hz  -23.5 dBFS"
+    fn inspect(&self, area: Rect, column: u16, row: u16, spectrum_data: &SpectrumData) -> Option<String> {
+
+        let visualizer_area = self.bars_area(area);
+        if !visualizer_area.contains(Position::new(column, row)) {
+            return None;
+        }
+
+        //棒の間のすきまはどの棒にも含めない
+        let (dots_x, _) = self.bars.style.resolution();
+        let pitch = (self.bars.width + self.bars.gap) as usize;
+        let dot = (column - visualizer_area.left()) as usize * dots_x;
+        if dot % pitch >= self.bars.width as usize {
+            return None;
+        }
+
+        let num_display_bars = self.bar_count(visualizer_area);
+        let (raw_values, stretch) = Self::raw_values(spectrum_data);
+        let index = dot / pitch;
+        if index >= num_display_bars || index >= Self::fit_values(&raw_values, num_display_bars, stretch).len() {
+            return None;
+        }
+
+        let (start, end) = Self::value_range(raw_values.len(), num_display_bars, stretch, index);
+        //AGCの倍率は割り戻して、フルスケール基準の値で表す
+        let magnitude = raw_values[start..end].iter().sum::<f32>() / (end - start) as f32 / spectrum_data.agc_gain.unwrap_or(1.0);
+
+        //帯域なら中心周波数(複数の帯域をまとめた棒は両端の幾何平均)、線形のビンなら両端のビンの中央
+        let hz = if stretch {
+            let bands = &spectrum_data.bands;
+            if end - start == 1 { bands[start].center_hz } else { (bands[start].low_hz * bands[end - 1].high_hz).sqrt() }
+        } else {
+            (spectrum_data.bin_frequency(start) + spectrum_data.bin_frequency(end - 1)) / 2.0
+        };
+
+        let level = if magnitude > 0.0 { format!("{:.1} dBFS", 20.0 * magnitude.log10()) } else { "silent".to_string() };
+
+        Some(format!("{} Hz  {}", format_frequency(hz), level))
+
+    }
+
+}
+
+//各ヴィジュアライザーで共通の枠
+pub(crate) fn visualizer_block(name: &str) -> Block<'static> {
+    Block::default()
+    .borders(Borders::ALL)
+    .title(format!("Audio Visualizer [{}]", name))
+}
+
+//周波数を短いラベルにする(例: 63, 500, 1k, 1.6k, 16k)
+pub(crate) fn format_frequency(hz: f32) -> String {
+
+    if hz < 1000.0 {
+        return format!("{}", hz.round() as u32);
+    }
+
+    let khz = hz / 1000.0;
+
+    if khz < 10.0 && (khz * 10.0).round() % 10.0 != 0.0 {
+        format!("{:.1}k", khz)
+    }
+    else {
+        format!("{}k", khz.round() as u32)
+    }
+
 }
 
 
@@ -191,7 +515,13 @@ fn get_bar_color(index: usize, total_bars: usize) -> Color {
 mod test_visualiezr {
     use std::vec;
 
-    use crate::visualizer::visualizer::SpectrumVisualizer;
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use crate::audio::analyzer::AudioAnalyzer;
+    use crate::audio::bands::Band;
+    use crate::visualizer::ballistics::BallisticsConfig;
+    use crate::visualizer::glyphs::BarStyle;
+    use crate::visualizer::visualizer::{format_frequency, BarsConfig, LevelRange, SpectrumVisualizer, Visualizer};
 
 
     #[test]
@@ -248,4 +578,68 @@ mod test_visualiezr {
         assert!(aggregated.is_empty());
     }
 
+    #[test]
+    fn test_stretched_bins() {
+        let stretched = SpectrumVisualizer::stretched_bins(&[1.0, 2.0], 5);
+        assert_eq!(stretched, vec![1.0, 1.0, 1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_format_frequency() {
+        assert_eq!(format_frequency(63.0), "63");
+        assert_eq!(format_frequency(999.4), "999");
+        assert_eq!(format_frequency(1000.0), "1k");
+        assert_eq!(format_frequency(1600.0), "1.6k");
+        assert_eq!(format_frequency(16000.0), "16k");
+    }
+
+    #[test]
+    fn test_band_labels_do_not_overlap() {
+        let bands: Vec<Band> = [100.0, 1000.0, 10000.0].iter()
+        .map(|&center_hz| Band { low_hz: center_hz / 2.0, center_hz, high_hz: center_hz * 2.0, value: 0.0 })
+        .collect();
+
+        let labels = SpectrumVisualizer::band_labels(&bands, 12);
+        assert_eq!(labels, vec![(0, "100".to_string()), (4, "1k".to_string()), (8, "10k".to_string())]);
+
+        //表示幅が狭いと間引かれる
+        let labels = SpectrumVisualizer::band_labels(&bands, 3);
+        assert_eq!(labels.len(), 1);
+    }
+
+    #[test]
+    fn test_magnitude_to_level() {
+        let levels = LevelRange::default();
+        assert_eq!(levels.level(0.0), 0.0);
+        assert_eq!(levels.level(1.0), 1.0);
+        assert!((levels.level(0.001) - 0.0).abs() < 0.001);//-60dB
+        assert!((levels.level(0.1) - 2.0 / 3.0).abs() < 0.001);//-20dB
+
+        //範囲を変えると-20dBが下端になる
+        let narrow = LevelRange { min_db: -20.0, max_db: 0.0 };
+        assert_eq!(narrow.level(0.1), 0.0);
+        assert!((narrow.level(0.5) - 0.699).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_bar_styles_draw_sub_cell_glyphs() {
+        let samples: Vec<f32> = (0..1024).map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 44100.0).sin()).collect();
+        let data = AudioAnalyzer::new(1024, 44100).unwrap().analyze(&samples).unwrap();
+
+        let drawn = |style: BarStyle, width: u16, gap: u16| -> String {
+            let mut visualizer = SpectrumVisualizer::new(BallisticsConfig::default(), BarsConfig { style, width, gap, ..Default::default() }, LevelRange::default());
+            let mut terminal = Terminal::new(TestBackend::new(60, 40)).unwrap();
+            terminal.draw(|frame| visualizer.draw(frame, frame.area(), Some(&data))).unwrap();
+            terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect()
+        };
+
+        let eighths = drawn(BarStyle::Eighths, 2, 1);
+        assert!(eighths.contains('█'));
+        assert!(!eighths.contains('⣿'));
+
+        let braille = drawn(BarStyle::Braille, 1, 1);
+        assert!(braille.contains('⣿') || braille.contains('⡇'));
+        assert!(!braille.contains('█'));
+    }
+
 }